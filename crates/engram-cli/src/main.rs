@@ -0,0 +1,797 @@
+//! `engram` command-line tool.
+//!
+//! Inspects, verifies and builds `.eng` archives through the same archive
+//! logic as the language bindings. Every command accepts `--json` for output
+//! meant for scripts rather than people.
+
+mod serve;
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use engram_common::codec::method_name;
+use engram_common::diff::{self, ArchiveDiff, DiffOptions};
+use engram_common::patch::{self, PatchOptions};
+use engram_common::{
+    database, signature, sql, Archive, CompressionPolicy, EncryptionKey, EntryInfo, OpenOptions,
+    Writer, WriterOptions,
+};
+use engram_core::CompressionMethod;
+use serde_json::{json, Value};
+
+type CliResult<T = ()> = Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(name = "engram", version, about = "Inspect and build .eng archives")]
+struct Cli {
+    /// Print JSON instead of human-readable output
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(flatten)]
+    open: OpenArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Options used whenever an archive is opened for reading.
+#[derive(Args)]
+struct OpenArgs {
+    /// Accept only archives signed by this hex-encoded Ed25519 public key
+    #[arg(long = "trusted-key", value_name = "HEX", global = true)]
+    trusted_keys: Vec<String>,
+
+    /// Reject archives without a signature from a --trusted-key
+    #[arg(long, global = true, requires = "trusted_keys")]
+    require_signature: bool,
+
+    /// File holding a 32-byte key for encrypted entries, raw or hex-encoded
+    #[arg(long = "key-file", value_name = "PATH", global = true)]
+    key_files: Vec<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pack a directory into a new archive
+    Pack {
+        source: PathBuf,
+        archive: PathBuf,
+        /// Archive path prefix for the packed files
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Compression for files the default policy does not decide
+        #[arg(long, value_enum)]
+        compression: Option<Compression>,
+        /// Compression level for zstd and deflate
+        #[arg(long)]
+        level: Option<i32>,
+        /// Store identical files once
+        #[arg(long)]
+        deduplicate: bool,
+        /// Sign with the Ed25519 secret key in this file, raw or hex-encoded
+        #[arg(long, value_name = "PATH")]
+        sign_key: Option<PathBuf>,
+    },
+    /// Extract every entry into a directory
+    Unpack {
+        archive: PathBuf,
+        destination: PathBuf,
+    },
+    /// List entries with their sizes and compression
+    Ls {
+        archive: PathBuf,
+        /// Only list entries starting with this prefix
+        prefix: Option<String>,
+    },
+    /// Write an entry to standard output
+    Cat { archive: PathBuf, path: String },
+    /// Show a summary of the archive, or the metadata of one entry
+    Stat {
+        archive: PathBuf,
+        path: Option<String>,
+    },
+    /// Read every entry, checking CRCs, hashes and the signature
+    Verify { archive: PathBuf },
+    /// Read or replace manifest.json
+    Manifest {
+        #[command(subcommand)]
+        action: ManifestAction,
+    },
+    /// Show what changed between two archives
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Also compare the rows of databases that changed
+        #[arg(long)]
+        databases: bool,
+    },
+    /// Create or apply binary delta patches between archive versions
+    Patch {
+        #[command(subcommand)]
+        action: PatchAction,
+    },
+    /// Run a SQL query against a database in the archive
+    Sql {
+        archive: PathBuf,
+        db: String,
+        query: String,
+    },
+    /// Serve the entries of an archive over HTTP
+    Serve {
+        archive: PathBuf,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Serve this archive directory as the site root
+        #[arg(long, default_value = "")]
+        root: String,
+        /// Entry served for extensionless paths that match nothing, such as
+        /// index.html for single-page apps
+        #[arg(long, value_name = "PATH")]
+        fallback: Option<String>,
+        /// Number of worker threads, each reading through its own handle on
+        /// the archive
+        #[arg(long, default_value_t = 4)]
+        threads: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum ManifestAction {
+    /// Print manifest.json
+    Get { archive: PathBuf },
+    /// Replace manifest.json, keeping every other entry
+    ///
+    /// The archive is rewritten. Entries compressed by the core writer are
+    /// decompressed and recompressed with their original method on the way.
+    /// A signed archive must be signed again with --sign-key, or its
+    /// signature dropped explicitly with --unsigned.
+    Set {
+        archive: PathBuf,
+        /// Manifest JSON, or @PATH to read it from a file
+        manifest: String,
+        /// Sign with the Ed25519 secret key in this file, raw or hex-encoded
+        #[arg(long, value_name = "PATH")]
+        sign_key: Option<PathBuf>,
+        /// Drop the signature of a signed archive instead of signing again
+        #[arg(long, conflicts_with = "sign_key")]
+        unsigned: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PatchAction {
+    /// Write a patch that rebuilds NEW from OLD
+    Create {
+        old: PathBuf,
+        new: PathBuf,
+        patch: PathBuf,
+        /// zstd level for deltas and full contents
+        #[arg(long)]
+        level: Option<i32>,
+    },
+    /// Rebuild the new archive from BASE and PATCH, verifying every entry
+    Apply {
+        base: PathBuf,
+        patch: PathBuf,
+        output: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Compression {
+    None,
+    Lz4,
+    Zstd,
+    Deflate,
+}
+
+impl From<Compression> for CompressionMethod {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => CompressionMethod::None,
+            Compression::Lz4 => CompressionMethod::Lz4,
+            Compression::Zstd => CompressionMethod::Zstd,
+            Compression::Deflate => CompressionMethod::Deflate,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("engram: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> CliResult<ExitCode> {
+    let json = cli.json;
+    match cli.command {
+        Command::Pack {
+            source,
+            archive,
+            prefix,
+            compression,
+            level,
+            deduplicate,
+            sign_key,
+        } => {
+            let policy = CompressionPolicy {
+                default_compression: compression.map(Into::into),
+                ..Default::default()
+            };
+            let options = WriterOptions {
+                policy: Some(policy),
+                level,
+                deduplicate,
+                ..Default::default()
+            };
+            pack(
+                &source,
+                &archive,
+                &prefix,
+                options,
+                sign_key.as_deref(),
+                json,
+            )?;
+        }
+        Command::Unpack {
+            archive,
+            destination,
+        } => unpack(&mut open(&archive, &cli.open)?, &destination, json)?,
+        Command::Ls { archive, prefix } => {
+            ls(&open(&archive, &cli.open)?, prefix.as_deref(), json)?
+        }
+        Command::Cat { archive, path } => {
+            let data = open(&archive, &cli.open)?.read_file(&path)?;
+            io::stdout().write_all(&data)?;
+        }
+        Command::Stat { archive, path } => {
+            stat(&mut open(&archive, &cli.open)?, path.as_deref(), json)?
+        }
+        Command::Verify { archive } => return verify(&archive, &cli.open, json),
+        Command::Manifest {
+            action: ManifestAction::Get { archive },
+        } => match open(&archive, &cli.open)?.read_manifest()? {
+            Some(manifest) => println!("{}", serde_json::to_string_pretty(&manifest)?),
+            None => return Err("archive has no manifest.json".into()),
+        },
+        Command::Manifest {
+            action:
+                ManifestAction::Set {
+                    archive,
+                    manifest,
+                    sign_key,
+                    unsigned,
+                },
+        } => set_manifest(&archive, &manifest, sign_key.as_deref(), unsigned)?,
+        Command::Diff {
+            old,
+            new,
+            databases,
+        } => {
+            let changes = diff::diff(
+                &mut open(&old, &cli.open)?,
+                &mut open(&new, &cli.open)?,
+                DiffOptions { databases },
+            )?;
+            print_diff(&changes, json)?
+        }
+        Command::Patch {
+            action:
+                PatchAction::Create {
+                    old,
+                    new,
+                    patch,
+                    level,
+                },
+        } => {
+            let stats = patch::create_patch(
+                &mut open(&old, &cli.open)?,
+                &mut open(&new, &cli.open)?,
+                &patch,
+                &PatchOptions { level },
+            )?;
+            if json {
+                print_json(&serde_json::to_value(stats)?)?;
+            } else {
+                println!(
+                    "{} copied, {} deltas, {} full; {} bytes for {} bytes of entries",
+                    stats.copied, stats.deltas, stats.literals, stats.patch_bytes, stats.new_bytes
+                );
+            }
+        }
+        Command::Patch {
+            action:
+                PatchAction::Apply {
+                    base,
+                    patch,
+                    output,
+                },
+        } => {
+            let stats = patch::apply_patch(
+                &mut open(&base, &cli.open)?,
+                &mut open(&patch, &cli.open)?,
+                &output,
+            )?;
+            if json {
+                print_json(&json!({ "entries": stats.entries }))?;
+            } else {
+                println!("{}: {} entries", output.display(), stats.entries);
+            }
+        }
+        Command::Sql { archive, db, query } => sql(open(&archive, &cli.open)?, &db, &query, json)?,
+        Command::Serve {
+            archive,
+            listen,
+            root,
+            fallback,
+            threads,
+        } => {
+            let root = root.trim_matches('/');
+            let options = serve::ServeOptions {
+                root: if root.is_empty() {
+                    String::new()
+                } else {
+                    format!("{root}/")
+                },
+                fallback: fallback.map(|path| path.trim_start_matches('/').to_string()),
+            };
+            // One handle per worker, so requests don't queue on a shared one.
+            let archives = (0..threads.max(1))
+                .map(|_| open(&archive, &cli.open))
+                .collect::<CliResult<Vec<_>>>()?;
+            serve::serve(archives, &listen, options)?
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn open(path: &Path, args: &OpenArgs) -> CliResult<Archive> {
+    let options = OpenOptions {
+        trusted_keys: args
+            .trusted_keys
+            .iter()
+            .map(|key| Ok(signature::public_key(&hex::decode(key)?)?))
+            .collect::<CliResult<_>>()?,
+        require_signature: args.require_signature,
+        keys: args
+            .key_files
+            .iter()
+            .map(|path| Ok(EncryptionKey::new(&read_key(path)?, None)?))
+            .collect::<CliResult<_>>()?,
+        ..Default::default()
+    };
+    Ok(Archive::open_with(path_str(path)?, &options)?)
+}
+
+fn path_str(path: &Path) -> CliResult<&str> {
+    path.to_str()
+        .ok_or_else(|| format!("path is not valid UTF-8: {}", path.display()).into())
+}
+
+/// Read a 32-byte key stored raw or as hex text.
+fn read_key(path: &Path) -> CliResult<Vec<u8>> {
+    let data = fs::read(path)?;
+    if data.len() == 32 {
+        return Ok(data);
+    }
+    Ok(hex::decode(String::from_utf8(data)?.trim())?)
+}
+
+/// Stored size as a percentage of the uncompressed size.
+fn ratio(entry: &EntryInfo) -> f64 {
+    if entry.uncompressed_size == 0 {
+        100.0
+    } else {
+        entry.compressed_size as f64 * 100.0 / entry.uncompressed_size as f64
+    }
+}
+
+fn entry_json(entry: &EntryInfo) -> Value {
+    json!({
+        "path": entry.path,
+        "uncompressedSize": entry.uncompressed_size,
+        "compressedSize": entry.compressed_size,
+        "compression": method_name(&entry.compression),
+        "compressionLevel": entry.compression_level,
+        "dictionary": entry.dictionary,
+        "aliasOf": entry.alias_of,
+        "encrypted": entry.encrypted,
+        "modifiedTime": entry.modified_time,
+        "crc32": entry.crc32,
+        "hash": entry.sha256,
+    })
+}
+
+fn print_json(value: &Value) -> CliResult {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn pack(
+    source: &Path,
+    archive: &Path,
+    prefix: &str,
+    options: WriterOptions,
+    sign_key: Option<&Path>,
+    json: bool,
+) -> CliResult {
+    let mut writer = Writer::create(archive, options)?;
+    let files = writer.add_directory(source, prefix)?;
+    if let Some(path) = sign_key {
+        writer.sign_with(&read_key(path)?)?;
+    }
+    let stats = writer.finalize()?;
+
+    if json {
+        print_json(&json!({
+            "archive": archive,
+            "files": files,
+            "entries": stats.entries,
+            "deduplicatedEntries": stats.deduplicated,
+            "bytesSaved": stats.bytes_saved,
+        }))
+    } else {
+        println!("packed {files} files into {}", archive.display());
+        if stats.deduplicated > 0 {
+            println!(
+                "{} duplicates, {} bytes saved",
+                stats.deduplicated, stats.bytes_saved
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Join an archive path onto `destination`, refusing paths that would escape
+/// it.
+fn extraction_path(destination: &Path, entry: &str) -> CliResult<PathBuf> {
+    let relative = Path::new(entry);
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(format!("refusing to extract unsafe path: {entry}").into());
+    }
+    Ok(destination.join(relative))
+}
+
+fn unpack(archive: &mut Archive, destination: &Path, json: bool) -> CliResult {
+    let files = archive.list_files().to_vec();
+    for file in &files {
+        let target = extraction_path(destination, file)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, archive.read_file(file)?)?;
+    }
+
+    if json {
+        print_json(&json!({ "destination": destination, "files": files }))
+    } else {
+        println!(
+            "extracted {} files to {}",
+            files.len(),
+            destination.display()
+        );
+        Ok(())
+    }
+}
+
+fn ls(archive: &Archive, prefix: Option<&str>, json: bool) -> CliResult {
+    let mut files = match prefix {
+        Some(prefix) => archive.list_prefix(prefix),
+        None => archive.list_files().to_vec(),
+    };
+    files.sort();
+    let entries: Vec<EntryInfo> = files
+        .iter()
+        .filter_map(|file| archive.get_entry(file))
+        .collect();
+
+    if json {
+        return print_json(&Value::Array(entries.iter().map(entry_json).collect()));
+    }
+
+    println!(
+        "{:>12} {:>12} {:>7}  {:<8} PATH",
+        "SIZE", "STORED", "RATIO", "METHOD"
+    );
+    for entry in &entries {
+        println!(
+            "{:>12} {:>12} {:>6.1}%  {:<8} {}",
+            entry.uncompressed_size,
+            entry.compressed_size,
+            ratio(entry),
+            method_name(&entry.compression),
+            entry.path
+        );
+    }
+    Ok(())
+}
+
+fn stat(archive: &mut Archive, path: Option<&str>, json: bool) -> CliResult {
+    if let Some(path) = path {
+        let entry = archive
+            .get_entry(path)
+            .ok_or_else(|| format!("entry not found: {path}"))?;
+        if json {
+            return print_json(&entry_json(&entry));
+        }
+
+        println!("path:         {}", entry.path);
+        println!("size:         {}", entry.uncompressed_size);
+        println!(
+            "stored:       {} ({:.1}%)",
+            entry.compressed_size,
+            ratio(&entry)
+        );
+        match entry.compression_level {
+            Some(level) => println!(
+                "compression:  {} level {level}",
+                method_name(&entry.compression)
+            ),
+            None => println!("compression:  {}", method_name(&entry.compression)),
+        }
+        println!("dictionary:   {}", entry.dictionary);
+        println!("encrypted:    {}", entry.encrypted);
+        if let Some(target) = &entry.alias_of {
+            println!("alias of:     {target}");
+        }
+        println!("modified:     {}", entry.modified_time);
+        println!("crc32:        {:08x}", entry.crc32);
+        if let Some(hash) = &entry.sha256 {
+            println!("sha256:       {hash}");
+        }
+        return Ok(());
+    }
+
+    let entries: Vec<EntryInfo> = archive
+        .list_files()
+        .iter()
+        .filter_map(|file| archive.get_entry(file))
+        .collect();
+    let size: u64 = entries.iter().map(|entry| entry.uncompressed_size).sum();
+    let stored: u64 = entries
+        .iter()
+        .filter(|entry| entry.alias_of.is_none())
+        .map(|entry| entry.compressed_size)
+        .sum();
+    let encrypted = entries.iter().filter(|entry| entry.encrypted).count();
+    let signer = archive.signer().map(hex::encode);
+    let manifest = archive.read_manifest()?;
+
+    if json {
+        return print_json(&json!({
+            "path": archive.path(),
+            "entries": entries.len(),
+            "uncompressedSize": size,
+            "compressedSize": stored,
+            "encryptedEntries": encrypted,
+            "signer": signer,
+            "manifest": manifest,
+        }));
+    }
+
+    println!("archive:      {}", archive.path());
+    println!("entries:      {}", entries.len());
+    println!("size:         {size}");
+    println!("stored:       {stored}");
+    println!("encrypted:    {encrypted} entries");
+    println!("signer:       {}", signer.as_deref().unwrap_or("unsigned"));
+    if let Some(manifest) = manifest {
+        let field = |name: &str| manifest.get(name).and_then(Value::as_str).unwrap_or("?");
+        println!("manifest:     {} {}", field("name"), field("version"));
+    }
+    Ok(())
+}
+
+fn verify(path: &Path, args: &OpenArgs, json: bool) -> CliResult<ExitCode> {
+    // A signature present in the archive is verified on open.
+    let mut archive = open(path, args)?;
+    let files = archive.list_files().to_vec();
+    let mut failures = Vec::new();
+    for file in &files {
+        if let Err(err) = archive.read_file(file) {
+            failures.push((file.clone(), err.to_string()));
+        }
+    }
+    let failed_entries = failures.len();
+    if let Err(err) = archive.read_manifest() {
+        failures.push(("manifest.json".to_string(), err.to_string()));
+    }
+    let signer = archive.signer().map(hex::encode);
+
+    if json {
+        print_json(&json!({
+            "valid": failures.is_empty(),
+            "entries": files.len(),
+            "signer": signer,
+            "failures": failures
+                .iter()
+                .map(|(path, error)| json!({ "path": path, "error": error }))
+                .collect::<Vec<_>>(),
+        }))?;
+    } else {
+        for (path, error) in &failures {
+            println!("FAILED {path}: {error}");
+        }
+        match &signer {
+            Some(signer) => println!("signature valid, signed by {signer}"),
+            None => println!("archive is not signed"),
+        }
+        println!(
+            "{} of {} entries verified",
+            files.len() - failed_entries,
+            files.len()
+        );
+    }
+
+    Ok(if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn set_manifest(
+    archive: &Path,
+    manifest: &str,
+    sign_key: Option<&Path>,
+    unsigned: bool,
+) -> CliResult {
+    let manifest = match manifest.strip_prefix('@') {
+        Some(path) => fs::read_to_string(path)?,
+        None => manifest.to_string(),
+    };
+    let manifest: Value = serde_json::from_str(&manifest)?;
+
+    let mut writer = Writer::open_for_update(archive, Default::default())?;
+    if writer.base_signed() && sign_key.is_none() && !unsigned {
+        return Err(format!(
+            "{} is signed; pass --sign-key to sign it again or --unsigned to drop the signature",
+            archive.display()
+        )
+        .into());
+    }
+    writer.add_manifest(&manifest)?;
+    if let Some(path) = sign_key {
+        writer.sign_with(&read_key(path)?)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn optional_json(value: &Option<Value>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "(none)".to_string(), Value::to_string)
+}
+
+fn print_diff(changes: &ArchiveDiff, json: bool) -> CliResult {
+    if json {
+        return print_json(&serde_json::to_value(changes)?);
+    }
+
+    for entry in &changes.added {
+        println!("+ {} ({} bytes)", entry.path, entry.size);
+    }
+    for entry in &changes.removed {
+        println!("- {} ({} bytes)", entry.path, entry.size);
+    }
+    for entry in &changes.modified {
+        let mut details = Vec::new();
+        if entry.content_changed {
+            details.push(format!("{:+} bytes", entry.size_delta));
+        }
+        if entry.compression_changed {
+            details.push(format!(
+                "{} -> {}",
+                entry.old_compression, entry.new_compression
+            ));
+        }
+        println!("M {} ({})", entry.path, details.join(", "));
+    }
+    for change in &changes.manifest {
+        println!(
+            "manifest {}: {} -> {}",
+            change.pointer,
+            optional_json(&change.old),
+            optional_json(&change.new)
+        );
+    }
+    for database in &changes.databases {
+        for table in &database.tables_added {
+            println!("{}: + table {table}", database.path);
+        }
+        for table in &database.tables_removed {
+            println!("{}: - table {table}", database.path);
+        }
+        for table in &database.tables {
+            if table.schema_changed {
+                println!("{}: table {} schema changed", database.path, table.name);
+            } else {
+                println!(
+                    "{}: table {} +{} -{} ~{} rows",
+                    database.path,
+                    table.name,
+                    table.added.len(),
+                    table.removed.len(),
+                    table.modified.len()
+                );
+            }
+        }
+    }
+    println!(
+        "{} added, {} removed, {} modified, {} unchanged; size {:+} bytes, stored {:+} bytes",
+        changes.added.len(),
+        changes.removed.len(),
+        changes.modified.len(),
+        changes.unchanged,
+        changes.size_delta,
+        changes.compressed_size_delta
+    );
+    Ok(())
+}
+
+fn sql_value(value: rusqlite::types::ValueRef) -> Value {
+    use rusqlite::types::ValueRef;
+
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => json!(i),
+        ValueRef::Real(f) => json!(f),
+        ValueRef::Text(s) => json!(String::from_utf8_lossy(s)),
+        ValueRef::Blob(b) => json!(b),
+    }
+}
+
+fn sql(mut archive: Archive, db: &str, query: &str, json: bool) -> CliResult {
+    let conn = database::open_database(&mut archive, db)?;
+    sql::register(&conn, Arc::new(Mutex::new(archive)))?;
+    let mut stmt = conn.prepare(query)?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+    let mut rows = Vec::new();
+    let mut result = stmt.query([])?;
+    while let Some(row) = result.next()? {
+        let values = (0..columns.len())
+            .map(|index| Ok(sql_value(row.get_ref(index)?)))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.push(values);
+    }
+
+    if json {
+        let objects = rows
+            .into_iter()
+            .map(|values| Value::Object(columns.iter().cloned().zip(values).collect()))
+            .collect();
+        return print_json(&Value::Array(objects));
+    }
+
+    println!("{}", columns.join("\t"));
+    for values in rows {
+        let cells: Vec<String> = values
+            .into_iter()
+            .map(|value| match value {
+                Value::Null => "NULL".to_string(),
+                Value::String(text) => text,
+                Value::Array(bytes) => format!("<blob {} bytes>", bytes.len()),
+                other => other.to_string(),
+            })
+            .collect();
+        println!("{}", cells.join("\t"));
+    }
+    Ok(())
+}
@@ -0,0 +1,460 @@
+//! `engram serve`: a local HTTP server for the entries of an archive.
+//!
+//! Entries are served with a Content-Type guessed from their extension, an
+//! ETag from their SHA-256 (or CRC-32 and size for older archives) and a
+//! Last-Modified date from their modification time. Single byte ranges are
+//! honored, but each one reads and decompresses the whole entry and sends
+//! the requested slice: archives can only be read an entry at a time, so
+//! seeking through a large video or download costs a full read per request.
+//! Entries the bindings stored with deflate, or with zstd and no dictionary,
+//! are sent still compressed to clients that accept gzip or zstd.
+//!
+//! Every worker thread reads through its own handle on the archive, so
+//! requests are served concurrently.
+
+use std::io::Cursor;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use engram_common::codec::Codec;
+use engram_common::{Archive, EntryInfo, Error};
+use engram_core::CompressionMethod;
+use percent_encoding::percent_decode_str;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::CliResult;
+
+/// Largest zstd entry passed through. Browsers only decode frames whose
+/// window fits in 8 MiB, and a single-shot frame's window is its size.
+const MAX_ZSTD_PASSTHROUGH: u64 = 8 * 1024 * 1024;
+
+/// Page served with status 404 when present in the served root.
+const NOT_FOUND_PAGE: &str = "404.html";
+
+pub struct ServeOptions {
+    /// Archive directory served as the site root, empty or ending with `/`.
+    pub root: String,
+    /// Entry below the root served for extensionless paths that match
+    /// nothing, for single-page apps.
+    pub fallback: Option<String>,
+}
+
+/// Serve on `listen` until the process is stopped, with one worker thread for
+/// each of `archives`, which are separate handles on the same archive.
+pub fn serve(archives: Vec<Archive>, listen: &str, options: ServeOptions) -> CliResult {
+    let server =
+        Arc::new(Server::http(listen).map_err(|e| format!("cannot listen on {listen}: {e}"))?);
+    let site = Arc::new(Site { options });
+    eprintln!("Serving on http://{}", server.server_addr());
+
+    let workers: Vec<_> = archives
+        .into_iter()
+        .map(|mut archive| {
+            let server = Arc::clone(&server);
+            let site = Arc::clone(&site);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    site.respond(&mut archive, request);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+struct Site {
+    options: ServeOptions,
+}
+
+/// Response built before it is handed to tiny_http.
+struct Reply {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn text(status: u16, message: &str) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(format!("{message}\n").into_bytes())
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let mut response = Response::from_data(self.body).with_status_code(self.status);
+        for (name, value) in self.headers {
+            if let Ok(header) = Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+                response.add_header(header);
+            }
+        }
+        response
+    }
+}
+
+/// What a request path resolved to.
+enum Target {
+    Entry {
+        path: String,
+        status: u16,
+    },
+    /// Directory requested without its trailing slash.
+    Redirect,
+    NotFound,
+}
+
+/// Representation of an entry sent to the client.
+enum Encoding {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Zstd => Some("zstd"),
+        }
+    }
+}
+
+fn request_header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Whether an Accept-Encoding header allows `coding`.
+fn accepts_encoding(header: Option<&str>, coding: &str) -> bool {
+    let Some(header) = header else {
+        return false;
+    };
+    let mut wildcard = false;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|part| part.trim().strip_prefix("q="))
+            .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0));
+        if name.eq_ignore_ascii_case(coding) {
+            return quality > 0.0;
+        }
+        if name == "*" {
+            wildcard = quality > 0.0;
+        }
+    }
+    wildcard
+}
+
+enum ByteRange {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a Range header against a body of `len` bytes. Multiple ranges and
+/// malformed headers are answered with the full body.
+fn parse_range(header: &str, len: u64) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    if start.trim().is_empty() {
+        return match end.trim().parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = start.trim().parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    let end = if end.trim().is_empty() {
+        len - 1
+    } else {
+        match end.trim().parse::<u64>() {
+            Ok(end) if end >= start => end.min(len - 1),
+            _ => return ByteRange::Full,
+        }
+    };
+    ByteRange::Partial(start, end)
+}
+
+/// Whether an If-None-Match header lists `etag`, compared weakly.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+    })
+}
+
+/// Wrap a raw deflate stream in a gzip member, which needs only the CRC-32
+/// and size of the payload.
+fn gzip(deflate: &[u8], crc32: u32, size: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(deflate.len() + 18);
+    out.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+    out.extend_from_slice(deflate);
+    out.extend_from_slice(&crc32.to_le_bytes());
+    out.extend_from_slice(&(size as u32).to_le_bytes());
+    out
+}
+
+fn content_type(path: &str) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let textual = mime.type_() == mime_guess::mime::TEXT
+        || matches!(mime.subtype().as_str(), "javascript" | "json" | "xml");
+    if textual {
+        format!("{mime}; charset=utf-8")
+    } else {
+        mime.to_string()
+    }
+}
+
+fn etag(entry: &EntryInfo) -> String {
+    match &entry.sha256 {
+        Some(sha256) => format!("\"{sha256}\""),
+        None => format!("\"{:08x}-{:x}\"", entry.crc32, entry.uncompressed_size),
+    }
+}
+
+/// Whether the bindings stored the entry with a codec that can be passed
+/// through: an explicit level marks a binding codec.
+fn passthrough_codec(entry: &EntryInfo) -> bool {
+    entry.compression_level.is_some() && !entry.dictionary && !entry.encrypted
+}
+
+impl Site {
+    fn respond(&self, archive: &mut Archive, request: Request) {
+        let reply = match request.method() {
+            Method::Get | Method::Head => self.handle(archive, &request),
+            _ => Reply::text(405, "Method Not Allowed").header("Allow", "GET, HEAD"),
+        };
+        eprintln!("{} {} {}", request.method(), request.url(), reply.status);
+        let _ = request.respond(reply.into_response());
+    }
+
+    fn handle(&self, archive: &mut Archive, request: &Request) -> Reply {
+        let (raw_path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (request.url(), None),
+        };
+        let Ok(path) = percent_decode_str(raw_path).decode_utf8() else {
+            return Reply::text(400, "Bad Request");
+        };
+        if path
+            .split('/')
+            .any(|segment| segment == ".." || segment == ".")
+        {
+            return Reply::text(400, "Bad Request");
+        }
+
+        match self.resolve(archive, path.trim_start_matches('/')) {
+            Target::Entry { path, status } => match self.entry(archive, request, &path, status) {
+                Ok(reply) => reply,
+                Err(Error::NotFound(_)) => Reply::text(404, "Not Found"),
+                Err(err) => Reply::text(500, &err.to_string()),
+            },
+            Target::Redirect => {
+                let location = match query {
+                    Some(query) => format!("{raw_path}/?{query}"),
+                    None => format!("{raw_path}/"),
+                };
+                Reply::text(301, "Moved Permanently").header("Location", location)
+            }
+            Target::NotFound => Reply::text(404, "Not Found"),
+        }
+    }
+
+    fn resolve(&self, archive: &Archive, relative: &str) -> Target {
+        let root = &self.options.root;
+        let full = format!("{root}{relative}");
+        let is_directory = relative.is_empty() || relative.ends_with('/');
+
+        if !is_directory && archive.contains(&full) {
+            return Target::Entry {
+                path: full,
+                status: 200,
+            };
+        }
+
+        if is_directory {
+            let index = format!("{full}index.html");
+            if archive.contains(&index) {
+                return Target::Entry {
+                    path: index,
+                    status: 200,
+                };
+            }
+        } else if archive.contains(&format!("{full}/index.html")) {
+            return Target::Redirect;
+        }
+
+        let last_segment = relative.rsplit('/').next().unwrap_or_default();
+        if let Some(fallback) = &self.options.fallback {
+            let fallback = format!("{root}{fallback}");
+            if !last_segment.contains('.') && archive.contains(&fallback) {
+                return Target::Entry {
+                    path: fallback,
+                    status: 200,
+                };
+            }
+        }
+
+        let not_found = format!("{root}{NOT_FOUND_PAGE}");
+        if archive.contains(&not_found) {
+            return Target::Entry {
+                path: not_found,
+                status: 404,
+            };
+        }
+        Target::NotFound
+    }
+
+    fn entry(
+        &self,
+        archive: &mut Archive,
+        request: &Request,
+        path: &str,
+        status: u16,
+    ) -> engram_common::Result<Reply> {
+        let entry = archive
+            .get_entry(path)
+            .ok_or_else(|| Error::NotFound(path.to_string()))?;
+        let range = request_header(request, "Range").filter(|_| status == 200);
+        let accept = request_header(request, "Accept-Encoding");
+
+        let encoding = if range.is_some() || !passthrough_codec(&entry) {
+            Encoding::Identity
+        } else {
+            match entry.compression {
+                CompressionMethod::Deflate if accepts_encoding(accept, "gzip") => Encoding::Gzip,
+                CompressionMethod::Zstd
+                    if entry.uncompressed_size <= MAX_ZSTD_PASSTHROUGH
+                        && accepts_encoding(accept, "zstd") =>
+                {
+                    Encoding::Zstd
+                }
+                _ => Encoding::Identity,
+            }
+        };
+
+        let etag = match encoding.name() {
+            Some(name) => format!("{}-{name}\"", etag(&entry).trim_end_matches('"')),
+            None => etag(&entry),
+        };
+        let modified = (entry.modified_time > 0)
+            .then(|| UNIX_EPOCH + Duration::from_secs(entry.modified_time));
+
+        let mut reply = Reply::new(status)
+            .header("Content-Type", content_type(path))
+            .header("ETag", etag.clone());
+        if let Some(modified) = modified {
+            reply = reply.header("Last-Modified", httpdate::fmt_http_date(modified));
+        }
+        if passthrough_codec(&entry) {
+            reply = reply.header("Vary", "Accept-Encoding");
+        }
+
+        if status == 200 && not_modified(request, &etag, modified) {
+            reply.status = 304;
+            return Ok(reply);
+        }
+
+        let body = match encoding {
+            Encoding::Identity => archive.read_file(path)?,
+            Encoding::Gzip | Encoding::Zstd => {
+                let Some(stored) = archive.read_stored(path)? else {
+                    return Ok(reply.body(archive.read_file(path)?));
+                };
+                reply = reply.header("Content-Encoding", encoding.name().unwrap_or_default());
+                match stored.codec {
+                    Codec::Deflate { .. } => gzip(&stored.data, stored.crc32, stored.size),
+                    Codec::Zstd { .. } => stored.data,
+                }
+            }
+        };
+
+        if matches!(encoding, Encoding::Identity) && status == 200 {
+            reply = reply.header("Accept-Ranges", "bytes");
+        }
+        let Some(range) = range.filter(|_| if_range_matches(request, &etag, modified)) else {
+            return Ok(reply.body(body));
+        };
+
+        let len = body.len() as u64;
+        Ok(match parse_range(range, len) {
+            ByteRange::Full => reply.body(body),
+            ByteRange::Partial(start, end) => {
+                reply.status = 206;
+                reply
+                    .header("Content-Range", format!("bytes {start}-{end}/{len}"))
+                    .body(body[start as usize..=end as usize].to_vec())
+            }
+            ByteRange::Unsatisfiable => Reply::text(416, "Range Not Satisfiable")
+                .header("Content-Range", format!("bytes */{len}")),
+        })
+    }
+}
+
+/// Whether the client's cached copy is current. If-None-Match takes
+/// precedence over If-Modified-Since.
+fn not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(header) = request_header(request, "If-None-Match") {
+        return etag_matches(header, etag);
+    }
+    match (request_header(request, "If-Modified-Since"), modified) {
+        (Some(since), Some(modified)) => {
+            httpdate::parse_http_date(since).is_ok_and(|since| modified <= since)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a Range request still applies under its If-Range condition.
+fn if_range_matches(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(condition) = request_header(request, "If-Range") else {
+        return true;
+    };
+    if condition.starts_with('"') {
+        return condition == etag;
+    }
+    match (httpdate::parse_http_date(condition), modified) {
+        (Ok(date), Some(modified)) => modified == date,
+        _ => false,
+    }
+}
@@ -0,0 +1,185 @@
+//! End-to-end tests running the `engram` binary.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use common::{engram, path, run, run_json, TempDir};
+
+/// A source directory with a nested file and two identical files.
+fn source_dir(temp: &TempDir) -> PathBuf {
+    let source = temp.join("site");
+    fs::create_dir_all(source.join("assets")).unwrap();
+    fs::write(source.join("index.html"), "<h1>hello</h1>").unwrap();
+    fs::write(source.join("assets/app.js"), "console.log(1);\n".repeat(64)).unwrap();
+    fs::write(
+        source.join("assets/copy.js"),
+        "console.log(1);\n".repeat(64),
+    )
+    .unwrap();
+    source
+}
+
+fn pack(temp: &TempDir, extra: &[&str]) -> PathBuf {
+    let source = source_dir(temp);
+    let archive = temp.join("site.eng");
+    let mut args = vec!["pack", path(&source), path(&archive)];
+    args.extend_from_slice(extra);
+    run(&args);
+    archive
+}
+
+fn write_signing_key(temp: &TempDir) -> PathBuf {
+    let key = temp.join("signing.key");
+    fs::write(&key, hex::encode([0x42; 32])).unwrap();
+    key
+}
+
+fn signer(archive: &Path) -> Value {
+    run_json(&["stat", path(archive)])["signer"].clone()
+}
+
+#[test]
+fn pack_and_unpack_round_trip() {
+    let temp = TempDir::new();
+    let source = source_dir(&temp);
+    let archive = temp.join("site.eng");
+
+    let packed = run_json(&["pack", path(&source), path(&archive), "--deduplicate"]);
+    assert_eq!(packed["files"], 3);
+    assert_eq!(packed["deduplicatedEntries"], 1);
+
+    let out = temp.join("out");
+    let unpacked = run_json(&["unpack", path(&archive), path(&out)]);
+    assert_eq!(unpacked["files"].as_array().unwrap().len(), 3);
+    for file in ["index.html", "assets/app.js", "assets/copy.js"] {
+        assert_eq!(
+            fs::read(out.join(file)).unwrap(),
+            fs::read(source.join(file)).unwrap(),
+            "{file}"
+        );
+    }
+}
+
+#[test]
+fn ls_json_lists_entries_in_path_order() {
+    let temp = TempDir::new();
+    let archive = pack(&temp, &[]);
+
+    let entries = run_json(&["ls", path(&archive)]);
+    let paths: Vec<&str> = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["assets/app.js", "assets/copy.js", "index.html"]);
+
+    let app = &entries[0];
+    assert_eq!(app["uncompressedSize"], 16 * 64);
+    assert!(app["compressedSize"].is_u64());
+    assert!(app["compression"].is_string());
+    assert_eq!(app["encrypted"], false);
+    assert!(app["hash"].is_string());
+
+    let assets = run_json(&["ls", path(&archive), "assets/"]);
+    assert_eq!(assets.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn verify_checks_entries_and_signature() {
+    let temp = TempDir::new();
+    let key = write_signing_key(&temp);
+    let archive = pack(&temp, &["--sign-key", path(&key)]);
+
+    let report = run_json(&["verify", path(&archive)]);
+    assert_eq!(report["valid"], true);
+    assert_eq!(report["entries"], 3);
+    let signer = report["signer"].as_str().unwrap().to_string();
+
+    run(&[
+        "verify",
+        path(&archive),
+        "--trusted-key",
+        &signer,
+        "--require-signature",
+    ]);
+
+    let untrusted = engram(&[
+        "verify",
+        path(&archive),
+        "--trusted-key",
+        &hex::encode([0x01; 32]),
+    ]);
+    assert!(!untrusted.status.success());
+
+    let unsigned = temp.join("unsigned.eng");
+    run(&["pack", path(&temp.join("site")), path(&unsigned)]);
+    let required = engram(&[
+        "verify",
+        path(&unsigned),
+        "--trusted-key",
+        &signer,
+        "--require-signature",
+    ]);
+    assert!(!required.status.success());
+}
+
+#[test]
+fn manifest_set_replaces_manifest_and_keeps_entries() {
+    let temp = TempDir::new();
+    let archive = pack(&temp, &[]);
+
+    run(&[
+        "manifest",
+        "set",
+        path(&archive),
+        r#"{"name":"site","version":"1.0.0"}"#,
+    ]);
+    let manifest_file = temp.join("manifest.json");
+    fs::write(&manifest_file, r#"{"name":"site","version":"1.1.0"}"#).unwrap();
+    run(&[
+        "manifest",
+        "set",
+        path(&archive),
+        &format!("@{}", path(&manifest_file)),
+    ]);
+
+    let manifest: Value = serde_json::from_str(&run(&["manifest", "get", path(&archive)])).unwrap();
+    assert_eq!(manifest["version"], "1.1.0");
+    assert_eq!(
+        run(&["cat", path(&archive), "index.html"]),
+        "<h1>hello</h1>"
+    );
+    assert_eq!(run_json(&["verify", path(&archive)])["valid"], true);
+}
+
+#[test]
+fn manifest_set_does_not_silently_drop_a_signature() {
+    let temp = TempDir::new();
+    let key = write_signing_key(&temp);
+    let archive = pack(&temp, &["--sign-key", path(&key)]);
+    let original = fs::read(&archive).unwrap();
+    let manifest = r#"{"name":"site","version":"2.0.0"}"#;
+
+    let refused = engram(&["manifest", "set", path(&archive), manifest]);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("--unsigned"));
+    assert_eq!(fs::read(&archive).unwrap(), original);
+
+    run(&[
+        "manifest",
+        "set",
+        path(&archive),
+        manifest,
+        "--sign-key",
+        path(&key),
+    ]);
+    assert!(signer(&archive).is_string());
+
+    run(&["manifest", "set", path(&archive), manifest, "--unsigned"]);
+    assert!(signer(&archive).is_null());
+}
@@ -0,0 +1,337 @@
+//! End-to-end tests of `engram serve` over real HTTP connections.
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use common::{path, run, TempDir};
+
+const APP_JS: &str = "export function main() { console.log('hello'); }\n";
+
+/// Running `engram serve`, killed on drop.
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    fn start(archive: &Path) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_engram"))
+            .args(["serve", path(archive), "--listen", "127.0.0.1:0"])
+            .args(["--threads", "4", "--fallback", "index.html"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("start engram serve");
+
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("Serving on http://")
+            .unwrap_or_else(|| panic!("unexpected server output: {line}"))
+            .to_string();
+        // Keep draining the request log so the server never blocks on it.
+        thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+
+        Self { child, addr }
+    }
+
+    /// Send a request and read the whole response. HTTP/1.0 keeps the body
+    /// unchunked and closes the connection after it.
+    fn request(&self, method: &str, target: &str, headers: &[(&str, &str)]) -> HttpResponse {
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        let mut request = format!("{method} {target} HTTP/1.0\r\nHost: {}\r\n", self.addr);
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).unwrap();
+        HttpResponse::parse(&raw)
+    }
+
+    fn get(&self, target: &str, headers: &[(&str, &str)]) -> HttpResponse {
+        self.request("GET", target, headers)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn parse(raw: &[u8]) -> Self {
+        let split = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("response head");
+        let head = String::from_utf8(raw[..split].to_vec()).unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .expect("status line");
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        Self {
+            status,
+            headers,
+            body: raw[split + 4..].to_vec(),
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8(self.body.clone()).unwrap()
+    }
+}
+
+/// A small site packed with explicit levels, so text entries are stored with
+/// zstd and other entries with deflate, both eligible for passthrough.
+fn site(temp: &TempDir) -> PathBuf {
+    let source = temp.join("site");
+    fs::create_dir_all(source.join("assets")).unwrap();
+    fs::create_dir_all(source.join("docs")).unwrap();
+    fs::write(source.join("index.html"), "<h1>home</h1>").unwrap();
+    fs::write(source.join("404.html"), "<h1>missing</h1>").unwrap();
+    fs::write(source.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+    fs::write(source.join("assets/app.js"), APP_JS.repeat(64)).unwrap();
+    fs::write(source.join("data.bin"), data_bin()).unwrap();
+
+    let archive = temp.join("site.eng");
+    run(&[
+        "pack",
+        path(&source),
+        path(&archive),
+        "--compression",
+        "deflate",
+        "--level",
+        "6",
+    ]);
+    archive
+}
+
+fn data_bin() -> Vec<u8> {
+    (0..4096u32).map(|i| (i % 7) as u8).collect()
+}
+
+#[test]
+fn serves_entries_with_content_types() {
+    let temp = TempDir::new();
+    let server = Server::start(&site(&temp));
+
+    let home = server.get("/", &[]);
+    assert_eq!(home.status, 200);
+    assert_eq!(
+        home.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(home.text(), "<h1>home</h1>");
+
+    let script = server.get("/assets/app.js", &[]);
+    assert_eq!(script.status, 200);
+    let content_type = script.header("Content-Type").unwrap();
+    assert!(content_type.contains("javascript"), "{content_type}");
+    assert!(content_type.ends_with("; charset=utf-8"), "{content_type}");
+    assert_eq!(script.text(), APP_JS.repeat(64));
+
+    let data = server.get("/data.bin", &[]);
+    assert_eq!(
+        data.header("Content-Type"),
+        Some("application/octet-stream")
+    );
+    assert_eq!(data.body, data_bin());
+
+    let head = server.request("HEAD", "/index.html", &[]);
+    assert_eq!(head.status, 200);
+    assert!(head.body.is_empty());
+
+    let post = server.request("POST", "/index.html", &[]);
+    assert_eq!(post.status, 405);
+    assert_eq!(post.header("Allow"), Some("GET, HEAD"));
+}
+
+#[test]
+fn revalidates_with_etag_and_last_modified() {
+    let temp = TempDir::new();
+    let server = Server::start(&site(&temp));
+
+    let first = server.get("/index.html", &[]);
+    let etag = first.header("ETag").expect("ETag").to_string();
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+    let cached = server.get("/index.html", &[("If-None-Match", &etag)]);
+    assert_eq!(cached.status, 304);
+    assert!(cached.body.is_empty());
+    assert_eq!(cached.header("ETag"), Some(etag.as_str()));
+
+    let weak = format!("W/{etag}");
+    assert_eq!(
+        server
+            .get("/index.html", &[("If-None-Match", &weak)])
+            .status,
+        304
+    );
+    let stale = server.get("/index.html", &[("If-None-Match", "\"other\"")]);
+    assert_eq!(stale.status, 200);
+
+    if let Some(modified) = first.header("Last-Modified") {
+        let since = server.get("/index.html", &[("If-Modified-Since", modified)]);
+        assert_eq!(since.status, 304);
+    }
+}
+
+#[test]
+fn answers_byte_ranges() {
+    let temp = TempDir::new();
+    let server = Server::start(&site(&temp));
+    let data = data_bin();
+    let len = data.len();
+
+    let full = server.get("/data.bin", &[]);
+    assert_eq!(full.header("Accept-Ranges"), Some("bytes"));
+
+    let partial = server.get("/data.bin", &[("Range", "bytes=10-19")]);
+    assert_eq!(partial.status, 206);
+    assert_eq!(
+        partial.header("Content-Range"),
+        Some(format!("bytes 10-19/{len}").as_str())
+    );
+    assert_eq!(partial.body, &data[10..20]);
+
+    let suffix = server.get("/data.bin", &[("Range", "bytes=-5")]);
+    assert_eq!(suffix.status, 206);
+    assert_eq!(suffix.body, &data[len - 5..]);
+
+    let open_ended = server.get("/data.bin", &[("Range", "bytes=4090-")]);
+    assert_eq!(open_ended.body, &data[4090..]);
+
+    let beyond = format!("bytes={len}-");
+    let unsatisfiable = server.get("/data.bin", &[("Range", &beyond)]);
+    assert_eq!(unsatisfiable.status, 416);
+    assert_eq!(
+        unsatisfiable.header("Content-Range"),
+        Some(format!("bytes */{len}").as_str())
+    );
+
+    let multiple = server.get("/data.bin", &[("Range", "bytes=0-1,5-6")]);
+    assert_eq!(multiple.status, 200);
+    assert_eq!(multiple.body, data);
+
+    let etag = full.header("ETag").unwrap().to_string();
+    let current = server.get("/data.bin", &[("Range", "bytes=0-3"), ("If-Range", &etag)]);
+    assert_eq!(current.status, 206);
+    let changed = server.get(
+        "/data.bin",
+        &[("Range", "bytes=0-3"), ("If-Range", "\"other\"")],
+    );
+    assert_eq!(changed.status, 200);
+    assert_eq!(changed.body, data);
+}
+
+#[test]
+fn passes_compressed_entries_through() {
+    let temp = TempDir::new();
+    let server = Server::start(&site(&temp));
+
+    let gzip = server.get("/data.bin", &[("Accept-Encoding", "gzip, deflate")]);
+    assert_eq!(gzip.status, 200);
+    assert_eq!(gzip.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(gzip.header("Vary"), Some("Accept-Encoding"));
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(&gzip.body[..])
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, data_bin());
+
+    let zstd = server.get("/assets/app.js", &[("Accept-Encoding", "zstd")]);
+    assert_eq!(zstd.header("Content-Encoding"), Some("zstd"));
+    assert_eq!(
+        zstd::decode_all(&zstd.body[..]).unwrap(),
+        APP_JS.repeat(64).as_bytes()
+    );
+
+    // Each representation has its own ETag.
+    let identity = server.get("/data.bin", &[]);
+    assert_eq!(identity.header("Content-Encoding"), None);
+    assert_ne!(identity.header("ETag"), gzip.header("ETag"));
+
+    let refused = server.get("/data.bin", &[("Accept-Encoding", "gzip;q=0")]);
+    assert_eq!(refused.header("Content-Encoding"), None);
+    assert_eq!(refused.body, data_bin());
+
+    // Ranges always apply to the decoded bytes.
+    let ranged = server.get(
+        "/data.bin",
+        &[("Accept-Encoding", "gzip"), ("Range", "bytes=0-3")],
+    );
+    assert_eq!(ranged.status, 206);
+    assert_eq!(ranged.header("Content-Encoding"), None);
+    assert_eq!(ranged.body, &data_bin()[..4]);
+}
+
+#[test]
+fn resolves_directories_and_fallbacks() {
+    let temp = TempDir::new();
+    let server = Server::start(&site(&temp));
+
+    let redirect = server.get("/docs?page=2", &[]);
+    assert_eq!(redirect.status, 301);
+    assert_eq!(redirect.header("Location"), Some("/docs/?page=2"));
+    assert_eq!(server.get("/docs/", &[]).text(), "<h1>docs</h1>");
+
+    // Extensionless paths are routes of the single-page app.
+    let route = server.get("/dashboard/settings", &[]);
+    assert_eq!(route.status, 200);
+    assert_eq!(route.text(), "<h1>home</h1>");
+
+    // Missing files fall through to 404.html.
+    let missing = server.get("/missing.png", &[]);
+    assert_eq!(missing.status, 404);
+    assert_eq!(missing.text(), "<h1>missing</h1>");
+
+    assert_eq!(server.get("/../secret", &[]).status, 400);
+}
+
+#[test]
+fn serves_requests_concurrently() {
+    let temp = TempDir::new();
+    let server = Server::start(&site(&temp));
+
+    thread::scope(|scope| {
+        let requests: Vec<_> = (0..16)
+            .map(|_| scope.spawn(|| server.get("/data.bin", &[])))
+            .collect();
+        for request in requests {
+            let response = request.join().unwrap();
+            assert_eq!(response.status, 200);
+            assert_eq!(response.body, data_bin());
+        }
+    });
+}
@@ -0,0 +1,86 @@
+//! Temporary files that atomically replace their target once complete.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary sibling of `target` that is renamed over it on [`commit`] and
+/// removed when dropped uncommitted.
+///
+/// Keeping the temporary file in the target's directory guarantees the rename
+/// stays on one filesystem, so readers only ever see a missing or complete
+/// archive.
+///
+/// [`commit`]: PendingFile::commit
+#[derive(Debug)]
+pub struct PendingFile {
+    temp: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl PendingFile {
+    pub fn new(target: impl AsRef<Path>) -> Self {
+        let target = target.as_ref().to_path_buf();
+        let mut name = OsString::from(".");
+        name.push(target.file_name().unwrap_or_else(|| "archive".as_ref()));
+        name.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        Self {
+            temp: target.with_file_name(name),
+            target,
+            committed: false,
+        }
+    }
+
+    /// Path the content is written to until it is committed.
+    pub fn path(&self) -> &Path {
+        &self.temp
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Move the temporary file into place. Its content is flushed to disk
+    /// before the rename and the rename itself after, so a crash leaves
+    /// either the old target or the complete new one.
+    pub fn commit(mut self) -> io::Result<()> {
+        fs::File::open(&self.temp)?.sync_all()?;
+        fs::rename(&self.temp, &self.target)?;
+        self.committed = true;
+        sync_parent(&self.target)
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Flush the directory entry of `path`. Windows cannot open directories as
+/// files and makes renames durable itself.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
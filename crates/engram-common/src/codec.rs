@@ -0,0 +1,256 @@
+//! Codecs applied by the bindings before entries reach engram-core.
+//!
+//! engram-core compresses with fixed settings, so entries that need an explicit
+//! level or a shared zstd dictionary are encoded here and handed to the core
+//! writer stored (`CompressionMethod::None`). The archive index records the
+//! codec so readers can reverse it.
+
+use std::io::{Read, Write};
+
+use engram_core::CompressionMethod;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// zstd level used when the bindings compress without an explicit level.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// deflate level used when the bindings compress without an explicit level.
+pub const DEFAULT_DEFLATE_LEVEL: u32 = 6;
+
+/// Upper bound of the deflate expansion ratio, used to cap how much is
+/// allocated up front for an untrusted size.
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+/// Binding-side encoding of a stored entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "lowercase")]
+pub enum Codec {
+    Zstd {
+        level: i32,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        dictionary: bool,
+    },
+    Deflate {
+        level: u32,
+    },
+}
+
+impl Codec {
+    /// Build the codec for `method` at `level`, or `None` when the method has
+    /// no tunable level.
+    pub fn for_level(method: CompressionMethod, level: i32) -> Result<Option<Self>> {
+        match method {
+            CompressionMethod::Zstd => {
+                if !zstd::compression_level_range().contains(&level) {
+                    return Err(Error::InvalidInput(format!(
+                        "zstd compression level must be between {} and {}, got {level}",
+                        zstd::compression_level_range().start(),
+                        zstd::compression_level_range().end(),
+                    )));
+                }
+                Ok(Some(Codec::Zstd {
+                    level,
+                    dictionary: false,
+                }))
+            }
+            CompressionMethod::Deflate => {
+                if !(0..=9).contains(&level) {
+                    return Err(Error::InvalidInput(format!(
+                        "deflate compression level must be between 0 and 9, got {level}"
+                    )));
+                }
+                Ok(Some(Codec::Deflate {
+                    level: level as u32,
+                }))
+            }
+            CompressionMethod::None | CompressionMethod::Lz4 => Ok(None),
+        }
+    }
+
+    /// Compression method the codec corresponds to.
+    pub fn compression(&self) -> CompressionMethod {
+        match self {
+            Codec::Zstd { .. } => CompressionMethod::Zstd,
+            Codec::Deflate { .. } => CompressionMethod::Deflate,
+        }
+    }
+
+    pub fn level(&self) -> i32 {
+        match self {
+            Codec::Zstd { level, .. } => *level,
+            Codec::Deflate { level } => *level as i32,
+        }
+    }
+
+    pub fn uses_dictionary(&self) -> bool {
+        matches!(
+            self,
+            Codec::Zstd {
+                dictionary: true,
+                ..
+            }
+        )
+    }
+
+    pub fn encode(&self, data: &[u8], dictionary: Option<&[u8]>) -> Result<Vec<u8>> {
+        match self {
+            Codec::Zstd {
+                level,
+                dictionary: uses_dictionary,
+            } => {
+                let dictionary = if *uses_dictionary {
+                    dictionary.ok_or_else(|| Error::Codec("zstd dictionary missing".into()))?
+                } else {
+                    &[]
+                };
+                zstd::bulk::Compressor::with_dictionary(*level, dictionary)
+                    .and_then(|mut compressor| compressor.compress(data))
+                    .map_err(|e| Error::Codec(format!("zstd compression failed: {e}")))
+            }
+            Codec::Deflate { level } => {
+                let mut encoder = flate2::write::DeflateEncoder::new(
+                    Vec::new(),
+                    flate2::Compression::new(*level),
+                );
+                encoder
+                    .write_all(data)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|e| Error::Codec(format!("deflate compression failed: {e}")))
+            }
+        }
+    }
+
+    /// Decode `data` into exactly `size` bytes.
+    pub fn decode(&self, data: &[u8], size: usize, dictionary: Option<&[u8]>) -> Result<Vec<u8>> {
+        let decoded = match self {
+            Codec::Zstd {
+                dictionary: uses_dictionary,
+                ..
+            } => {
+                let dictionary = if *uses_dictionary {
+                    dictionary.ok_or_else(|| Error::Codec("zstd dictionary missing".into()))?
+                } else {
+                    &[]
+                };
+                // The size comes from the index, which is as untrusted as the
+                // data, so the frame has to agree with it before the output
+                // buffer is allocated.
+                match zstd::zstd_safe::get_frame_content_size(data) {
+                    Ok(Some(content_size)) if content_size == size as u64 => {}
+                    _ => {
+                        return Err(Error::Codec(format!(
+                            "zstd frame does not declare the expected size of {size} bytes"
+                        )))
+                    }
+                }
+                zstd::bulk::Decompressor::with_dictionary(dictionary)
+                    .and_then(|mut decompressor| decompressor.decompress(data, size))
+                    .map_err(|e| Error::Codec(format!("zstd decompression failed: {e}")))?
+            }
+            Codec::Deflate { .. } => {
+                let mut decoded =
+                    Vec::with_capacity(size.min(data.len().saturating_mul(MAX_DEFLATE_RATIO)));
+                flate2::read::DeflateDecoder::new(data)
+                    .take(size as u64 + 1)
+                    .read_to_end(&mut decoded)
+                    .map_err(|e| Error::Codec(format!("deflate decompression failed: {e}")))?;
+                decoded
+            }
+        };
+
+        if decoded.len() != size {
+            return Err(Error::Codec(format!(
+                "decoded {} bytes, expected {size}",
+                decoded.len()
+            )));
+        }
+
+        Ok(decoded)
+    }
+}
+
+/// Train a zstd dictionary of at most `max_size` bytes from sample payloads.
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+        .map_err(|e| Error::Codec(format!("zstd dictionary training failed: {e}")))
+}
+
+/// Lowercase name of a compression method, as shown by the bindings.
+pub fn method_name(method: &CompressionMethod) -> &'static str {
+    match method {
+        CompressionMethod::None => "none",
+        CompressionMethod::Lz4 => "lz4",
+        CompressionMethod::Zstd => "zstd",
+        CompressionMethod::Deflate => "deflate",
+    }
+}
+
+/// Compression method named by [`method_name`].
+pub fn method_from_name(name: &str) -> Result<CompressionMethod> {
+    match name {
+        "none" => Ok(CompressionMethod::None),
+        "lz4" => Ok(CompressionMethod::Lz4),
+        "zstd" => Ok(CompressionMethod::Zstd),
+        "deflate" => Ok(CompressionMethod::Deflate),
+        other => Err(Error::InvalidInput(format!(
+            "unknown compression method: {other}"
+        ))),
+    }
+}
+
+/// Copy a core compression method, which is not `Copy` itself.
+pub(crate) fn copy_method(method: &CompressionMethod) -> CompressionMethod {
+    match method {
+        CompressionMethod::None => CompressionMethod::None,
+        CompressionMethod::Lz4 => CompressionMethod::Lz4,
+        CompressionMethod::Zstd => CompressionMethod::Zstd,
+        CompressionMethod::Deflate => CompressionMethod::Deflate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"Regression payload for untrusted sizes. ";
+
+    #[test]
+    fn zstd_rejects_a_size_the_frame_does_not_declare() {
+        let codec = Codec::Zstd {
+            level: 3,
+            dictionary: false,
+        };
+        let data = codec.encode(PAYLOAD, None).unwrap();
+
+        assert_eq!(codec.decode(&data, PAYLOAD.len(), None).unwrap(), PAYLOAD);
+        assert!(codec.decode(&data, PAYLOAD.len() + 1, None).is_err());
+        // Would abort on allocation if the size were trusted.
+        assert!(codec.decode(&data, usize::MAX / 2, None).is_err());
+    }
+
+    #[test]
+    fn zstd_rejects_frames_without_a_content_size() {
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).unwrap();
+        encoder.include_contentsize(false).unwrap();
+        encoder.write_all(PAYLOAD).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let codec = Codec::Zstd {
+            level: 3,
+            dictionary: false,
+        };
+        assert!(codec.decode(&data, PAYLOAD.len(), None).is_err());
+    }
+
+    #[test]
+    fn deflate_caps_allocation_for_untrusted_sizes() {
+        let codec = Codec::Deflate { level: 6 };
+        let data = codec.encode(PAYLOAD, None).unwrap();
+
+        assert_eq!(codec.decode(&data, PAYLOAD.len(), None).unwrap(), PAYLOAD);
+        assert!(codec.decode(&data, PAYLOAD.len() - 1, None).is_err());
+        // Would abort on allocation if the size were trusted.
+        assert!(codec.decode(&data, usize::MAX / 2, None).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Opening SQLite databases stored in archives.
+//!
+//! Plain databases are opened through `EngramVfs`, which pages them straight
+//! out of the archive. Databases the VFS cannot read as-is, such as encrypted
+//! ones, are read through [`Archive`] and loaded into an in-memory,
+//! read-only connection. `EngramVfs` reads pages from engram-core with no
+//! hook to decrypt them, and engram-core cannot read part of an entry, so an
+//! encrypted database is decrypted in full when it is opened.
+//!
+//! Databases of signed archives are loaded the same way. `EngramVfs` opens
+//! the archive again by path and has no hook to check pages either, so the
+//! bytes it served could differ from the ones checked against the signature
+//! if the file were replaced in between. SQLite only ever sees the verified
+//! copy, at the cost of holding the whole database in memory.
+//!
+//! So are databases of archives opened with a total read limit. Pages read
+//! through `EngramVfs` bypass [`Archive`] and could not be counted against
+//! it, so the database is read once, and counted, when it is opened.
+//!
+//! Further databases can be attached to a connection for cross-database
+//! queries. A database from an archive is opened through `EngramVfs` and
+//! attached as a read-only snapshot serialized into memory; see
+//! [`attach_snapshot`] for why it cannot be paged from the archive like the
+//! main database. Plain SQLite files are attached by path.
+
+use std::path::Path;
+use std::ptr::NonNull;
+
+use engram_vfs::EngramVfs;
+use rusqlite::serialize::OwnedData;
+use rusqlite::{Connection, DatabaseName};
+
+use crate::error::{Error, Result};
+use crate::reader::Archive;
+
+/// Open the database stored at `db_path` in `archive`.
+pub fn open_database(archive: &mut Archive, db_path: &str) -> Result<Connection> {
+    // The VFS reads pages on demand, so only the claimed size is checked.
+    archive.check_limits(db_path)?;
+
+    if archive.is_encrypted(db_path)
+        || archive.signer().is_some()
+        || archive.limits().max_total_bytes.is_some()
+    {
+        let data = archive.read_file(db_path)?;
+        return load_in_memory(&data);
+    }
+
+    EngramVfs::new(archive.path())
+        .open_database(db_path)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+/// Attach a read-only snapshot of the main database of `source` to `conn`
+/// as `alias`. The whole database is serialized into memory when attached,
+/// and later changes to `source` are not seen.
+///
+/// Attaching by URI, as `file:<db_path>?vfs=engram`, would page the database
+/// from its archive instead, but `EngramVfs` is not registered with SQLite
+/// under a name. It only hands out the connections it opens itself through
+/// `EngramVfs::open_database`, and an attached URI has no way to say which
+/// archive it belongs to. A snapshot is the only way to attach one until
+/// engram-vfs can be registered for URIs.
+pub fn attach_snapshot(conn: &mut Connection, source: &Connection, alias: &str) -> Result<()> {
+    let data = source
+        .serialize(DatabaseName::Main)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let owned = sqlite_copy(&data)?;
+
+    conn.execute(
+        &format!("ATTACH DATABASE ':memory:' AS {}", quote_identifier(alias)),
+        [],
+    )
+    .map_err(|e| Error::Database(e.to_string()))?;
+    if let Err(err) = conn.deserialize(DatabaseName::Attached(alias), owned, true) {
+        let _ = conn.execute(&format!("DETACH DATABASE {}", quote_identifier(alias)), []);
+        return Err(Error::Database(err.to_string()));
+    }
+    Ok(())
+}
+
+/// Attach the SQLite file at `path` to `conn` as `alias`. The file must
+/// exist; it is opened with the flags of `conn`.
+pub fn attach_file(conn: &Connection, path: &Path, alias: &str) -> Result<()> {
+    if !path.is_file() {
+        return Err(Error::InvalidInput(format!(
+            "{} does not exist or is not a file",
+            path.display()
+        )));
+    }
+    let path = std::path::absolute(path)?;
+    let path = path
+        .to_str()
+        .ok_or_else(|| Error::InvalidInput(format!("{} is not valid UTF-8", path.display())))?;
+
+    // An attached file is opened through the VFS of the connection unless
+    // the URI names another, and connections from `open_database` use
+    // `EngramVfs`, which only reads archives.
+    conn.execute(
+        &format!("ATTACH DATABASE ?1 AS {}", quote_identifier(alias)),
+        [file_uri(path)],
+    )
+    .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(())
+}
+
+/// `name` quoted as an SQL identifier.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// URI for a file on disk, opened through the platform's default VFS.
+fn file_uri(path: &str) -> String {
+    let vfs = if cfg!(windows) { "win32" } else { "unix" };
+    let mut uri = String::from("file:");
+    if cfg!(windows) {
+        uri.push('/');
+    }
+    for c in path.chars() {
+        match c {
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            '\\' if cfg!(windows) => uri.push('/'),
+            c => uri.push(c),
+        }
+    }
+    format!("{uri}?vfs={vfs}")
+}
+
+/// Open a read-only in-memory copy of the database in `data`.
+pub(crate) fn load_in_memory(data: &[u8]) -> Result<Connection> {
+    let mut conn = Connection::open_in_memory().map_err(|e| Error::Database(e.to_string()))?;
+    conn.deserialize(DatabaseName::Main, sqlite_copy(data)?, true)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(conn)
+}
+
+/// Copy `data` into a buffer allocated by SQLite, which sqlite3_deserialize
+/// takes ownership of.
+fn sqlite_copy(data: &[u8]) -> Result<OwnedData> {
+    let buffer = unsafe { rusqlite::ffi::sqlite3_malloc64(data.len().max(1) as u64) } as *mut u8;
+    let buffer = NonNull::new(buffer)
+        .ok_or_else(|| Error::Database("out of memory loading database".into()))?;
+    Ok(unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buffer.as_ptr(), data.len());
+        OwnedData::from_raw_nonnull(buffer, data.len())
+    })
+}
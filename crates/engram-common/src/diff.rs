@@ -0,0 +1,487 @@
+//! Differences between two versions of an archive.
+//!
+//! Entries are matched by path and compared by SHA-256 when both sides
+//! recorded one, and by CRC-32 and size otherwise. The manifest is compared
+//! field by field. Embedded SQLite databases can additionally be compared row
+//! by row, keyed by each table's primary key or rowid.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::codec;
+use crate::database;
+use crate::error::{Error, Result};
+use crate::reader::{Archive, EntryInfo};
+
+/// Header that starts every SQLite database file.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// What [`diff`] compares beyond entry metadata.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffOptions {
+    /// Compare the rows of SQLite databases that changed.
+    pub databases: bool,
+}
+
+/// Changes from an old archive to a new one.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveDiff {
+    pub added: Vec<EntrySummary>,
+    pub removed: Vec<EntrySummary>,
+    /// Entries whose content or compression changed.
+    pub modified: Vec<EntryChange>,
+    pub unchanged: usize,
+    /// Change in the total uncompressed size of all entries.
+    pub size_delta: i64,
+    /// Change in the total stored size of all entries.
+    pub compressed_size_delta: i64,
+    /// Changed manifest fields, by JSON pointer.
+    pub manifest: Vec<ValueChange>,
+    /// Row changes of modified databases, when requested.
+    pub databases: Vec<DatabaseDiff>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntrySummary {
+    pub path: String,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub compression: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryChange {
+    pub path: String,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub size_delta: i64,
+    pub old_compressed_size: u64,
+    pub new_compressed_size: u64,
+    pub old_compression: String,
+    pub new_compression: String,
+    pub content_changed: bool,
+    pub compression_changed: bool,
+}
+
+/// A value that was added (`old` is `None`), removed (`new` is `None`) or
+/// replaced.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValueChange {
+    /// JSON pointer of the value within the manifest.
+    pub pointer: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseDiff {
+    pub path: String,
+    pub tables_added: Vec<String>,
+    pub tables_removed: Vec<String>,
+    /// Tables present on both sides that changed.
+    pub tables: Vec<TableDiff>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableDiff {
+    pub name: String,
+    /// The columns changed, so rows were not compared.
+    pub schema_changed: bool,
+    /// Rows as objects of column values.
+    pub added: Vec<Value>,
+    pub removed: Vec<Value>,
+    pub modified: Vec<RowChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RowChange {
+    /// Primary key columns, or the rowid, of the row.
+    pub key: Value,
+    pub old: Value,
+    pub new: Value,
+}
+
+fn summary(entry: &EntryInfo) -> EntrySummary {
+    EntrySummary {
+        path: entry.path.clone(),
+        size: entry.uncompressed_size,
+        compressed_size: entry.compressed_size,
+        compression: codec::method_name(&entry.compression).to_string(),
+    }
+}
+
+fn same_content(old: &EntryInfo, new: &EntryInfo) -> bool {
+    match (&old.sha256, &new.sha256) {
+        (Some(old_hash), Some(new_hash)) => old_hash == new_hash,
+        _ => old.crc32 == new.crc32 && old.uncompressed_size == new.uncompressed_size,
+    }
+}
+
+fn entries(archive: &Archive) -> BTreeMap<String, EntryInfo> {
+    archive
+        .list_files()
+        .iter()
+        .filter_map(|path| Some((path.clone(), archive.get_entry(path)?)))
+        .collect()
+}
+
+fn total(entries: &BTreeMap<String, EntryInfo>, size: impl Fn(&EntryInfo) -> u64) -> i64 {
+    entries.values().map(|entry| size(entry) as i64).sum()
+}
+
+/// Compare `old` with `new`.
+pub fn diff(old: &mut Archive, new: &mut Archive, options: DiffOptions) -> Result<ArchiveDiff> {
+    let old_entries = entries(old);
+    let new_entries = entries(new);
+    let mut result = ArchiveDiff {
+        size_delta: total(&new_entries, |entry| entry.uncompressed_size)
+            - total(&old_entries, |entry| entry.uncompressed_size),
+        compressed_size_delta: total(&new_entries, |entry| entry.compressed_size)
+            - total(&old_entries, |entry| entry.compressed_size),
+        ..Default::default()
+    };
+
+    for (path, old_entry) in &old_entries {
+        let Some(new_entry) = new_entries.get(path) else {
+            result.removed.push(summary(old_entry));
+            continue;
+        };
+
+        // Encrypted entries record no payload hash, so they are compared by
+        // decrypting them.
+        let content_changed = if old_entry.encrypted || new_entry.encrypted {
+            old.hash_file(path)? != new.hash_file(path)?
+        } else {
+            !same_content(old_entry, new_entry)
+        };
+        let old_compression = codec::method_name(&old_entry.compression);
+        let new_compression = codec::method_name(&new_entry.compression);
+        let compression_changed = old_compression != new_compression
+            || old_entry.compression_level != new_entry.compression_level;
+        if !content_changed && !compression_changed {
+            result.unchanged += 1;
+            continue;
+        }
+
+        result.modified.push(EntryChange {
+            path: path.clone(),
+            old_size: old_entry.uncompressed_size,
+            new_size: new_entry.uncompressed_size,
+            size_delta: new_entry.uncompressed_size as i64 - old_entry.uncompressed_size as i64,
+            old_compressed_size: old_entry.compressed_size,
+            new_compressed_size: new_entry.compressed_size,
+            old_compression: old_compression.to_string(),
+            new_compression: new_compression.to_string(),
+            content_changed,
+            compression_changed,
+        });
+
+        if options.databases && content_changed {
+            if let Some(database) = diff_database(old, new, path)? {
+                result.databases.push(database);
+            }
+        }
+    }
+
+    result.added = new_entries
+        .iter()
+        .filter(|(path, _)| !old_entries.contains_key(*path))
+        .map(|(_, entry)| summary(entry))
+        .collect();
+
+    let old_manifest = old.read_manifest()?;
+    let new_manifest = new.read_manifest()?;
+    diff_values(
+        String::new(),
+        old_manifest.as_ref(),
+        new_manifest.as_ref(),
+        &mut result.manifest,
+    );
+
+    Ok(result)
+}
+
+/// Record the differences between two JSON values under `pointer`.
+fn diff_values(
+    pointer: String,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    changes: &mut Vec<ValueChange>,
+) {
+    if old == new {
+        return;
+    }
+
+    if let (Some(Value::Object(old)), Some(Value::Object(new))) = (old, new) {
+        let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for key in keys {
+            let escaped = key.replace('~', "~0").replace('/', "~1");
+            diff_values(
+                format!("{pointer}/{escaped}"),
+                old.get(key),
+                new.get(key),
+                changes,
+            );
+        }
+        return;
+    }
+
+    changes.push(ValueChange {
+        pointer,
+        old: old.cloned(),
+        new: new.cloned(),
+    });
+}
+
+/// Contents of `path` if it is a SQLite database.
+fn database_bytes(archive: &mut Archive, path: &str) -> Result<Option<Vec<u8>>> {
+    let data = archive.read_file(path)?;
+    Ok(data.starts_with(SQLITE_HEADER).then_some(data))
+}
+
+fn diff_database(old: &mut Archive, new: &mut Archive, path: &str) -> Result<Option<DatabaseDiff>> {
+    // engram-core cannot read just the header, so both sides are read in full
+    // to check it and then queried from those bytes instead of read again.
+    let Some(old_data) = database_bytes(old, path)? else {
+        return Ok(None);
+    };
+    let old_conn = database::load_in_memory(&old_data)?;
+    drop(old_data);
+    let Some(new_data) = database_bytes(new, path)? else {
+        return Ok(None);
+    };
+    let new_conn = database::load_in_memory(&new_data)?;
+    drop(new_data);
+    let old_tables = tables(&old_conn)?;
+    let new_tables = tables(&new_conn)?;
+
+    let mut result = DatabaseDiff {
+        path: path.to_string(),
+        tables_added: new_tables.difference(&old_tables).cloned().collect(),
+        tables_removed: old_tables.difference(&new_tables).cloned().collect(),
+        tables: Vec::new(),
+    };
+
+    for table in old_tables.intersection(&new_tables) {
+        let diff = diff_table(&old_conn, &new_conn, table)?;
+        if diff.schema_changed
+            || !diff.added.is_empty()
+            || !diff.removed.is_empty()
+            || !diff.modified.is_empty()
+        {
+            result.tables.push(diff);
+        }
+    }
+
+    Ok(Some(result))
+}
+
+fn sql_error(err: rusqlite::Error) -> Error {
+    Error::Database(err.to_string())
+}
+
+fn tables(conn: &Connection) -> Result<BTreeSet<String>> {
+    let mut stmt = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'")
+        .map_err(sql_error)?;
+    let names = stmt
+        .query_map([], |row| row.get(0))
+        .map_err(sql_error)?
+        .collect::<rusqlite::Result<_>>()
+        .map_err(sql_error)?;
+    Ok(names)
+}
+
+/// Quote an identifier for SQL.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Column names of `table`, and the primary key columns in key order.
+fn columns(conn: &Connection, table: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", quote(table)))
+        .map_err(sql_error)?;
+    let info = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>("name")?, row.get::<_, i64>("pk")?))
+        })
+        .map_err(sql_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(sql_error)?;
+
+    let mut key: Vec<&(String, i64)> = info.iter().filter(|(_, pk)| *pk > 0).collect();
+    key.sort_by_key(|(_, pk)| *pk);
+    let key = key.into_iter().map(|(name, _)| name.clone()).collect();
+    Ok((info.into_iter().map(|(name, _)| name).collect(), key))
+}
+
+fn sql_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(s) => Value::from(String::from_utf8_lossy(s)),
+        ValueRef::Blob(b) => Value::from(b),
+    }
+}
+
+/// Rows of `table` as objects, keyed by the serialized primary key, or by
+/// rowid for tables without one.
+fn rows(
+    conn: &Connection,
+    table: &str,
+    columns: &[String],
+    key: &[String],
+) -> Result<BTreeMap<String, (Value, Value)>> {
+    let select: Vec<String> = columns.iter().map(|column| quote(column)).collect();
+    let key_select = if key.is_empty() {
+        "rowid".to_string()
+    } else {
+        key.iter()
+            .map(|column| quote(column))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let sql = format!(
+        "SELECT {key_select}, {} FROM {}",
+        select.join(", "),
+        quote(table)
+    );
+    let key_len = key.len().max(1);
+
+    let mut stmt = conn.prepare(&sql).map_err(sql_error)?;
+    let mut result = stmt.query([]).map_err(sql_error)?;
+    let mut rows = BTreeMap::new();
+    while let Some(row) = result.next().map_err(sql_error)? {
+        let mut values = (0..key_len + columns.len())
+            .map(|index| row.get_ref(index).map(sql_value))
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(sql_error)?;
+        let object: serde_json::Map<String, Value> = columns
+            .iter()
+            .cloned()
+            .zip(values.split_off(key_len))
+            .collect();
+        let key = match values.len() {
+            1 => values.remove(0),
+            _ => Value::Array(values),
+        };
+        rows.insert(key.to_string(), (key, Value::Object(object)));
+    }
+    Ok(rows)
+}
+
+fn diff_table(old: &Connection, new: &Connection, table: &str) -> Result<TableDiff> {
+    let (old_columns, old_key) = columns(old, table)?;
+    let (new_columns, new_key) = columns(new, table)?;
+    if old_columns != new_columns || old_key != new_key {
+        return Ok(TableDiff {
+            name: table.to_string(),
+            schema_changed: true,
+            ..Default::default()
+        });
+    }
+
+    let old_rows = rows(old, table, &old_columns, &old_key)?;
+    let mut new_rows = rows(new, table, &new_columns, &new_key)?;
+    let mut result = TableDiff {
+        name: table.to_string(),
+        ..Default::default()
+    };
+
+    for (id, (key, old_row)) in old_rows {
+        match new_rows.remove(&id) {
+            None => result.removed.push(old_row),
+            Some((_, new_row)) if new_row != old_row => result.modified.push(RowChange {
+                key,
+                old: old_row,
+                new: new_row,
+            }),
+            Some(_) => {}
+        }
+    }
+    result.added = new_rows.into_values().map(|(_, row)| row).collect();
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn database(sql: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(sql).unwrap();
+        conn
+    }
+
+    #[test]
+    fn manifest_changes_are_keyed_by_pointer() {
+        let old = json!({"name": "app", "tags": ["a"], "a/b": 1, "meta": {"x": 1}});
+        let new = json!({"name": "app", "tags": ["a", "b"], "a/b": 2, "meta": {"y": 1}});
+        let mut changes = Vec::new();
+        diff_values(String::new(), Some(&old), Some(&new), &mut changes);
+
+        let pointers: Vec<&str> = changes.iter().map(|c| c.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/a~1b", "/meta/x", "/meta/y", "/tags"]);
+        assert_eq!(changes[1].new, None);
+        assert_eq!(changes[2].old, None);
+        assert_eq!(changes[3].new, Some(json!(["a", "b"])));
+
+        let mut changes = Vec::new();
+        diff_values(String::new(), Some(&old), Some(&old), &mut changes);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn rows_are_matched_by_primary_key() {
+        let old = database(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+             INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob'), (3, 'Carol');",
+        );
+        let new = database(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+             INSERT INTO users VALUES (1, 'Alice'), (3, 'Caroline'), (4, 'Dave');",
+        );
+        let diff = diff_table(&old, &new, "users").unwrap();
+
+        assert!(!diff.schema_changed);
+        assert_eq!(diff.removed, [json!({"id": 2, "name": "Bob"})]);
+        assert_eq!(diff.added, [json!({"id": 4, "name": "Dave"})]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].old["name"], "Carol");
+        assert_eq!(diff.modified[0].new["name"], "Caroline");
+    }
+
+    #[test]
+    fn schema_changes_skip_rows() {
+        let old = database("CREATE TABLE t (a TEXT); INSERT INTO t VALUES ('x');");
+        let new = database("CREATE TABLE t (a TEXT, b TEXT); INSERT INTO t VALUES ('y', 'z');");
+        let diff = diff_table(&old, &new, "t").unwrap();
+
+        assert!(diff.schema_changed);
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty());
+    }
+
+    #[test]
+    fn tables_without_a_key_use_the_rowid() {
+        let old = database("CREATE TABLE log (line TEXT); INSERT INTO log VALUES ('a'), ('b');");
+        let new = database("CREATE TABLE log (line TEXT); INSERT INTO log VALUES ('a'), ('c');");
+        let diff = diff_table(&old, &new, "log").unwrap();
+
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].new["line"], "c");
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+    }
+}
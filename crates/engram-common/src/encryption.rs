@@ -0,0 +1,269 @@
+//! Authenticated encryption of archive entries.
+//!
+//! Encrypted entries are compressed by the bindings first, then sealed with
+//! AES-256-GCM or ChaCha20-Poly1305 under a fresh random nonce and stored as
+//! `nonce || ciphertext || tag`. The payload size is sealed along with the
+//! compressed bytes, and the entry path and codec are bound as associated
+//! data, so sealed data cannot be moved to another entry or decoded with
+//! another codec. The index records the cipher and key id so readers can pick
+//! the right key from their keyring; its size, CRC-32 and SHA-256 describe the
+//! sealed bytes, not the payload. Paths stay readable without a key.
+
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+
+use crate::codec::Codec;
+use crate::error::{Error, Result};
+
+/// Length of the nonce stored in front of every encrypted entry.
+const NONCE_LEN: usize = 12;
+
+/// Length of the payload size sealed in front of the compressed bytes.
+const SIZE_LEN: usize = 8;
+
+/// Prefix of the associated data, so sealed entries cannot be replayed
+/// elsewhere.
+const DOMAIN: &[u8] = b"engram-entry-v1\n";
+
+/// AEAD cipher used for an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cipher {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+/// 256-bit key, optionally tagged with an id that is stored with every entry
+/// it encrypts.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    pub key: [u8; 32],
+    pub key_id: Option<String>,
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("key", &"<redacted>")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl EncryptionKey {
+    pub fn new(key: &[u8], key_id: Option<String>) -> Result<Self> {
+        let key = key.try_into().map_err(|_| {
+            Error::InvalidInput(format!("encryption keys are 32 bytes, got {}", key.len()))
+        })?;
+        Ok(Self { key, key_id })
+    }
+}
+
+/// Encryption settings for a writer.
+#[derive(Debug, Clone)]
+pub struct EncryptionOptions {
+    pub cipher: Cipher,
+    pub key: EncryptionKey,
+}
+
+/// Index record of an encrypted entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Encryption {
+    pub cipher: Cipher,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+impl EncryptionOptions {
+    /// Seal `data`, the bytes of `path` encoded with `codec` from a payload of
+    /// `size` bytes, returning the stored bytes and their index record.
+    pub fn encrypt(
+        &self,
+        path: &str,
+        codec: Option<Codec>,
+        size: u64,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, Encryption)> {
+        let aad = associated_data(path, codec)?;
+        let mut plaintext = Vec::with_capacity(SIZE_LEN + data.len());
+        plaintext.extend_from_slice(&size.to_le_bytes());
+        plaintext.extend_from_slice(data);
+
+        let sealed = match self.cipher {
+            Cipher::Aes256Gcm => seal::<Aes256Gcm>(&self.key.key, &aad, &plaintext),
+            Cipher::ChaCha20Poly1305 => seal::<ChaCha20Poly1305>(&self.key.key, &aad, &plaintext),
+        }?;
+
+        let record = Encryption {
+            cipher: self.cipher,
+            key_id: self.key.key_id.clone(),
+        };
+        Ok((sealed, record))
+    }
+}
+
+impl Encryption {
+    /// Open the stored bytes of `path`, encoded with `codec`, with a key from
+    /// `keys`, returning the payload size and the encoded bytes.
+    ///
+    /// Keys whose id matches the record are tried first; without a matching
+    /// id every key is tried.
+    pub fn decrypt(
+        &self,
+        path: &str,
+        codec: Option<Codec>,
+        data: &[u8],
+        keys: &[EncryptionKey],
+    ) -> Result<(u64, Vec<u8>)> {
+        let matching: Vec<&EncryptionKey> = keys
+            .iter()
+            .filter(|key| self.key_id.is_some() && key.key_id == self.key_id)
+            .collect();
+        let candidates = if matching.is_empty() {
+            keys.iter().collect()
+        } else {
+            matching
+        };
+
+        if candidates.is_empty() {
+            return Err(Error::Encryption(format!("no key to decrypt {path}")));
+        }
+
+        let aad = associated_data(path, codec)?;
+        let mut plaintext = candidates
+            .into_iter()
+            .find_map(|key| match self.cipher {
+                Cipher::Aes256Gcm => open::<Aes256Gcm>(&key.key, &aad, data),
+                Cipher::ChaCha20Poly1305 => open::<ChaCha20Poly1305>(&key.key, &aad, data),
+            })
+            .filter(|plaintext| plaintext.len() >= SIZE_LEN)
+            .ok_or_else(|| Error::Encryption(format!("wrong key or corrupted data for {path}")))?;
+
+        let encoded = plaintext.split_off(SIZE_LEN);
+        let size = u64::from_le_bytes(plaintext.try_into().expect("split at SIZE_LEN"));
+        Ok((size, encoded))
+    }
+}
+
+/// Associated data binding sealed bytes to their entry path and codec.
+fn associated_data(path: &str, codec: Option<Codec>) -> Result<Vec<u8>> {
+    let mut aad = DOMAIN.to_vec();
+    aad.extend(serde_json::to_vec(&(path, codec))?);
+    Ok(aad)
+}
+
+fn seal<C: Aead + AeadCore + KeyInit>(key: &[u8; 32], aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = C::new_from_slice(key).map_err(|_| Error::Encryption("invalid key".into()))?;
+    let nonce = C::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: data, aad })
+        .map_err(|_| Error::Encryption("encryption failed".into()))?;
+
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn open<C: Aead + AeadCore + KeyInit>(key: &[u8; 32], aad: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let cipher = C::new_from_slice(key).ok()?;
+    cipher
+        .decrypt(
+            nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"encrypted entry payload";
+
+    fn options(cipher: Cipher) -> EncryptionOptions {
+        EncryptionOptions {
+            cipher,
+            key: EncryptionKey::new(&[1; 32], Some("primary".into())).unwrap(),
+        }
+    }
+
+    #[test]
+    fn round_trips_with_both_ciphers() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let options = options(cipher);
+            let (sealed, record) = options.encrypt("a.txt", None, 42, PAYLOAD).unwrap();
+
+            let (size, data) = record
+                .decrypt("a.txt", None, &sealed, std::slice::from_ref(&options.key))
+                .unwrap();
+            assert_eq!(size, 42);
+            assert_eq!(data, PAYLOAD);
+        }
+    }
+
+    #[test]
+    fn detects_tampered_data() {
+        let options = options(Cipher::Aes256Gcm);
+        let (sealed, record) = options.encrypt("a.txt", None, 42, PAYLOAD).unwrap();
+        let keys = [options.key.clone()];
+
+        for position in [0, NONCE_LEN, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[position] ^= 1;
+            assert!(matches!(
+                record.decrypt("a.txt", None, &tampered, &keys),
+                Err(Error::Encryption(_))
+            ));
+        }
+        assert!(record
+            .decrypt("a.txt", None, &sealed[..NONCE_LEN - 1], &keys)
+            .is_err());
+    }
+
+    #[test]
+    fn binds_path_and_codec() {
+        let options = options(Cipher::ChaCha20Poly1305);
+        let codec = Codec::Zstd {
+            level: 3,
+            dictionary: false,
+        };
+        let (sealed, record) = options.encrypt("a.txt", Some(codec), 42, PAYLOAD).unwrap();
+        let keys = [options.key.clone()];
+
+        assert!(record.decrypt("a.txt", Some(codec), &sealed, &keys).is_ok());
+        assert!(record
+            .decrypt("b.txt", Some(codec), &sealed, &keys)
+            .is_err());
+        assert!(record.decrypt("a.txt", None, &sealed, &keys).is_err());
+    }
+
+    #[test]
+    fn tries_every_key_without_a_matching_id() {
+        let options = options(Cipher::Aes256Gcm);
+        let (sealed, record) = options.encrypt("a.txt", None, 42, PAYLOAD).unwrap();
+        let wrong = EncryptionKey::new(&[2; 32], None).unwrap();
+        let unlabelled = EncryptionKey::new(&[1; 32], None).unwrap();
+
+        assert!(record
+            .decrypt("a.txt", None, &sealed, std::slice::from_ref(&wrong))
+            .is_err());
+        assert!(record
+            .decrypt("a.txt", None, &sealed, &[wrong, unlabelled])
+            .is_ok());
+        assert!(record.decrypt("a.txt", None, &sealed, &[]).is_err());
+    }
+}
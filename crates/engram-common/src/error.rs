@@ -0,0 +1,67 @@
+//! Error type shared by the archive helpers.
+
+use std::fmt;
+use std::io;
+
+/// Errors raised while reading or writing archives through the bindings.
+#[derive(Debug)]
+pub enum Error {
+    /// Error reported by engram-core.
+    Core(String),
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// An entry could not be encoded or decoded.
+    Codec(String),
+    NotFound(String),
+    InvalidInput(String),
+    /// The archive signature is missing, untrusted or invalid.
+    Signature(String),
+    /// An entry does not match the hash recorded for it.
+    Integrity(String),
+    /// An entry could not be encrypted or decrypted.
+    Encryption(String),
+    /// SQLite failed to open or load a database.
+    Database(String),
+    /// An archive exceeds the resource limits it was opened with.
+    Limit(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn core(err: impl fmt::Display) -> Self {
+        Error::Core(err.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Core(msg) => write!(f, "{msg}"),
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Json(err) => write!(f, "JSON error: {err}"),
+            Error::Codec(msg) => write!(f, "codec error: {msg}"),
+            Error::NotFound(path) => write!(f, "entry not found: {path}"),
+            Error::InvalidInput(msg) => write!(f, "{msg}"),
+            Error::Signature(msg) => write!(f, "signature verification failed: {msg}"),
+            Error::Integrity(msg) => write!(f, "integrity check failed: {msg}"),
+            Error::Encryption(msg) => write!(f, "encryption error: {msg}"),
+            Error::Database(msg) => write!(f, "database error: {msg}"),
+            Error::Limit(msg) => write!(f, "resource limit exceeded: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
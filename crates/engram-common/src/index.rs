@@ -0,0 +1,208 @@
+//! Archive index describing entries the bindings encoded themselves.
+//!
+//! The index is stored as a regular archive entry under the reserved
+//! `.engram/` prefix, which readers in every binding hide from listings.
+//! Archives without binding-encoded or deduplicated entries carry no index at
+//! all.
+
+use std::collections::BTreeMap;
+
+use engram_core::ArchiveReader;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::codec::Codec;
+use crate::encryption::Encryption;
+use crate::error::{Error, Result};
+
+/// Prefix reserved for archive metadata written by the bindings.
+pub const RESERVED_PREFIX: &str = ".engram/";
+
+/// Path of the archive index.
+pub const INDEX_PATH: &str = ".engram/index.json";
+
+/// Path of the shared zstd dictionary.
+pub const DICTIONARY_PATH: &str = ".engram/zstd.dict";
+
+/// Path engram-core stores the archive manifest under.
+pub const MANIFEST_PATH: &str = "manifest.json";
+
+/// Current index format version.
+pub const INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveIndex {
+    pub version: u32,
+    #[serde(default)]
+    pub entries: BTreeMap<String, IndexEntry>,
+}
+
+/// Index record for a single entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Size of the original payload.
+    pub size: u64,
+    /// CRC-32 of the original payload.
+    pub crc32: u32,
+    /// Hex-encoded SHA-256 of the original payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<Codec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// Entry whose stored data this entry shares. Aliases have no data of
+    /// their own in the archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+}
+
+impl IndexEntry {
+    /// Record for `data`, whose SHA-256 digest is `sha256`.
+    pub fn new(data: &[u8], sha256: &[u8; 32]) -> Self {
+        Self {
+            size: data.len() as u64,
+            crc32: crc32fast::hash(data),
+            sha256: Some(hex::encode(sha256)),
+            codec: None,
+            encryption: None,
+            alias_of: None,
+        }
+    }
+
+    /// Whether the stored data was encoded by the bindings rather than
+    /// engram-core.
+    pub fn is_encoded(&self) -> bool {
+        self.codec.is_some() || self.encryption.is_some()
+    }
+}
+
+impl Default for ArchiveIndex {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl ArchiveIndex {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entry `path` shares its data with, when it is an alias.
+    pub fn alias_target(&self, path: &str) -> Option<&str> {
+        self.entries.get(path)?.alias_of.as_deref()
+    }
+
+    /// Paths of all aliases.
+    pub fn aliases(&self) -> impl Iterator<Item = &String> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.alias_of.is_some())
+            .map(|(path, _)| path)
+    }
+
+    pub fn uses_dictionary(&self) -> bool {
+        self.entries
+            .values()
+            .any(|entry| entry.codec.is_some_and(|codec| codec.uses_dictionary()))
+    }
+}
+
+/// Whether `path` lies in the namespace reserved for binding metadata.
+pub fn is_reserved(path: &str) -> bool {
+    path.starts_with(RESERVED_PREFIX)
+}
+
+/// SHA-256 of an entry payload.
+///
+/// The manifest is hashed in its compact JSON form, since engram-core decides
+/// how it is laid out on disk.
+pub fn entry_digest(path: &str, data: &[u8]) -> Result<[u8; 32]> {
+    if path == MANIFEST_PATH {
+        let manifest: serde_json::Value = serde_json::from_slice(data)?;
+        return Ok(manifest_digest(&manifest));
+    }
+    Ok(Sha256::digest(data).into())
+}
+
+/// SHA-256 of the compact JSON form of a manifest.
+pub fn manifest_digest(manifest: &serde_json::Value) -> [u8; 32] {
+    Sha256::digest(manifest.to_string().as_bytes()).into()
+}
+
+/// Load the index of an archive, or an empty index when it has none.
+pub fn read_index(reader: &mut ArchiveReader) -> Result<ArchiveIndex> {
+    if !reader.contains(INDEX_PATH) {
+        return Ok(ArchiveIndex::default());
+    }
+
+    let data = reader.read_file(INDEX_PATH).map_err(Error::core)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Load the shared zstd dictionary when the index refers to it.
+pub fn read_dictionary(
+    reader: &mut ArchiveReader,
+    index: &ArchiveIndex,
+) -> Result<Option<Vec<u8>>> {
+    if !index.uses_dictionary() {
+        return Ok(None);
+    }
+
+    reader
+        .read_file(DICTIONARY_PATH)
+        .map(Some)
+        .map_err(Error::core)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_changes_with_content() {
+        let digest = entry_digest("a.txt", b"alpha").unwrap();
+
+        assert_eq!(digest, entry_digest("b.txt", b"alpha").unwrap());
+        assert_ne!(digest, entry_digest("a.txt", b"alphb").unwrap());
+        assert_ne!(digest, entry_digest("a.txt", b"alpha\0").unwrap());
+    }
+
+    #[test]
+    fn manifest_digest_ignores_layout_but_not_values() {
+        let compact = entry_digest(MANIFEST_PATH, br#"{"name":"app","version":1}"#).unwrap();
+
+        assert_eq!(
+            compact,
+            entry_digest(MANIFEST_PATH, b"{ \"name\": \"app\",\n  \"version\": 1 }").unwrap()
+        );
+        assert_ne!(
+            compact,
+            entry_digest(MANIFEST_PATH, br#"{"name":"app","version":2}"#).unwrap()
+        );
+        assert!(entry_digest(MANIFEST_PATH, b"not json").is_err());
+    }
+
+    #[test]
+    fn records_describe_the_payload() {
+        let digest = entry_digest("a.txt", b"alpha").unwrap();
+        let entry = IndexEntry::new(b"alpha", &digest);
+
+        assert_eq!(entry.size, 5);
+        assert_eq!(entry.crc32, crc32fast::hash(b"alpha"));
+        assert_eq!(entry.sha256, Some(hex::encode(digest)));
+        assert!(!entry.is_encoded());
+    }
+
+    #[test]
+    fn reserves_only_the_metadata_prefix() {
+        assert!(is_reserved(INDEX_PATH));
+        assert!(is_reserved(DICTIONARY_PATH));
+        assert!(!is_reserved(MANIFEST_PATH));
+        assert!(!is_reserved("data/.engram/index.json"));
+        assert!(!is_reserved(".engramrc"));
+    }
+}
@@ -0,0 +1,31 @@
+//! # Engram Common
+//!
+//! Archive logic shared by the Node.js and C bindings, layered on top of
+//! `engram-core` so every binding makes the same decisions when writing and
+//! reading .eng archives.
+
+pub mod atomic;
+pub mod codec;
+pub mod database;
+pub mod diff;
+pub mod encryption;
+pub mod error;
+pub mod index;
+pub mod limits;
+pub mod memory;
+pub mod overlay;
+pub mod patch;
+pub mod policy;
+pub mod reader;
+pub mod signature;
+pub mod sql;
+pub mod walk;
+pub mod writer;
+
+pub use encryption::{Cipher, EncryptionKey, EncryptionOptions};
+pub use error::{Error, Result};
+pub use limits::Limits;
+pub use overlay::Overlay;
+pub use policy::{CompressionPolicy, CompressionRule};
+pub use reader::{Archive, EntryInfo, OpenOptions, StoredEntry};
+pub use writer::{DictionaryOptions, FinalizeStats, Writer, WriterOptions};
@@ -0,0 +1,161 @@
+//! Resource limits for archives from untrusted sources.
+//!
+//! engram-core allocates whatever size an entry header claims, so the limits
+//! are checked against the claimed sizes before an entry is read or decoded.
+//! Binding codecs never decode past the size recorded in the index.
+//!
+//! Databases are normally paged by `EngramVfs`, whose reads never pass
+//! through [`Archive`](crate::Archive). With a total read limit, databases
+//! are therefore read and counted in full when they are opened instead; see
+//! [`database`](crate::database).
+
+use crate::error::{Error, Result};
+
+/// Limits applied when opening and reading an archive. `None` disables a
+/// limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Maximum number of entries, counting deduplicated aliases.
+    pub max_entries: Option<usize>,
+    /// Maximum length of an entry path in bytes.
+    pub max_path_length: Option<usize>,
+    /// Maximum uncompressed size of a single entry.
+    pub max_entry_size: Option<u64>,
+    /// Maximum ratio of uncompressed to stored size of an entry.
+    pub max_compression_ratio: Option<f64>,
+    /// Maximum number of uncompressed bytes read over the archive's lifetime.
+    pub max_total_bytes: Option<u64>,
+}
+
+impl Limits {
+    /// Check the entry listing of an archive.
+    pub(crate) fn check_listing<'a>(
+        &self,
+        paths: impl ExactSizeIterator<Item = &'a String>,
+    ) -> Result<()> {
+        if let Some(max) = self.max_entries {
+            if paths.len() > max {
+                return Err(Error::Limit(format!(
+                    "archive has {} entries, limit is {max}",
+                    paths.len()
+                )));
+            }
+        }
+
+        if let Some(max) = self.max_path_length {
+            for path in paths {
+                if path.len() > max {
+                    return Err(Error::Limit(format!(
+                        "path is {} bytes, limit is {max}: {path}",
+                        path.len()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the claimed `size` and `stored_size` of `path` before it is read,
+    /// given that `read` bytes have been read so far.
+    pub(crate) fn check_entry(
+        &self,
+        path: &str,
+        size: u64,
+        stored_size: u64,
+        read: u64,
+    ) -> Result<()> {
+        if let Some(max) = self.max_entry_size {
+            if size > max {
+                return Err(Error::Limit(format!(
+                    "{path} is {size} bytes uncompressed, limit is {max}"
+                )));
+            }
+        }
+
+        if let Some(max) = self.max_compression_ratio {
+            let ratio = size as f64 / stored_size.max(1) as f64;
+            if ratio > max {
+                return Err(Error::Limit(format!(
+                    "{path} has a compression ratio of {ratio:.1}, limit is {max}"
+                )));
+            }
+        }
+
+        if let Some(max) = self.max_total_bytes {
+            if read.saturating_add(size) > max {
+                return Err(Error::Limit(format!(
+                    "reading {path} would exceed the total read limit of {max} bytes"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_limits_are_inclusive() {
+        let limits = Limits {
+            max_entry_size: Some(100),
+            max_compression_ratio: Some(10.0),
+            max_total_bytes: Some(250),
+            ..Default::default()
+        };
+
+        assert!(limits.check_entry("a", 100, 10, 150).is_ok());
+        assert!(matches!(
+            limits.check_entry("a", 101, 100, 0),
+            Err(Error::Limit(_))
+        ));
+        assert!(matches!(
+            limits.check_entry("a", 100, 9, 0),
+            Err(Error::Limit(_))
+        ));
+        assert!(matches!(
+            limits.check_entry("a", 100, 100, 151),
+            Err(Error::Limit(_))
+        ));
+    }
+
+    #[test]
+    fn empty_stored_entries_do_not_divide_by_zero() {
+        let limits = Limits {
+            max_compression_ratio: Some(1.0),
+            ..Default::default()
+        };
+
+        assert!(limits.check_entry("a", 0, 0, 0).is_ok());
+        assert!(limits.check_entry("a", 2, 0, 0).is_err());
+    }
+
+    #[test]
+    fn total_bytes_do_not_overflow() {
+        let limits = Limits {
+            max_total_bytes: Some(u64::MAX),
+            ..Default::default()
+        };
+
+        assert!(limits.check_entry("a", 1, 1, u64::MAX - 1).is_ok());
+        assert!(limits.check_entry("a", u64::MAX, 1, 1).is_ok());
+    }
+
+    #[test]
+    fn listing_limits_are_inclusive() {
+        let limits = Limits {
+            max_entries: Some(2),
+            max_path_length: Some(3),
+            ..Default::default()
+        };
+        let paths = ["abc".to_string(), "de".to_string(), "fghi".to_string()];
+
+        assert!(limits.check_listing(paths[..2].iter()).is_ok());
+        assert!(limits.check_listing(paths.iter()).is_err());
+        assert!(limits.check_listing(paths[1..].iter()).is_err());
+        assert!(Limits::default().check_listing(paths.iter()).is_ok());
+    }
+}
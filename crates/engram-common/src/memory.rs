@@ -0,0 +1,68 @@
+//! Archives held in memory.
+//!
+//! engram-core and the SQLite VFS open archives by path, so in-memory archives
+//! are exposed through a path that refers to memory: an anonymous `memfd`
+//! reached through `/proc/self/fd`, which never touches a filesystem. macOS
+//! and Windows have no equivalent, and a temporary file would put the bytes
+//! on disk, so in-memory archives are only supported on Linux and fail with
+//! [`io::ErrorKind::Unsupported`] elsewhere.
+//!
+//! Writers use the same path to build an archive in memory.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// In-memory copy of an archive, readable by path while it is alive.
+#[derive(Debug)]
+pub struct MemoryFile {
+    path: String,
+    // Keeps the memfd open; its `/proc` path dies with it.
+    file: File,
+}
+
+impl MemoryFile {
+    /// Path engram-core and the VFS can open.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Current contents, including anything written through [`path`].
+    ///
+    /// [`path`]: MemoryFile::path
+    pub fn read_all(&self) -> io::Result<Vec<u8>> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Copy `data` into an anonymous memory file.
+    #[cfg(target_os = "linux")]
+    pub fn new(data: &[u8]) -> io::Result<Self> {
+        use std::io::Write;
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        let fd = unsafe { libc::memfd_create(c"engram-archive".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(data)?;
+
+        Ok(Self {
+            path: format!("/proc/self/fd/{}", file.as_raw_fd()),
+            file,
+        })
+    }
+
+    /// Platforms without `memfd` cannot hold an archive in memory.
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_data: &[u8]) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "in-memory archives are only supported on Linux; write the archive to a file instead",
+        ))
+    }
+}
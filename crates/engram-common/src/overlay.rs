@@ -0,0 +1,181 @@
+//! Union view over a stack of archives.
+//!
+//! Layers are ordered by precedence, so a patch archive listed before its base
+//! shadows the base's entries. A patch deletes a lower entry with a whiteout:
+//! an empty entry named `.wh.<name>` next to the entry it hides, as in OCI
+//! image layers. A whiteout of a directory hides everything below it, and
+//! whiteouts only affect lower layers.
+
+use std::collections::{BTreeSet, HashSet};
+
+use rusqlite::Connection;
+
+use crate::database;
+use crate::error::{Error, Result};
+use crate::reader::{Archive, EntryInfo};
+
+/// File name prefix marking a whiteout entry.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Path of the whiteout entry that hides `path` in lower layers.
+pub fn whiteout_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((dir, name)) => format!("{dir}/{WHITEOUT_PREFIX}{name}"),
+        None => format!("{WHITEOUT_PREFIX}{path}"),
+    }
+}
+
+/// Path hidden by the whiteout entry `path`, if it is one.
+pub fn whiteout_target(path: &str) -> Option<String> {
+    match path.rsplit_once('/') {
+        Some((dir, name)) => name
+            .strip_prefix(WHITEOUT_PREFIX)
+            .map(|name| format!("{dir}/{name}")),
+        None => path.strip_prefix(WHITEOUT_PREFIX).map(str::to_string),
+    }
+}
+
+/// `path` and each of its parent directories, shortest first.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/')
+        .map(|(end, _)| &path[..end])
+        .chain(std::iter::once(path))
+}
+
+/// Whether `path` or one of its parent directories is in `hidden`.
+fn is_hidden(path: &str, hidden: &HashSet<String>) -> bool {
+    ancestors(path).any(|ancestor| hidden.contains(ancestor))
+}
+
+/// Archives merged into a single read-only view.
+pub struct Overlay {
+    /// Highest precedence first.
+    layers: Vec<Archive>,
+}
+
+impl Overlay {
+    /// Stack `layers`, the first of which takes precedence.
+    pub fn new(layers: Vec<Archive>) -> Result<Self> {
+        if layers.is_empty() {
+            return Err(Error::InvalidInput(
+                "an overlay needs at least one layer".into(),
+            ));
+        }
+        Ok(Self { layers })
+    }
+
+    pub fn layers(&self) -> &[Archive] {
+        &self.layers
+    }
+
+    /// Index of the layer that provides `path`, or `None` if no layer has it
+    /// or a higher layer whites it out.
+    pub fn layer_of(&self, path: &str) -> Option<usize> {
+        if whiteout_target(path).is_some() {
+            return None;
+        }
+
+        for (position, layer) in self.layers.iter().enumerate() {
+            if layer.contains(path) {
+                return Some(position);
+            }
+            if ancestors(path).any(|ancestor| layer.contains(&whiteout_path(ancestor))) {
+                return None;
+            }
+        }
+        None
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.layer_of(path).is_some()
+    }
+
+    /// Visible entries of every layer, sorted.
+    pub fn list_files(&self) -> Vec<String> {
+        let mut visible = BTreeSet::new();
+        let mut hidden = HashSet::new();
+        for layer in &self.layers {
+            let mut whiteouts = Vec::new();
+            for path in layer.list_files() {
+                match whiteout_target(path) {
+                    Some(target) => whiteouts.push(target),
+                    None if !is_hidden(path, &hidden) => {
+                        visible.insert(path.clone());
+                    }
+                    None => {}
+                }
+            }
+            hidden.extend(whiteouts);
+        }
+        visible.into_iter().collect()
+    }
+
+    pub fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        // A whiteout that hides a directory sits outside of it, so whiteouts
+        // cannot be filtered by the prefix.
+        let mut files = self.list_files();
+        files.retain(|file| file.starts_with(prefix));
+        files
+    }
+
+    pub fn get_entry(&self, path: &str) -> Option<EntryInfo> {
+        self.layers[self.layer_of(path)?].get_entry(path)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let position = self
+            .layer_of(path)
+            .ok_or_else(|| Error::NotFound(path.to_string()))?;
+        self.layers[position].read_file(path)
+    }
+
+    /// Open the database at `db_path` from the layer that provides it.
+    pub fn open_database(&mut self, db_path: &str) -> Result<Connection> {
+        let position = self
+            .layer_of(db_path)
+            .ok_or_else(|| Error::NotFound(db_path.to_string()))?;
+        database::open_database(&mut self.layers[position], db_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whiteouts_name_the_entry_they_hide() {
+        assert_eq!(whiteout_path("a.txt"), ".wh.a.txt");
+        assert_eq!(whiteout_path("docs/a.txt"), "docs/.wh.a.txt");
+        assert_eq!(whiteout_path("docs/"), ".wh.docs");
+
+        for path in ["a.txt", "docs/a.txt", "docs/nested/a.txt"] {
+            assert_eq!(whiteout_target(&whiteout_path(path)).as_deref(), Some(path));
+        }
+        assert_eq!(whiteout_target("docs/a.wh.txt"), None);
+        assert_eq!(whiteout_target(".wh/a.txt"), None);
+    }
+
+    #[test]
+    fn directory_whiteouts_hide_everything_below() {
+        let hidden = HashSet::from(["docs".to_string(), "img/logo.png".to_string()]);
+
+        assert!(is_hidden("docs", &hidden));
+        assert!(is_hidden("docs/a.txt", &hidden));
+        assert!(is_hidden("docs/nested/a.txt", &hidden));
+        assert!(is_hidden("img/logo.png", &hidden));
+        assert!(!is_hidden("docs.txt", &hidden));
+        assert!(!is_hidden("documents/a.txt", &hidden));
+        assert!(!is_hidden("img/logo.png.bak", &hidden));
+        assert!(!is_hidden("img/icon.png", &hidden));
+    }
+
+    #[test]
+    fn ancestors_start_at_the_top() {
+        assert_eq!(
+            ancestors("a/b/c.txt").collect::<Vec<_>>(),
+            ["a", "a/b", "a/b/c.txt"]
+        );
+        assert_eq!(ancestors("c.txt").collect::<Vec<_>>(), ["c.txt"]);
+    }
+}
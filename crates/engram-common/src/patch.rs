@@ -0,0 +1,451 @@
+//! Binary delta patches between two versions of an archive.
+//!
+//! A patch is itself an archive. `patch.json` lists every entry of the new
+//! archive in order, with its SHA-256 and compression, and says where its
+//! content comes from: an identical entry of the base archive, a zstd delta
+//! compressed with a base entry as reference prefix, or the full content.
+//! Deltas and full contents are stored under `data/`.
+//!
+//! A changed entry is diffed against the base entry at the same path. An
+//! entry whose path is new, such as a renamed file, is diffed against a
+//! removed base entry with the same file name, or failing that the same
+//! extension.
+//!
+//! Applying a patch checks each base entry it uses and each rebuilt entry
+//! against the hashes in `patch.json`, so a patch applied to the wrong base
+//! fails instead of producing a corrupt archive. Deltas are checked against
+//! the patch archive's limits before they are decoded. The rebuilt archive is
+//! not signed; sign the patch to authenticate an update.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use engram_core::CompressionMethod;
+use serde::{Deserialize, Serialize};
+
+use crate::codec;
+use crate::error::{Error, Result};
+use crate::index;
+use crate::reader::Archive;
+use crate::writer::{FinalizeStats, Writer, WriterOptions};
+
+/// Entry of a patch archive that describes the patch.
+pub const PATCH_HEADER_PATH: &str = "patch.json";
+
+/// Version of the patch layout written by [`create_patch`].
+pub const PATCH_VERSION: u32 = 1;
+
+/// zstd level used for deltas and full contents when none is given.
+pub const DEFAULT_PATCH_LEVEL: i32 = 19;
+
+/// Smallest zstd window, used for small entries.
+const MIN_WINDOW_LOG: u32 = 10;
+
+/// Largest zstd window a delta may need, matching zstd's 64-bit limit.
+const MAX_WINDOW_LOG: u32 = 31;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PatchOptions {
+    /// zstd level for deltas and full contents.
+    pub level: Option<i32>,
+}
+
+/// How the entries of a new archive were carried by a patch.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchStats {
+    /// Entries taken unchanged from the base archive.
+    pub copied: usize,
+    /// Entries stored as deltas against a base entry.
+    pub deltas: usize,
+    /// Entries stored in full.
+    pub literals: usize,
+    /// Total uncompressed size of the new archive's entries.
+    pub new_bytes: u64,
+    /// Total size of the deltas and full contents in the patch.
+    pub patch_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PatchHeader {
+    version: u32,
+    /// The new archive stored identical entries once.
+    deduplicate: bool,
+    /// Entries of the new archive, in order.
+    entries: Vec<PatchEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PatchEntry {
+    path: String,
+    size: u64,
+    sha256: String,
+    compression: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    level: Option<i32>,
+    source: Source,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Source {
+    /// Identical to the base entry `base`.
+    Copy { base: String, base_sha256: String },
+    /// zstd frame at `data`, compressed with the base entry `base` as
+    /// reference prefix.
+    Delta {
+        base: String,
+        base_sha256: String,
+        data: String,
+    },
+    /// Full content at `data`.
+    Literal { data: String },
+}
+
+/// zstd window that spans the reference prefix and the content after it.
+fn window_log(prefix_len: usize, len: u64) -> u32 {
+    let span = (prefix_len as u64).saturating_add(len).max(1);
+    (u64::BITS - (span - 1).leading_zeros()).clamp(MIN_WINDOW_LOG, MAX_WINDOW_LOG)
+}
+
+fn encode_delta(base: &[u8], data: &[u8], level: i32) -> Result<Vec<u8>> {
+    let codec_error = |e: std::io::Error| Error::Codec(format!("failed to encode delta: {e}"));
+    let mut encoder = zstd::stream::write::Encoder::with_ref_prefix(Vec::new(), level, base)
+        .map_err(codec_error)?;
+    encoder.long_distance_matching(true).map_err(codec_error)?;
+    encoder
+        .window_log(window_log(base.len(), data.len() as u64))
+        .map_err(codec_error)?;
+    encoder.write_all(data).map_err(codec_error)?;
+    encoder.finish().map_err(codec_error)
+}
+
+fn decode_delta(base: &[u8], delta: &[u8], size: u64, path: &str) -> Result<Vec<u8>> {
+    let codec_error = |e: std::io::Error| Error::Codec(format!("failed to decode {path}: {e}"));
+    let mut decoder =
+        zstd::stream::read::Decoder::with_ref_prefix(delta, base).map_err(codec_error)?;
+    decoder
+        .window_log_max(window_log(base.len(), size))
+        .map_err(codec_error)?;
+
+    let mut data = Vec::new();
+    decoder
+        .take(size.saturating_add(1))
+        .read_to_end(&mut data)
+        .map_err(codec_error)?;
+    if data.len() as u64 != size {
+        return Err(Error::Codec(format!("delta for {path} has the wrong size")));
+    }
+    Ok(data)
+}
+
+/// Hex-encoded SHA-256 of `data` stored as `path`.
+fn digest(path: &str, data: &[u8]) -> Result<String> {
+    Ok(hex::encode(index::entry_digest(path, data)?))
+}
+
+/// Base entry to diff `path` against when `old` has no entry at that path:
+/// the first of `removed` with the same file name, or else the same
+/// extension.
+fn similar_base<'a>(path: &str, removed: &'a [String]) -> Option<&'a String> {
+    let path = Path::new(path);
+    let file_name = path.file_name()?;
+    removed
+        .iter()
+        .find(|base| Path::new(base).file_name() == Some(file_name))
+        .or_else(|| {
+            let extension = path.extension()?;
+            removed
+                .iter()
+                .find(|base| Path::new(base).extension() == Some(extension))
+        })
+}
+
+/// Read the base entry `path` and check it is the one the patch was made
+/// against.
+fn read_base(base: &mut Archive, path: &str, sha256: &str) -> Result<Vec<u8>> {
+    if !base.contains(path) {
+        return Err(Error::Integrity(format!(
+            "base archive has no entry {path}, which the patch needs"
+        )));
+    }
+    let data = base.read_file(path)?;
+    if digest(path, &data)? != sha256 {
+        return Err(Error::Integrity(format!(
+            "base entry {path} does not match the patch"
+        )));
+    }
+    Ok(data)
+}
+
+/// Write a patch to `out` that rebuilds `new` from `old`.
+///
+/// Entries of `new` identical to an entry of `old`, at any path, are copied.
+/// A changed entry becomes a delta against the entry at the same path in
+/// `old`, or against a similarly named entry removed from `old` when there is
+/// none, if that is smaller than compressing it on its own. Encrypted entries
+/// are refused, since they would be rebuilt in the clear.
+///
+/// Entries of `old` are matched by the SHA-256 in their index where it has
+/// one, so only the entries used as delta bases are read.
+pub fn create_patch(
+    old: &mut Archive,
+    new: &mut Archive,
+    out: &Path,
+    options: &PatchOptions,
+) -> Result<PatchStats> {
+    let level = options.level.unwrap_or(DEFAULT_PATCH_LEVEL);
+    codec::Codec::for_level(CompressionMethod::Zstd, level)?;
+    let literal_codec = codec::Codec::Zstd {
+        level,
+        dictionary: false,
+    };
+
+    // The manifest is hashed as compact JSON rather than as stored, so it is
+    // only ever copied from the base manifest. A recorded hash is trusted
+    // here, since applying the patch checks every base entry it uses.
+    let mut old_paths: HashMap<String, String> = HashMap::new();
+    let mut old_hashes: HashMap<String, String> = HashMap::new();
+    for path in old.list_files().to_vec() {
+        if path != index::MANIFEST_PATH {
+            let sha256 = match old.get_entry(&path).and_then(|entry| entry.sha256) {
+                Some(sha256) => sha256,
+                None => old.hash_file(&path)?,
+            };
+            old_paths
+                .entry(sha256.clone())
+                .or_insert_with(|| path.clone());
+            old_hashes.insert(path, sha256);
+        }
+    }
+    let new_paths: HashSet<&String> = new.list_files().iter().collect();
+    let removed: Vec<String> = old
+        .list_files()
+        .iter()
+        .filter(|path| old_hashes.contains_key(*path) && !new_paths.contains(path))
+        .cloned()
+        .collect();
+
+    let mut writer = Writer::create(out, WriterOptions::default())?;
+    let mut stats = PatchStats::default();
+    let mut header = PatchHeader {
+        version: PATCH_VERSION,
+        deduplicate: false,
+        entries: Vec::new(),
+    };
+
+    for (position, path) in new.list_files().to_vec().into_iter().enumerate() {
+        let entry = new
+            .get_entry(&path)
+            .ok_or_else(|| Error::NotFound(path.clone()))?;
+        if entry.encrypted {
+            return Err(Error::InvalidInput(format!(
+                "cannot patch encrypted entry {path}"
+            )));
+        }
+        header.deduplicate |= entry.alias_of.is_some();
+
+        let data = new.read_file(&path)?;
+        let sha256 = digest(&path, &data)?;
+        stats.new_bytes += data.len() as u64;
+
+        let copy_from = if path == index::MANIFEST_PATH {
+            (old.contains(&path) && old.hash_file(&path)? == sha256).then(|| path.clone())
+        } else {
+            old_paths.get(&sha256).cloned()
+        };
+
+        let source = if let Some(base) = copy_from {
+            stats.copied += 1;
+            Source::Copy {
+                base,
+                base_sha256: sha256.clone(),
+            }
+        } else {
+            let data_path = format!("data/{position}");
+            let full = literal_codec.encode(&data, None)?;
+            let base_path = if old_hashes.contains_key(&path) {
+                Some(&path)
+            } else {
+                similar_base(&path, &removed)
+            };
+            let delta = match base_path {
+                Some(base_path) => {
+                    let base = old.read_file(base_path)?;
+                    let delta = encode_delta(&base, &data, level)?;
+                    (delta.len() < full.len()).then(|| (delta, base_path.clone()))
+                }
+                None => None,
+            };
+
+            match delta {
+                Some((delta, base_path)) => {
+                    stats.deltas += 1;
+                    stats.patch_bytes += delta.len() as u64;
+                    writer.add_file_with_compression(
+                        &data_path,
+                        &delta,
+                        CompressionMethod::None,
+                        None,
+                    )?;
+                    Source::Delta {
+                        base_sha256: old_hashes[&base_path].clone(),
+                        base: base_path,
+                        data: data_path,
+                    }
+                }
+                None => {
+                    stats.literals += 1;
+                    stats.patch_bytes += full.len() as u64;
+                    writer.add_precompressed(&data_path, &data, literal_codec, full)?;
+                    Source::Literal { data: data_path }
+                }
+            }
+        };
+
+        header.entries.push(PatchEntry {
+            path,
+            size: data.len() as u64,
+            sha256,
+            compression: codec::method_name(&entry.compression).to_string(),
+            level: entry.compression_level,
+            source,
+        });
+    }
+
+    writer.add_file_with_compression(
+        PATCH_HEADER_PATH,
+        &serde_json::to_vec(&header)?,
+        CompressionMethod::Zstd,
+        None,
+    )?;
+    writer.finalize()?;
+    Ok(stats)
+}
+
+/// Rebuild the new archive described by `patch` from `base`, writing it to
+/// `out`.
+///
+/// Every entry is checked against the SHA-256 recorded in the patch before it
+/// is written, and `out` is only replaced once the whole archive is rebuilt.
+/// The sizes `patch.json` claims for deltas are checked against the limits
+/// `patch` was opened with, counting every rebuilt delta towards the total
+/// read limit. Entries compressed with a zstd dictionary are recompressed without one.
+pub fn apply_patch(base: &mut Archive, patch: &mut Archive, out: &Path) -> Result<FinalizeStats> {
+    if !patch.contains(PATCH_HEADER_PATH) {
+        return Err(Error::InvalidInput(format!(
+            "{} is not a patch: it has no {PATCH_HEADER_PATH}",
+            patch.path()
+        )));
+    }
+    let header: PatchHeader = serde_json::from_slice(&patch.read_file(PATCH_HEADER_PATH)?)?;
+    if header.version != PATCH_VERSION {
+        return Err(Error::InvalidInput(format!(
+            "unsupported patch version {}",
+            header.version
+        )));
+    }
+
+    let options = WriterOptions {
+        deduplicate: header.deduplicate,
+        ..Default::default()
+    };
+    let mut writer = Writer::create(out, options)?;
+    let mut rebuilt = 0u64;
+
+    for entry in &header.entries {
+        let data = match &entry.source {
+            Source::Copy {
+                base: path,
+                base_sha256,
+            } => read_base(base, path, base_sha256)?,
+            Source::Delta {
+                base: path,
+                base_sha256,
+                data,
+            } => {
+                let delta = patch.read_file(data)?;
+                let prefix = read_base(base, path, base_sha256)?;
+                let stored = (prefix.len() + delta.len()) as u64;
+                patch
+                    .limits()
+                    .check_entry(&entry.path, entry.size, stored, rebuilt)?;
+                rebuilt += entry.size;
+                decode_delta(&prefix, &delta, entry.size, &entry.path)?
+            }
+            Source::Literal { data } => patch.read_file(data)?,
+        };
+        if digest(&entry.path, &data)? != entry.sha256 {
+            return Err(Error::Integrity(format!(
+                "SHA-256 mismatch for rebuilt entry {}",
+                entry.path
+            )));
+        }
+
+        if entry.path == index::MANIFEST_PATH {
+            writer.add_manifest(&serde_json::from_slice(&data)?)?;
+        } else {
+            let method = codec::method_from_name(&entry.compression)?;
+            writer.add_file_with_compression(&entry.path, &data, method, entry.level)?;
+        }
+    }
+
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &[u8] = b"The quick brown fox jumps over the lazy dog. Pack my box with jugs.";
+
+    #[test]
+    fn delta_round_trips() {
+        let mut data = BASE.repeat(50);
+        data.extend_from_slice(b"and one more line");
+
+        let delta = encode_delta(BASE, &data, 3).unwrap();
+        assert!(delta.len() < data.len());
+        assert_eq!(
+            decode_delta(BASE, &delta, data.len() as u64, "a").unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn delta_needs_its_base_and_size() {
+        let data = [BASE, b" changed".as_slice()].concat();
+        let delta = encode_delta(BASE, &data, 3).unwrap();
+        let size = data.len() as u64;
+
+        assert!(decode_delta(BASE, &delta, size - 1, "a").is_err());
+        assert!(decode_delta(BASE, &delta, size + 1, "a").is_err());
+        assert_ne!(
+            decode_delta(&BASE[1..], &delta, size, "a").ok().as_deref(),
+            Some(data.as_slice())
+        );
+        // The window is clamped, and the size is checked against the output.
+        assert!(decode_delta(BASE, &delta, u64::MAX, "a").is_err());
+    }
+
+    #[test]
+    fn window_spans_prefix_and_content() {
+        assert_eq!(window_log(0, 0), MIN_WINDOW_LOG);
+        assert_eq!(window_log(1 << 19, 1 << 19), 20);
+        assert_eq!(window_log(1 << 19, (1 << 19) + 1), 21);
+        assert_eq!(window_log(usize::MAX, u64::MAX), MAX_WINDOW_LOG);
+    }
+
+    #[test]
+    fn renamed_entries_prefer_the_same_file_name() {
+        let removed = ["old/app.js".to_string(), "old/lib.js".to_string()];
+
+        assert_eq!(similar_base("new/lib.js", &removed), Some(&removed[1]));
+        assert_eq!(similar_base("new/main.js", &removed), Some(&removed[0]));
+        assert_eq!(similar_base("new/main.css", &removed), None);
+        assert_eq!(similar_base("README", &removed), None);
+    }
+}
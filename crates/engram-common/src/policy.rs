@@ -0,0 +1,176 @@
+//! Automatic compression selection for archive entries.
+//!
+//! A [`CompressionPolicy`] maps entry paths and payloads to a compression
+//! method: extension rules first, then a size threshold below which entries are
+//! stored, then an entropy probe that stores payloads which would not shrink.
+
+use engram_core::CompressionMethod;
+
+/// Extensions of formats that are already compressed and gain nothing from
+/// a second pass.
+const PRECOMPRESSED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "heic", "mp3", "mp4", "m4a", "ogg", "webm",
+    "woff", "woff2", "zip", "gz", "tgz", "bz2", "xz", "zst", "br", "lz4", "7z", "rar", "eng",
+];
+
+/// Extensions of text formats that compress well with zstd.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "json", "ndjson", "jsonl", "csv", "tsv", "xml", "html", "htm", "css", "js", "mjs",
+    "cjs", "ts", "svg", "yaml", "yml", "toml", "sql", "log",
+];
+
+/// Default number of bytes below which entries are stored uncompressed.
+pub const DEFAULT_STORE_BELOW: u64 = 512;
+
+/// Default entropy (bits per byte) at or above which payloads are stored.
+pub const DEFAULT_ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// Default number of bytes sampled when estimating entropy.
+pub const DEFAULT_SAMPLE_SIZE: usize = 16 * 1024;
+
+/// Number of evenly spaced windows the entropy sample is drawn from.
+const SAMPLE_WINDOWS: usize = 4;
+
+/// Maps a set of file extensions to a compression method.
+#[derive(Debug, Clone)]
+pub struct CompressionRule {
+    /// Lower-case extensions without the leading dot.
+    pub extensions: Vec<String>,
+    pub compression: CompressionMethod,
+}
+
+impl CompressionRule {
+    pub fn new<I, S>(extensions: I, compression: CompressionMethod) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            extensions: extensions
+                .into_iter()
+                .map(|ext| normalize_extension(ext.as_ref()))
+                .collect(),
+            compression,
+        }
+    }
+
+    fn matches(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|ext| ext == extension)
+    }
+}
+
+/// Declarative compression policy applied when entries are added to an archive.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    /// Extension rules, checked in order; the first match wins.
+    pub rules: Vec<CompressionRule>,
+    /// Method for entries no rule matches. `None` defers to engram-core.
+    pub default_compression: Option<CompressionMethod>,
+    /// Entries smaller than this many bytes are stored uncompressed.
+    pub store_below: u64,
+    /// Payloads whose sampled entropy reaches this value are stored
+    /// uncompressed. `None` disables the probe.
+    pub entropy_threshold: Option<f64>,
+    /// Number of bytes sampled by the entropy probe.
+    pub sample_size: usize,
+}
+
+impl Default for CompressionPolicy {
+    /// Store already-compressed formats, zstd for text, and defer everything
+    /// else to engram-core.
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                CompressionRule::new(PRECOMPRESSED_EXTENSIONS, CompressionMethod::None),
+                CompressionRule::new(TEXT_EXTENSIONS, CompressionMethod::Zstd),
+            ],
+            default_compression: None,
+            store_below: DEFAULT_STORE_BELOW,
+            entropy_threshold: Some(DEFAULT_ENTROPY_THRESHOLD),
+            sample_size: DEFAULT_SAMPLE_SIZE,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Choose a compression method for an entry.
+    ///
+    /// Returns `None` when the decision should be left to engram-core's own
+    /// default selection.
+    pub fn choose(&self, path: &str, data: &[u8]) -> Option<CompressionMethod> {
+        if (data.len() as u64) < self.store_below {
+            return Some(CompressionMethod::None);
+        }
+
+        let extension = extension_of(path);
+        let selected = extension
+            .as_deref()
+            .and_then(|ext| self.rules.iter().find(|rule| rule.matches(ext)))
+            .map(|rule| rule.compression)
+            .or(self.default_compression);
+
+        if selected == Some(CompressionMethod::None) {
+            return selected;
+        }
+
+        if let Some(threshold) = self.entropy_threshold {
+            if sample_entropy(data, self.sample_size) >= threshold {
+                return Some(CompressionMethod::None);
+            }
+        }
+
+        selected
+    }
+}
+
+/// Estimate the Shannon entropy of `data` in bits per byte.
+///
+/// Large payloads are sampled from evenly spaced windows totalling roughly
+/// `sample_size` bytes so the probe stays cheap for big entries.
+pub fn sample_entropy(data: &[u8], sample_size: usize) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0u64; 256];
+    let mut total = 0u64;
+
+    if sample_size == 0 || data.len() <= sample_size {
+        for &byte in data {
+            counts[byte as usize] += 1;
+        }
+        total = data.len() as u64;
+    } else {
+        let window = (sample_size / SAMPLE_WINDOWS).max(1);
+        let stride = (data.len() - window) / (SAMPLE_WINDOWS - 1);
+        for i in 0..SAMPLE_WINDOWS {
+            let start = i * stride;
+            for &byte in &data[start..start + window] {
+                counts[byte as usize] += 1;
+            }
+            total += window as u64;
+        }
+    }
+
+    let total = total as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn extension_of(path: &str) -> Option<String> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => Some(ext.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+fn normalize_extension(ext: &str) -> String {
+    ext.trim_start_matches('.').to_ascii_lowercase()
+}
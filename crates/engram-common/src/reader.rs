@@ -0,0 +1,478 @@
+//! Archive reader that reverses binding-side encodings on top of
+//! `engram_core::ArchiveReader`.
+
+use engram_core::{ArchiveReader, CompressionMethod};
+
+use crate::codec;
+use crate::encryption::EncryptionKey;
+use crate::error::{Error, Result};
+use crate::index::{self, ArchiveIndex, IndexEntry};
+use crate::limits::Limits;
+use crate::memory::MemoryFile;
+use crate::signature::{self, ArchiveSignature};
+
+/// Keys, signature requirements and resource limits used when an archive is
+/// opened.
+///
+/// A signature present in the archive is always verified. `trusted_keys`
+/// additionally restricts who may have produced it.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    /// Ed25519 public keys accepted as signers; empty accepts any signer.
+    pub trusted_keys: Vec<[u8; 32]>,
+    /// Reject archives without a signature. Anyone can sign an archive with
+    /// a key of their own, so this needs at least one entry in
+    /// `trusted_keys`.
+    pub require_signature: bool,
+    /// Keyring for encrypted entries.
+    pub keys: Vec<EncryptionKey>,
+    /// Limits for archives from untrusted sources.
+    pub limits: Limits,
+}
+
+/// Metadata for a single archive entry as seen through the bindings.
+#[derive(Debug, Clone)]
+pub struct EntryInfo {
+    pub path: String,
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    pub compression: CompressionMethod,
+    /// Explicit compression level, when the entry was written with one.
+    pub compression_level: Option<i32>,
+    /// Whether the entry was compressed with the archive's zstd dictionary.
+    pub dictionary: bool,
+    /// Whether the entry is encrypted.
+    pub encrypted: bool,
+    /// Entry whose stored data this entry shares, for deduplicated entries.
+    pub alias_of: Option<String>,
+    pub modified_time: u64,
+    pub crc32: u32,
+    /// Hex-encoded SHA-256 of the original payload, when the writer recorded
+    /// one.
+    pub sha256: Option<String>,
+}
+
+/// Compressed bytes of an entry as stored, for callers that can hand the
+/// compressed form on without decoding it.
+#[derive(Debug, Clone)]
+pub struct StoredEntry {
+    pub codec: codec::Codec,
+    pub data: Vec<u8>,
+    /// Size of the decoded payload.
+    pub size: u64,
+    /// CRC-32 of the decoded payload.
+    pub crc32: u32,
+}
+
+/// Archive reader used by the bindings.
+pub struct Archive {
+    reader: ArchiveReader,
+    path: String,
+    index: ArchiveIndex,
+    files: Vec<String>,
+    dictionary: Option<Vec<u8>>,
+    signer: Option<[u8; 32]>,
+    keys: Vec<EncryptionKey>,
+    limits: Limits,
+    /// Uncompressed bytes read so far, for `Limits::max_total_bytes`.
+    bytes_read: u64,
+    /// Backing memory of archives opened from a buffer. Declared last so it
+    /// outlives the reader.
+    memory: Option<MemoryFile>,
+}
+
+impl Archive {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::open_with(path, &OpenOptions::default())
+    }
+
+    /// Open an archive and check its signature and limits against `options`.
+    pub fn open_with(path: &str, options: &OpenOptions) -> Result<Self> {
+        if options.require_signature && options.trusted_keys.is_empty() {
+            return Err(Error::InvalidInput(
+                "requiring a signature needs at least one trusted key".into(),
+            ));
+        }
+
+        let mut reader = ArchiveReader::open(path).map_err(Error::core)?;
+
+        // Binding metadata is read up front, so it must fit the limits too.
+        for file in reader.list_files().iter().filter(|f| index::is_reserved(f)) {
+            if let Some(entry) = reader.get_entry(file) {
+                options.limits.check_entry(
+                    file,
+                    entry.uncompressed_size,
+                    entry.compressed_size,
+                    0,
+                )?;
+            }
+        }
+
+        let index = index::read_index(&mut reader)?;
+        let signer = verify_signature(&mut reader, &index, options)?;
+        let dictionary = index::read_dictionary(&mut reader, &index)?;
+
+        let files = reader
+            .list_files()
+            .iter()
+            .filter(|file| !index::is_reserved(file))
+            .chain(index.aliases())
+            .cloned()
+            .collect::<Vec<_>>();
+        options.limits.check_listing(files.iter())?;
+
+        Ok(Self {
+            reader,
+            path: path.to_string(),
+            index,
+            files,
+            dictionary,
+            signer,
+            keys: options.keys.clone(),
+            limits: options.limits,
+            bytes_read: 0,
+            memory: None,
+        })
+    }
+
+    /// Open an archive held in memory.
+    ///
+    /// `data` is copied, so every read API including databases keeps working
+    /// after the caller's buffer is gone.
+    pub fn open_memory(data: &[u8], options: &OpenOptions) -> Result<Self> {
+        let memory = MemoryFile::new(data)?;
+        let mut archive = Self::open_with(memory.path(), options)?;
+        archive.memory = Some(memory);
+        Ok(archive)
+    }
+
+    /// Whether `path` is stored encrypted.
+    pub fn is_encrypted(&self, path: &str) -> bool {
+        self.index
+            .entries
+            .get(self.resolve(path))
+            .is_some_and(|record| record.encryption.is_some())
+    }
+
+    /// Public key of the verified signer, for signed archives.
+    pub fn signer(&self) -> Option<&[u8; 32]> {
+        self.signer.as_ref()
+    }
+
+    /// Filesystem path the archive was opened from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn list_files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        self.reader
+            .list_prefix(prefix)
+            .into_iter()
+            .filter(|file| !index::is_reserved(file))
+            .chain(
+                self.index
+                    .aliases()
+                    .filter(|alias| alias.starts_with(prefix)),
+            )
+            .cloned()
+            .collect()
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        !index::is_reserved(path)
+            && (self.reader.contains(path) || self.index.alias_target(path).is_some())
+    }
+
+    /// Path whose stored data backs `path`.
+    fn resolve<'a>(&'a self, path: &'a str) -> &'a str {
+        self.index.alias_target(path).unwrap_or(path)
+    }
+
+    pub fn get_entry(&self, path: &str) -> Option<EntryInfo> {
+        if index::is_reserved(path) {
+            return None;
+        }
+
+        let stored = self.resolve(path);
+        let entry = self.reader.get_entry(stored)?;
+        let mut info = EntryInfo {
+            path: path.to_string(),
+            uncompressed_size: entry.uncompressed_size,
+            compressed_size: entry.compressed_size,
+            compression: codec::copy_method(&entry.compression),
+            compression_level: None,
+            dictionary: false,
+            encrypted: false,
+            alias_of: self.index.alias_target(path).map(str::to_string),
+            modified_time: entry.modified_time,
+            crc32: entry.crc32,
+            sha256: None,
+        };
+
+        if let Some(record) = self.index.entries.get(stored) {
+            // Encrypted entries only record the hash of their sealed bytes.
+            if record.encryption.is_none() {
+                info.sha256 = self
+                    .index
+                    .entries
+                    .get(path)
+                    .unwrap_or(record)
+                    .sha256
+                    .clone();
+            }
+            if record.is_encoded() {
+                info.uncompressed_size = record.size;
+                info.crc32 = record.crc32;
+                info.encrypted = record.encryption.is_some();
+            }
+            if let Some(codec) = record.codec {
+                info.compression = codec.compression();
+                info.compression_level = Some(codec.level());
+                info.dictionary = codec.uses_dictionary();
+            }
+        }
+
+        Some(info)
+    }
+
+    /// Limits the archive was opened with.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Check the claimed size of `path` against the limits before reading it.
+    pub(crate) fn check_limits(&self, path: &str) -> Result<()> {
+        let stored = self.resolve(path);
+        let Some(entry) = self.reader.get_entry(stored) else {
+            return Ok(());
+        };
+
+        // engram-core allocates the size in its header, binding codecs the
+        // size in the index.
+        let size = match self.index.entries.get(stored) {
+            Some(record) if record.is_encoded() => entry.uncompressed_size.max(record.size),
+            _ => entry.uncompressed_size,
+        };
+        self.limits
+            .check_entry(path, size, entry.compressed_size, self.bytes_read)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        if index::is_reserved(path) {
+            return Err(Error::NotFound(path.to_string()));
+        }
+
+        self.check_limits(path)?;
+        let stored = self.resolve(path).to_string();
+        let data = self.reader.read_file(&stored).map_err(Error::core)?;
+        let Some(record) = self.index.entries.get(&stored) else {
+            self.bytes_read += data.len() as u64;
+            return Ok(data);
+        };
+
+        // An alias is checked against its own record, so repointing it at
+        // another entry cannot go unnoticed.
+        let expected = self.index.entries.get(path).unwrap_or(record);
+        let data = match &record.encryption {
+            // The record of an encrypted entry hashes its sealed bytes; the
+            // cipher authenticates the payload.
+            Some(encryption) => {
+                check_sha256(expected, &stored, path, &data)?;
+                let (size, encoded) =
+                    encryption.decrypt(&stored, record.codec, &data, &self.keys)?;
+                self.limits
+                    .check_entry(path, size, data.len() as u64, self.bytes_read)?;
+                match record.codec {
+                    Some(codec) => {
+                        codec.decode(&encoded, size as usize, self.dictionary.as_deref())?
+                    }
+                    None => encoded,
+                }
+            }
+            None => {
+                let data = match record.codec {
+                    Some(codec) => {
+                        let decoded = codec.decode(
+                            &data,
+                            record.size as usize,
+                            self.dictionary.as_deref(),
+                        )?;
+                        if crc32fast::hash(&decoded) != record.crc32 {
+                            return Err(Error::Codec(format!("CRC mismatch for {path}")));
+                        }
+                        decoded
+                    }
+                    None => data,
+                };
+                check_sha256(expected, &stored, path, &data)?;
+                data
+            }
+        };
+
+        self.bytes_read += data.len() as u64;
+        Ok(data)
+    }
+
+    /// Stored bytes of `path` when the bindings compressed it with a codec
+    /// that needs nothing from the archive to decode: zstd without the
+    /// dictionary, or deflate. Returns `None` for other entries, including
+    /// encrypted ones.
+    ///
+    /// engram-core checks the stored bytes, but the decoded payload is not
+    /// checked against its hash, since it is never decoded here.
+    pub fn read_stored(&mut self, path: &str) -> Result<Option<StoredEntry>> {
+        if index::is_reserved(path) {
+            return Err(Error::NotFound(path.to_string()));
+        }
+
+        let stored = self.resolve(path).to_string();
+        let Some(record) = self.index.entries.get(&stored) else {
+            return Ok(None);
+        };
+        let (Some(codec), None) = (record.codec, &record.encryption) else {
+            return Ok(None);
+        };
+        if codec.uses_dictionary() {
+            return Ok(None);
+        }
+        let (size, crc32) = (record.size, record.crc32);
+
+        self.check_limits(path)?;
+        let data = self.reader.read_file(&stored).map_err(Error::core)?;
+        self.bytes_read += size;
+        Ok(Some(StoredEntry {
+            codec,
+            data,
+            size,
+            crc32,
+        }))
+    }
+
+    /// Hex-encoded SHA-256 of `path`.
+    ///
+    /// The entry is read in full, so a recorded hash is verified before it is
+    /// returned. Entries written without one are hashed on the fly.
+    pub fn hash_file(&mut self, path: &str) -> Result<String> {
+        let data = self.read_file(path)?;
+        match self.get_entry(path).and_then(|entry| entry.sha256) {
+            Some(sha256) => Ok(sha256),
+            None => Ok(hex::encode(index::entry_digest(self.resolve(path), &data)?)),
+        }
+    }
+
+    pub fn read_manifest(&mut self) -> Result<Option<serde_json::Value>> {
+        self.check_limits(index::MANIFEST_PATH)?;
+        let manifest = self.reader.read_manifest().map_err(Error::core)?;
+        if let Some(entry) = self.reader.get_entry(index::MANIFEST_PATH) {
+            self.bytes_read += entry.uncompressed_size;
+        }
+        let expected = self
+            .index
+            .entries
+            .get(index::MANIFEST_PATH)
+            .and_then(|record| record.sha256.as_ref());
+
+        if let (Some(manifest), Some(expected)) = (&manifest, expected) {
+            if hex::encode(index::manifest_digest(manifest)) != *expected {
+                return Err(Error::Integrity(format!(
+                    "SHA-256 mismatch for {}",
+                    index::MANIFEST_PATH
+                )));
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Check `data`, read for `path` from `stored`, against the hash in `record`.
+fn check_sha256(record: &IndexEntry, stored: &str, path: &str, data: &[u8]) -> Result<()> {
+    if let Some(expected) = &record.sha256 {
+        if hex::encode(index::entry_digest(stored, data)?) != *expected {
+            return Err(Error::Integrity(format!("SHA-256 mismatch for {path}")));
+        }
+    }
+    Ok(())
+}
+
+/// Verify the archive signature, returning the signer's public key.
+fn verify_signature(
+    reader: &mut ArchiveReader,
+    index: &ArchiveIndex,
+    options: &OpenOptions,
+) -> Result<Option<[u8; 32]>> {
+    if !reader.contains(signature::SIGNATURE_PATH) {
+        if options.require_signature {
+            return Err(Error::Signature("archive is not signed".into()));
+        }
+        return Ok(None);
+    }
+
+    let data = reader
+        .read_file(signature::SIGNATURE_PATH)
+        .map_err(Error::core)?;
+    let archive_signature: ArchiveSignature = serde_json::from_slice(&data)
+        .map_err(|e| Error::Signature(format!("malformed signature: {e}")))?;
+    let signer = signature::verify(&archive_signature, index)?;
+
+    if !options.trusted_keys.is_empty() && !options.trusted_keys.contains(&signer) {
+        return Err(Error::Signature(format!(
+            "archive signed by untrusted key {}",
+            hex::encode(signer)
+        )));
+    }
+
+    check_directory(reader, index)?;
+    Ok(Some(signer))
+}
+
+/// Check that engram-core's directory holds exactly the entries of a signed
+/// index, stored the way the index says.
+fn check_directory(reader: &ArchiveReader, index: &ArchiveIndex) -> Result<()> {
+    // Entries missing from the index would escape the signature.
+    if let Some(unsigned) = reader.list_files().iter().find(|file| {
+        !index::is_reserved(file)
+            && index
+                .entries
+                .get(*file)
+                .is_none_or(|record| record.sha256.is_none())
+    }) {
+        return Err(Error::Signature(format!(
+            "entry is not covered by the signature: {unsigned}"
+        )));
+    }
+
+    for (path, record) in &index.entries {
+        let mismatch = match (&record.alias_of, reader.get_entry(path)) {
+            (Some(target), None) => index
+                .entries
+                .get(target)
+                .is_none_or(|target| target.alias_of.is_some()),
+            (Some(_), Some(_)) | (None, None) => true,
+            (None, Some(entry)) if record.is_encoded() => {
+                !matches!(entry.compression, CompressionMethod::None)
+                    || (record.encryption.is_some() && entry.uncompressed_size != record.size)
+            }
+            // The manifest is signed in its compact form, which engram-core
+            // need not store.
+            (None, Some(entry)) => {
+                path != index::MANIFEST_PATH && entry.uncompressed_size != record.size
+            }
+        };
+        if mismatch {
+            return Err(Error::Signature(format!(
+                "archive directory does not match the signed index: {path}"
+            )));
+        }
+    }
+
+    Ok(())
+}
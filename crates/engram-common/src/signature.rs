@@ -0,0 +1,207 @@
+//! Ed25519 signatures over the archive index.
+//!
+//! The signature covers the index version and the full index record of every
+//! entry: size, CRC-32, SHA-256, codec, encryption and alias target. Readers
+//! verify it when an archive is opened, check that engram-core's directory
+//! agrees with the signed records, and check each entry's hash as it is read,
+//! so a valid signature vouches for every byte an archive returns.
+//!
+//! engram-core assigns modification times, compressed sizes and its own
+//! compression method only after the index has been signed, so those
+//! directory fields are not part of the message. They cannot change what a
+//! reader returns without failing the hash check.
+
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::index::{self, ArchiveIndex};
+
+/// Path of the detached signature.
+pub const SIGNATURE_PATH: &str = ".engram/signature.json";
+
+/// Only supported signature algorithm.
+const ALGORITHM: &str = "ed25519";
+
+/// Prefix of the signed message, so signatures cannot be replayed elsewhere.
+const DOMAIN: &[u8] = b"engram-signature-v2\n";
+
+/// Signature stored in an archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveSignature {
+    pub algorithm: String,
+    /// Hex-encoded Ed25519 public key of the signer.
+    pub public_key: String,
+    /// Hex-encoded Ed25519 signature.
+    pub signature: String,
+}
+
+/// Build a signing key from a 32-byte Ed25519 secret key.
+pub fn signing_key(secret_key: &[u8]) -> Result<SigningKey> {
+    let bytes: [u8; 32] = secret_key.try_into().map_err(|_| {
+        Error::InvalidInput(format!(
+            "Ed25519 secret keys are 32 bytes, got {}",
+            secret_key.len()
+        ))
+    })?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Parse a 32-byte Ed25519 public key.
+pub fn public_key(bytes: &[u8]) -> Result<[u8; 32]> {
+    bytes.try_into().map_err(|_| {
+        Error::InvalidInput(format!(
+            "Ed25519 public keys are 32 bytes, got {}",
+            bytes.len()
+        ))
+    })
+}
+
+/// Sign the entries recorded in `index`.
+pub fn sign(index: &ArchiveIndex, key: &SigningKey) -> Result<ArchiveSignature> {
+    let signature = key.sign(&message(index)?);
+    Ok(ArchiveSignature {
+        algorithm: ALGORITHM.to_string(),
+        public_key: hex::encode(key.verifying_key().as_bytes()),
+        signature: hex::encode(signature.to_bytes()),
+    })
+}
+
+/// Verify `signature` over `index`, returning the signer's public key.
+pub fn verify(signature: &ArchiveSignature, index: &ArchiveIndex) -> Result<[u8; 32]> {
+    if signature.algorithm != ALGORITHM {
+        return Err(Error::Signature(format!(
+            "unsupported algorithm: {}",
+            signature.algorithm
+        )));
+    }
+
+    let key_bytes = decode::<32>(&signature.public_key, "public key")?;
+    let signature_bytes = decode::<64>(&signature.signature, "signature")?;
+    let key = VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|_| Error::Signature("malformed public key".into()))?;
+
+    key.verify_strict(
+        &message(index)?,
+        &ed25519_dalek::Signature::from_bytes(&signature_bytes),
+    )
+    .map_err(|_| Error::Signature("signature does not match the archive contents".into()))?;
+
+    Ok(key_bytes)
+}
+
+/// Canonical message covering every entry in the index.
+///
+/// Records are serialized in path order with their fields in declaration
+/// order, so the same index always yields the same message.
+fn message(index: &ArchiveIndex) -> Result<Vec<u8>> {
+    let mut entries = Vec::with_capacity(index.entries.len());
+    for (path, entry) in &index.entries {
+        if index::is_reserved(path) {
+            continue;
+        }
+        if entry.sha256.is_none() {
+            return Err(Error::Signature(format!("entry has no hash: {path}")));
+        }
+        entries.push((path, entry));
+    }
+
+    let mut message = DOMAIN.to_vec();
+    message.extend(serde_json::to_vec(&(index.version, entries))?);
+    Ok(message)
+}
+
+fn decode<const N: usize>(value: &str, what: &str) -> Result<[u8; N]> {
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::Signature(format!("malformed {what}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexEntry, INDEX_PATH};
+
+    fn signed_index() -> (ArchiveIndex, ArchiveSignature) {
+        let mut index = ArchiveIndex::default();
+        for (path, data) in [("a.txt", &b"alpha"[..]), ("b.txt", &b"beta"[..])] {
+            let entry = IndexEntry::new(data, &index::entry_digest(path, data).unwrap());
+            index.entries.insert(path.to_string(), entry);
+        }
+        let signature = sign(&index, &signing_key(&[7; 32]).unwrap()).unwrap();
+        (index, signature)
+    }
+
+    #[test]
+    fn verifies_untampered_index() {
+        let (index, signature) = signed_index();
+        let key = signing_key(&[7; 32]).unwrap();
+
+        assert_eq!(
+            verify(&signature, &index).unwrap(),
+            *key.verifying_key().as_bytes()
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_records() {
+        let (index, signature) = signed_index();
+
+        let mut tampered = index.clone();
+        tampered.entries.get_mut("a.txt").unwrap().sha256 = Some(hex::encode([0; 32]));
+        assert!(matches!(
+            verify(&signature, &tampered),
+            Err(Error::Signature(_))
+        ));
+
+        let mut tampered = index.clone();
+        tampered.entries.get_mut("a.txt").unwrap().alias_of = Some("b.txt".into());
+        assert!(verify(&signature, &tampered).is_err());
+
+        let mut tampered = index.clone();
+        tampered.entries.remove("b.txt");
+        assert!(verify(&signature, &tampered).is_err());
+
+        let mut tampered = index;
+        tampered.version += 1;
+        assert!(verify(&signature, &tampered).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_signatures() {
+        let (index, signature) = signed_index();
+
+        let other = sign(&index, &signing_key(&[8; 32]).unwrap()).unwrap();
+        let mut tampered = signature.clone();
+        tampered.public_key = other.public_key;
+        assert!(verify(&tampered, &index).is_err());
+
+        let mut bytes = hex::decode(&signature.signature).unwrap();
+        bytes[0] ^= 1;
+        let mut tampered = signature.clone();
+        tampered.signature = hex::encode(bytes);
+        assert!(verify(&tampered, &index).is_err());
+
+        let mut tampered = signature.clone();
+        tampered.signature.pop();
+        assert!(verify(&tampered, &index).is_err());
+
+        let mut tampered = signature;
+        tampered.algorithm = "rsa".into();
+        assert!(verify(&tampered, &index).is_err());
+    }
+
+    #[test]
+    fn ignores_reserved_entries_and_requires_hashes() {
+        let (mut index, signature) = signed_index();
+        index
+            .entries
+            .insert(INDEX_PATH.to_string(), IndexEntry::new(b"{}", &[0; 32]));
+        assert!(verify(&signature, &index).is_ok());
+
+        index.entries.get_mut("a.txt").unwrap().sha256 = None;
+        assert!(sign(&index, &signing_key(&[7; 32]).unwrap()).is_err());
+    }
+}
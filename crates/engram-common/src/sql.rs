@@ -0,0 +1,394 @@
+//! SQL access to the archive a database was opened from.
+//!
+//! [`register`] adds an `engram_files` table to a connection, listing the
+//! entries of the archive (or overlay) with their metadata. Its `data` column
+//! is only read for rows that select it, so metadata queries and joins stay
+//! cheap. An equality constraint on `path` is answered with a single lookup.
+//!
+//! It also adds scalar functions that resolve a path stored in the database:
+//! `engram_read(path)` returns the entry as a BLOB, `engram_read_text(path)`
+//! as UTF-8 text, `engram_json(path, json_path)` the value at `json_path` in
+//! a JSON entry, and `engram_exists(path)` whether the entry exists. The
+//! readers return NULL for a NULL or missing path.
+
+use std::cell::RefCell;
+use std::os::raw::c_int;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::functions::{Context as FunctionContext, FunctionFlags};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::vtab::{
+    eponymous_only_module, Context, IndexConstraintOp, IndexInfo, VTab, VTabConnection, VTabCursor,
+    Values,
+};
+use rusqlite::Connection;
+use serde_json::Value;
+
+use crate::codec;
+use crate::error::{Error, Result};
+use crate::overlay::Overlay;
+use crate::reader::{Archive, EntryInfo};
+
+/// Name of the table listing the archive's entries.
+pub const FILES_TABLE: &str = "engram_files";
+
+const COLUMN_PATH: c_int = 0;
+const COLUMN_SIZE: c_int = 1;
+const COLUMN_COMPRESSED_SIZE: c_int = 2;
+const COLUMN_COMPRESSION: c_int = 3;
+const COLUMN_MTIME: c_int = 4;
+const COLUMN_CRC32: c_int = 5;
+const COLUMN_DATA: c_int = 6;
+
+/// Plan chosen by `best_index` when the query fixes the path.
+const PLAN_PATH_EQ: c_int = 1;
+
+/// Entries that SQL functions and tables read from.
+///
+/// Implemented for the shared archives and overlays the bindings hold, so the
+/// connection can outlive the call that opened it.
+pub trait EntrySource: Send + Sync {
+    fn list_files(&self) -> Result<Vec<String>>;
+    fn get_entry(&self, path: &str) -> Result<Option<EntryInfo>>;
+    fn read_file(&self, path: &str) -> Result<Vec<u8>>;
+}
+
+impl EntrySource for Mutex<Archive> {
+    fn list_files(&self) -> Result<Vec<String>> {
+        Ok(self.lock().unwrap().list_files().to_vec())
+    }
+
+    fn get_entry(&self, path: &str) -> Result<Option<EntryInfo>> {
+        Ok(self.lock().unwrap().get_entry(path))
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.lock().unwrap().read_file(path)
+    }
+}
+
+impl EntrySource for Mutex<Overlay> {
+    fn list_files(&self) -> Result<Vec<String>> {
+        Ok(self.lock().unwrap().list_files())
+    }
+
+    fn get_entry(&self, path: &str) -> Result<Option<EntryInfo>> {
+        Ok(self.lock().unwrap().get_entry(path))
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.lock().unwrap().read_file(path)
+    }
+}
+
+/// Expose the entries of `source` to queries on `conn`, through the
+/// `engram_files` table and the `engram_*` functions.
+pub fn register(conn: &Connection, source: Arc<dyn EntrySource>) -> Result<()> {
+    conn.create_module(
+        FILES_TABLE,
+        eponymous_only_module::<FilesTable>(),
+        Some(Arc::clone(&source)),
+    )
+    .map_err(|e| Error::Database(e.to_string()))?;
+    register_functions(conn, source).map_err(|e| Error::Database(e.to_string()))
+}
+
+thread_local! {
+    /// Archive error behind the last failure of an `engram_*` function or
+    /// table on this thread. SQLite only passes the message on, so callers
+    /// recover the error itself through [`function_error`].
+    static LAST_ERROR: RefCell<Option<Error>> = const { RefCell::new(None) };
+}
+
+fn sql_error(err: Error) -> rusqlite::Error {
+    let message = err.to_string();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(err));
+    rusqlite::Error::ModuleError(message)
+}
+
+/// The archive error that made a statement fail with `err`, if an
+/// `engram_*` function or table raised it, such as an exceeded limit or a
+/// failed signature check. Statements run on the calling thread, so this
+/// must be called on the thread that ran the statement.
+pub fn function_error(err: &rusqlite::Error) -> Option<Error> {
+    let last = LAST_ERROR.with(|last| last.borrow_mut().take())?;
+    err.to_string().contains(&last.to_string()).then_some(last)
+}
+
+fn register_functions(conn: &Connection, source: Arc<dyn EntrySource>) -> rusqlite::Result<()> {
+    // The archive cannot change under an open connection, so the functions
+    // return the same result for the same path.
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+
+    let reader = AssertUnwindSafe(Arc::clone(&source));
+    conn.create_scalar_function("engram_read", 1, flags, move |ctx| {
+        Ok(read_entry(&**reader, ctx)?.map(|(_, data)| data))
+    })?;
+
+    let reader = AssertUnwindSafe(Arc::clone(&source));
+    conn.create_scalar_function("engram_read_text", 1, flags, move |ctx| {
+        read_entry(&**reader, ctx)?
+            .map(|(path, data)| {
+                String::from_utf8(data).map_err(|_| {
+                    rusqlite::Error::UserFunctionError(format!("{path} is not valid UTF-8").into())
+                })
+            })
+            .transpose()
+    })?;
+
+    for n_arg in [1, 2] {
+        let reader = AssertUnwindSafe(Arc::clone(&source));
+        conn.create_scalar_function("engram_json", n_arg, flags, move |ctx| {
+            let Some((entry, data)) = read_entry(&**reader, ctx)? else {
+                return Ok(SqlValue::Null);
+            };
+            let document: Value = serde_json::from_slice(&data).map_err(|e| {
+                rusqlite::Error::UserFunctionError(format!("{entry} is not valid JSON: {e}").into())
+            })?;
+            let path = if ctx.len() > 1 {
+                ctx.get::<Option<String>>(1)?
+            } else {
+                Some("$".to_string())
+            };
+            let Some(path) = path else {
+                return Ok(SqlValue::Null);
+            };
+            let value = json_path(&document, &path)
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            Ok(value.map_or(SqlValue::Null, json_to_sql))
+        })?;
+    }
+
+    let reader = AssertUnwindSafe(source);
+    conn.create_scalar_function("engram_exists", 1, flags, move |ctx| {
+        match ctx.get::<Option<String>>(0)? {
+            Some(path) => Ok(reader.get_entry(&path).map_err(sql_error)?.is_some()),
+            None => Ok(false),
+        }
+    })
+}
+
+/// Path and content of the entry named by the first argument, or `None` when
+/// the argument is NULL or no such entry exists.
+fn read_entry(
+    source: &dyn EntrySource,
+    ctx: &FunctionContext<'_>,
+) -> rusqlite::Result<Option<(String, Vec<u8>)>> {
+    let Some(path) = ctx.get::<Option<String>>(0)? else {
+        return Ok(None);
+    };
+    if source.get_entry(&path).map_err(sql_error)?.is_none() {
+        return Ok(None);
+    }
+    let data = source.read_file(&path).map_err(sql_error)?;
+    Ok(Some((path, data)))
+}
+
+/// Value at `path` in `document`, using SQLite's JSON path syntax: `$`
+/// followed by `.key`, `."quoted key"`, `[index]` or `[#-n]` steps.
+fn json_path<'a>(
+    document: &'a Value,
+    path: &str,
+) -> std::result::Result<Option<&'a Value>, String> {
+    let bad_path = || format!("bad JSON path: {path}");
+    let mut rest = path.strip_prefix('$').ok_or_else(bad_path)?;
+    let mut value = document;
+
+    while !rest.is_empty() {
+        let next = if let Some(step) = rest.strip_prefix('.') {
+            let (key, after) = if let Some(quoted) = step.strip_prefix('"') {
+                let end = quoted.find('"').ok_or_else(bad_path)?;
+                (&quoted[..end], &quoted[end + 1..])
+            } else {
+                let end = step.find(['.', '[']).unwrap_or(step.len());
+                (&step[..end], &step[end..])
+            };
+            if key.is_empty() && !step.starts_with('"') {
+                return Err(bad_path());
+            }
+            rest = after;
+            value.as_object().and_then(|object| object.get(key))
+        } else if let Some(step) = rest.strip_prefix('[') {
+            let end = step.find(']').ok_or_else(bad_path)?;
+            let index = &step[..end];
+            rest = &step[end + 1..];
+            let Some(array) = value.as_array() else {
+                return Ok(None);
+            };
+            let position = match index.strip_prefix("#-") {
+                Some(back) => {
+                    let back: usize = back.parse().map_err(|_| bad_path())?;
+                    array.len().checked_sub(back)
+                }
+                None if index == "#" => Some(array.len()),
+                None => Some(index.parse().map_err(|_| bad_path())?),
+            };
+            position.and_then(|position| array.get(position))
+        } else {
+            return Err(bad_path());
+        };
+
+        match next {
+            Some(next) => value = next,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// SQL value for a JSON value, as `json_extract` returns it: scalars as SQL
+/// values and objects and arrays as JSON text.
+fn json_to_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        Value::Array(_) | Value::Object(_) => SqlValue::Text(value.to_string()),
+    }
+}
+
+#[repr(C)]
+struct FilesTable {
+    /// Base class, which SQLite requires to come first.
+    base: rusqlite::vtab::sqlite3_vtab,
+    source: Arc<dyn EntrySource>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for FilesTable {
+    type Aux = Arc<dyn EntrySource>;
+    type Cursor = FilesCursor;
+
+    fn connect(
+        _db: &mut VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> rusqlite::Result<(String, Self)> {
+        let source = aux
+            .cloned()
+            .ok_or_else(|| rusqlite::Error::ModuleError("no archive for engram_files".into()))?;
+        let schema = "CREATE TABLE x(path TEXT, size INTEGER, compressed_size INTEGER, \
+                      compression TEXT, mtime INTEGER, crc32 INTEGER, data BLOB)";
+        let table = FilesTable {
+            base: rusqlite::vtab::sqlite3_vtab::default(),
+            source,
+        };
+        Ok((schema.to_string(), table))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> rusqlite::Result<()> {
+        let path_eq = info.constraints().position(|constraint| {
+            constraint.is_usable()
+                && constraint.column() == COLUMN_PATH
+                && constraint.operator() == IndexConstraintOp::SQLITE_INDEX_CONSTRAINT_EQ
+        });
+
+        match path_eq {
+            Some(position) => {
+                let mut usage = info.constraint_usage(position);
+                usage.set_argv_index(1);
+                usage.set_omit(true);
+                info.set_idx_num(PLAN_PATH_EQ);
+                info.set_estimated_cost(1.0);
+                info.set_estimated_rows(1);
+            }
+            None => {
+                info.set_idx_num(0);
+                info.set_estimated_cost(1_000_000.0);
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> rusqlite::Result<FilesCursor> {
+        Ok(FilesCursor {
+            base: rusqlite::vtab::sqlite3_vtab_cursor::default(),
+            source: Arc::clone(&self.source),
+            entries: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+#[repr(C)]
+struct FilesCursor {
+    /// Base class, which SQLite requires to come first.
+    base: rusqlite::vtab::sqlite3_vtab_cursor,
+    source: Arc<dyn EntrySource>,
+    /// Entries matched by the current scan, read when it starts.
+    entries: Vec<EntryInfo>,
+    position: usize,
+}
+
+impl FilesCursor {
+    fn current(&self) -> rusqlite::Result<&EntryInfo> {
+        self.entries
+            .get(self.position)
+            .ok_or_else(|| rusqlite::Error::ModuleError("engram_files cursor past the end".into()))
+    }
+}
+
+unsafe impl VTabCursor for FilesCursor {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &Values<'_>,
+    ) -> rusqlite::Result<()> {
+        self.position = 0;
+        self.entries = if idx_num == PLAN_PATH_EQ {
+            // A non-text path, including NULL, matches nothing.
+            match args.get::<Option<String>>(0) {
+                Ok(Some(path)) => self.source.get_entry(&path).map_err(sql_error)?,
+                _ => None,
+            }
+            .into_iter()
+            .collect()
+        } else {
+            let mut entries = Vec::new();
+            for path in self.source.list_files().map_err(sql_error)? {
+                if let Some(entry) = self.source.get_entry(&path).map_err(sql_error)? {
+                    entries.push(entry);
+                }
+            }
+            entries
+        };
+        Ok(())
+    }
+
+    fn next(&mut self) -> rusqlite::Result<()> {
+        self.position += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.position >= self.entries.len()
+    }
+
+    fn column(&self, ctx: &mut Context, i: c_int) -> rusqlite::Result<()> {
+        let entry = self.current()?;
+        match i {
+            COLUMN_PATH => ctx.set_result(&entry.path),
+            COLUMN_SIZE => ctx.set_result(&(entry.uncompressed_size as i64)),
+            COLUMN_COMPRESSED_SIZE => ctx.set_result(&(entry.compressed_size as i64)),
+            COLUMN_COMPRESSION => ctx.set_result(&codec::method_name(&entry.compression)),
+            COLUMN_MTIME => ctx.set_result(&(entry.modified_time as i64)),
+            COLUMN_CRC32 => ctx.set_result(&entry.crc32),
+            COLUMN_DATA => {
+                let data = self.source.read_file(&entry.path).map_err(sql_error)?;
+                ctx.set_result(&data)
+            }
+            _ => Err(rusqlite::Error::ModuleError(format!(
+                "engram_files has no column {i}"
+            ))),
+        }
+    }
+
+    fn rowid(&self) -> rusqlite::Result<i64> {
+        Ok(self.position as i64 + 1)
+    }
+}
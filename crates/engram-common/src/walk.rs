@@ -0,0 +1,61 @@
+//! Directory traversal for packing a directory tree into an archive.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Collect every regular file below `root`, paired with the archive path it
+/// should be stored under.
+///
+/// Archive paths use forward slashes, are joined onto `prefix` and are
+/// returned sorted so packing the same tree always yields the same archive.
+/// Symlinks to files are packed as the file they point at; symlinks to
+/// directories are skipped so a link back up the tree cannot loop forever.
+pub fn collect_files(root: &Path, prefix: &str) -> io::Result<Vec<(String, PathBuf)>> {
+    let prefix = prefix.trim_matches('/');
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let mut metadata = fs::symlink_metadata(&path)?;
+            if metadata.file_type().is_symlink() {
+                metadata = fs::metadata(&path)?;
+                if metadata.is_dir() {
+                    continue;
+                }
+            }
+
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.is_file() {
+                let relative = path.strip_prefix(root).map_err(io::Error::other)?;
+                files.push((archive_path(prefix, relative)?, path));
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+fn archive_path(prefix: &str, relative: &Path) -> io::Result<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    if !prefix.is_empty() {
+        parts.push(prefix);
+    }
+
+    for component in relative.components() {
+        let part = component.as_os_str().to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("path is not valid UTF-8: {}", relative.display()),
+            )
+        })?;
+        parts.push(part);
+    }
+
+    Ok(parts.join("/"))
+}
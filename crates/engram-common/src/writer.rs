@@ -0,0 +1,910 @@
+//! Archive writer that layers compression policies, levels and zstd
+//! dictionaries over `engram_core::ArchiveWriter`.
+//!
+//! Archives are written to a temporary sibling file and only renamed to their
+//! target path by [`Writer::finalize`], so an interrupted or abandoned write
+//! never leaves a truncated archive behind.
+//!
+//! [`Writer::open_for_update`] starts from an existing archive instead: entries
+//! that are not replaced or deleted are carried over when the writer is
+//! finalized, and the result replaces the original the same way.
+//!
+//! [`Writer::create_in_memory`] writes to a [`MemoryFile`] and hands the
+//! finished archive back as bytes from [`Writer::finalize_to_bytes`].
+//! engram-core only writes to paths, so the bytes become available once the
+//! archive is complete rather than while entries are added.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use ed25519_dalek::SigningKey;
+use engram_core::{ArchiveReader, ArchiveWriter, CompressionMethod};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::PendingFile;
+use crate::codec::{self, Codec, DEFAULT_DEFLATE_LEVEL, DEFAULT_ZSTD_LEVEL};
+use crate::encryption::EncryptionOptions;
+use crate::error::{Error, Result};
+use crate::index::{self, ArchiveIndex, IndexEntry};
+use crate::memory::MemoryFile;
+use crate::overlay;
+use crate::policy::CompressionPolicy;
+use crate::signature;
+use crate::walk;
+
+/// Header that starts every SQLite database file.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Fewer samples than this are not worth training a dictionary on.
+const MIN_DICTIONARY_SAMPLES: usize = 8;
+
+/// Settings for training a shared zstd dictionary while writing.
+#[derive(Debug, Clone)]
+pub struct DictionaryOptions {
+    /// Maximum dictionary size in bytes.
+    pub max_size: usize,
+    /// Number of entries collected before the dictionary is trained.
+    pub sample_count: usize,
+    /// Entries larger than this are compressed without the dictionary.
+    pub max_entry_size: usize,
+    /// Lower-case extensions eligible for the dictionary; empty means all.
+    pub extensions: Vec<String>,
+}
+
+impl Default for DictionaryOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 112_640,
+            sample_count: 1_000,
+            max_entry_size: 64 * 1024,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl DictionaryOptions {
+    fn accepts(&self, path: &str, data: &[u8]) -> bool {
+        if data.len() > self.max_entry_size {
+            return false;
+        }
+        if self.extensions.is_empty() {
+            return true;
+        }
+        path.rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .is_some_and(|ext| self.extensions.contains(&ext))
+    }
+}
+
+/// Options controlling how entries are compressed.
+#[derive(Debug, Clone, Default)]
+pub struct WriterOptions {
+    /// Picks a compression method for entries added without one.
+    pub policy: Option<CompressionPolicy>,
+    /// Writer-wide compression level for zstd and deflate entries.
+    pub level: Option<i32>,
+    /// Train and use a shared zstd dictionary for small entries.
+    pub dictionary: Option<DictionaryOptions>,
+    /// Store byte-identical payloads once, with later paths recorded as
+    /// aliases of the first.
+    pub deduplicate: bool,
+    /// Encrypt every entry. Entries are compressed by the bindings before
+    /// encryption, with LZ4 requests served by zstd.
+    pub encryption: Option<EncryptionOptions>,
+}
+
+/// Summary of a finalized archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FinalizeStats {
+    /// Number of entries in the archive, aliases included.
+    pub entries: usize,
+    /// Number of entries stored as aliases of an identical entry.
+    pub deduplicated: usize,
+    /// Uncompressed bytes not stored thanks to deduplication.
+    pub bytes_saved: u64,
+}
+
+enum DictionaryState {
+    Disabled,
+    Collecting(Vec<PendingEntry>),
+    Trained(Vec<u8>),
+    Unavailable,
+}
+
+struct PendingEntry {
+    path: String,
+    data: Vec<u8>,
+    level: Option<i32>,
+}
+
+/// Existing archive whose entries an update carries over.
+struct UpdateBase {
+    reader: ArchiveReader,
+    index: ArchiveIndex,
+    removed: HashSet<String>,
+}
+
+/// Where a [`Writer`] puts the archive.
+#[derive(Debug)]
+enum Target {
+    File(PendingFile),
+    Memory(MemoryFile),
+}
+
+impl Target {
+    fn path(&self) -> &Path {
+        match self {
+            Target::File(file) => file.path(),
+            Target::Memory(memory) => Path::new(memory.path()),
+        }
+    }
+}
+
+/// Archive writer used by the bindings.
+///
+/// Dropping a writer without calling [`finalize`](Writer::finalize) discards
+/// the partial archive.
+pub struct Writer {
+    // Declared before `target` so the archive handle is closed before the
+    // temporary file is removed on drop.
+    inner: ArchiveWriter,
+    target: Target,
+    options: WriterOptions,
+    index: ArchiveIndex,
+    dictionary: DictionaryState,
+    base: Option<UpdateBase>,
+    /// Paths added by this writer, which replace base entries.
+    written: HashSet<String>,
+    /// SHA-256 of every payload added while deduplicating, mapped to the
+    /// path that stores it.
+    digests: HashMap<[u8; 32], String>,
+    signing_key: Option<SigningKey>,
+}
+
+impl Writer {
+    pub fn create(path: impl AsRef<Path>, options: WriterOptions) -> Result<Self> {
+        Self::with_target(Target::File(PendingFile::new(path)), options)
+    }
+
+    /// Create a writer that keeps the archive in memory.
+    ///
+    /// Finish it with [`finalize_to_bytes`](Writer::finalize_to_bytes). Only
+    /// supported on Linux; see [`MemoryFile`].
+    pub fn create_in_memory(options: WriterOptions) -> Result<Self> {
+        Self::with_target(Target::Memory(MemoryFile::new(&[])?), options)
+    }
+
+    fn with_target(target: Target, options: WriterOptions) -> Result<Self> {
+        if options.encryption.is_some() && options.dictionary.is_some() {
+            // The dictionary is trained on entry contents and stored in the
+            // clear.
+            return Err(Error::InvalidInput(
+                "zstd dictionaries cannot be combined with encryption".into(),
+            ));
+        }
+
+        let inner = ArchiveWriter::create(target.path()).map_err(Error::core)?;
+        let dictionary = if options.dictionary.is_some() {
+            DictionaryState::Collecting(Vec::new())
+        } else {
+            DictionaryState::Disabled
+        };
+
+        Ok(Self {
+            inner,
+            target,
+            options,
+            index: ArchiveIndex::default(),
+            dictionary,
+            base: None,
+            written: HashSet::new(),
+            digests: HashMap::new(),
+            signing_key: None,
+        })
+    }
+
+    /// Open an existing archive for update.
+    ///
+    /// Entries that are neither replaced nor deleted are copied into the new
+    /// archive on [`finalize`](Writer::finalize), which then replaces the
+    /// original atomically. Entries encoded by the bindings are copied as
+    /// stored. Entries compressed by the core writer are still recompressed
+    /// with their original method: copying them verbatim needs engram-core
+    /// to take precompressed bytes. Entries are copied in path order. An
+    /// archive written with a zstd dictionary keeps it; when
+    /// `options.dictionary` is set, new eligible entries are compressed with
+    /// it too. Deduplication
+    /// only considers entries added by this writer. The signature is dropped
+    /// unless the writer signs again; see [`base_signed`](Writer::base_signed).
+    pub fn open_for_update(path: impl AsRef<Path>, options: WriterOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let path_str = path.to_str().ok_or_else(|| {
+            Error::InvalidInput(format!("path is not valid UTF-8: {}", path.display()))
+        })?;
+
+        let mut reader = ArchiveReader::open(path_str).map_err(Error::core)?;
+        let base_index = index::read_index(&mut reader)?;
+        let base_dictionary = index::read_dictionary(&mut reader, &base_index)?;
+
+        let mut writer = Self::create(path, options)?;
+        if let Some(dictionary) = base_dictionary {
+            writer.dictionary = DictionaryState::Trained(dictionary);
+        }
+        writer.base = Some(UpdateBase {
+            reader,
+            index: base_index,
+            removed: HashSet::new(),
+        });
+        Ok(writer)
+    }
+
+    /// Whether the writer was created with
+    /// [`create_in_memory`](Writer::create_in_memory) and is finished with
+    /// [`finalize_to_bytes`](Writer::finalize_to_bytes).
+    pub fn is_in_memory(&self) -> bool {
+        matches!(self.target, Target::Memory(_))
+    }
+
+    /// Path the archive is published to by [`finalize`](Writer::finalize), or
+    /// `None` for an in-memory writer.
+    pub fn target(&self) -> Option<&Path> {
+        match &self.target {
+            Target::File(file) => Some(file.target()),
+            Target::Memory(_) => None,
+        }
+    }
+
+    /// Add an entry, letting the compression policy pick the method.
+    pub fn add_file(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let method = self
+            .options
+            .policy
+            .as_ref()
+            .and_then(|policy| policy.choose(path, data));
+        self.add_new(path, data, method, None)
+    }
+
+    /// Add an entry with an explicit method and optional level.
+    pub fn add_file_with_compression(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        method: CompressionMethod,
+        level: Option<i32>,
+    ) -> Result<()> {
+        self.add_new(path, data, Some(method), level)
+    }
+
+    /// Add a file from disk.
+    pub fn add_file_from_disk(&mut self, path: &str, disk_path: &Path) -> Result<()> {
+        if self.options.policy.is_none()
+            && self.options.level.is_none()
+            && self.options.dictionary.is_none()
+            && !self.options.deduplicate
+            && self.options.encryption.is_none()
+        {
+            self.claim_path(path)?;
+            let record = hash_file(disk_path)?;
+            self.inner
+                .add_file_from_disk(path, disk_path)
+                .map_err(Error::core)?;
+            self.index.entries.insert(path.to_string(), record);
+            return Ok(());
+        }
+
+        let data = std::fs::read(disk_path)?;
+        self.add_file(path, &data)
+    }
+
+    /// Add every file below `dir` under `prefix`, returning the number of
+    /// files added.
+    pub fn add_directory(&mut self, dir: &Path, prefix: &str) -> Result<usize> {
+        let files = walk::collect_files(dir, prefix)?;
+        for (path, disk_path) in &files {
+            self.add_file_from_disk(path, disk_path)?;
+        }
+        Ok(files.len())
+    }
+
+    pub fn add_manifest(&mut self, manifest: &serde_json::Value) -> Result<()> {
+        self.claim_path(index::MANIFEST_PATH)?;
+        self.inner.add_manifest(manifest).map_err(Error::core)?;
+        self.index.entries.insert(
+            index::MANIFEST_PATH.to_string(),
+            IndexEntry::new(
+                manifest.to_string().as_bytes(),
+                &index::manifest_digest(manifest),
+            ),
+        );
+        Ok(())
+    }
+
+    /// Sign the archive with an Ed25519 secret key when it is finalized.
+    pub fn sign_with(&mut self, secret_key: &[u8]) -> Result<()> {
+        self.signing_key = Some(signature::signing_key(secret_key)?);
+        Ok(())
+    }
+
+    /// Whether the archive opened for update is signed.
+    ///
+    /// Its signature is never copied, since it would not cover the updated
+    /// index. Call [`sign_with`](Writer::sign_with) to sign the result again.
+    pub fn base_signed(&self) -> bool {
+        self.base
+            .as_ref()
+            .is_some_and(|base| base.reader.contains(signature::SIGNATURE_PATH))
+    }
+
+    /// Delete an entry of the archive being updated.
+    ///
+    /// Returns `false` when the archive has no such entry or it was already
+    /// deleted. Entries added by this writer cannot be deleted again.
+    pub fn delete_file(&mut self, path: &str) -> Result<bool> {
+        if self.written.contains(path) {
+            return Err(Error::InvalidInput(format!(
+                "cannot delete {path}: it was added by this writer"
+            )));
+        }
+
+        let Some(base) = self.base.as_mut() else {
+            return Ok(false);
+        };
+        let exists = base.reader.contains(path) || base.index.alias_target(path).is_some();
+        if index::is_reserved(path) || !exists {
+            return Ok(false);
+        }
+        Ok(base.removed.insert(path.to_string()))
+    }
+
+    /// Add a whiteout entry that hides `path`, and everything below it, in the
+    /// layers under this archive when they are stacked in an
+    /// [`Overlay`](crate::overlay::Overlay).
+    pub fn add_whiteout(&mut self, path: &str) -> Result<()> {
+        if path.trim_end_matches('/').is_empty() {
+            return Err(Error::InvalidInput("whiteout path cannot be empty".into()));
+        }
+        self.add_new(
+            &overlay::whiteout_path(path),
+            &[],
+            Some(CompressionMethod::None),
+            None,
+        )
+    }
+
+    /// Discard the partial archive without touching the target path.
+    pub fn abort(self) {
+        // Field order closes the archive handle before the temporary file is
+        // removed.
+        drop(self);
+    }
+
+    /// Flush pending entries, write the archive index, finish the archive and
+    /// atomically move it to the target path.
+    pub fn finalize(mut self) -> Result<FinalizeStats> {
+        if matches!(self.target, Target::Memory(_)) {
+            return Err(Error::InvalidInput(
+                "in-memory writers are finished with finalize_to_bytes".into(),
+            ));
+        }
+
+        let stats = self.write_trailer()?;
+        self.inner.finalize().map_err(Error::core)?;
+        if let Target::File(file) = self.target {
+            file.commit()?;
+        }
+        Ok(stats)
+    }
+
+    /// Finish an in-memory writer like [`finalize`](Writer::finalize) and
+    /// return the archive bytes.
+    pub fn finalize_to_bytes(mut self) -> Result<(FinalizeStats, Vec<u8>)> {
+        if !matches!(self.target, Target::Memory(_)) {
+            return Err(Error::InvalidInput(
+                "only in-memory writers can be finalized to bytes".into(),
+            ));
+        }
+
+        let stats = self.write_trailer()?;
+        self.inner.finalize().map_err(Error::core)?;
+        match self.target {
+            Target::Memory(memory) => Ok((stats, memory.read_all()?)),
+            Target::File(_) => unreachable!("checked above"),
+        }
+    }
+
+    /// Write the entries deferred to the end of the archive: carried-over
+    /// base entries, the dictionary, the index and the signature.
+    fn write_trailer(&mut self) -> Result<FinalizeStats> {
+        let copied = self.copy_base()?;
+        let stats = FinalizeStats {
+            entries: self.written.len() + copied,
+            deduplicated: self.index.aliases().count(),
+            bytes_saved: self
+                .index
+                .entries
+                .values()
+                .filter(|entry| entry.alias_of.is_some())
+                .map(|entry| entry.size)
+                .sum(),
+        };
+
+        if let DictionaryState::Collecting(pending) =
+            std::mem::replace(&mut self.dictionary, DictionaryState::Unavailable)
+        {
+            self.train_and_flush(pending)?;
+        }
+
+        if let DictionaryState::Trained(dictionary) = &self.dictionary {
+            if self.index.uses_dictionary() {
+                self.inner
+                    .add_file_with_compression(
+                        index::DICTIONARY_PATH,
+                        dictionary,
+                        CompressionMethod::None,
+                    )
+                    .map_err(Error::core)?;
+            }
+        }
+
+        if !self.index.is_empty() {
+            let json = serde_json::to_vec(&self.index)?;
+            self.inner
+                .add_file_with_compression(index::INDEX_PATH, &json, CompressionMethod::Zstd)
+                .map_err(Error::core)?;
+        }
+
+        if let Some(key) = &self.signing_key {
+            let signature = serde_json::to_vec(&signature::sign(&self.index, key)?)?;
+            self.inner
+                .add_file_with_compression(
+                    signature::SIGNATURE_PATH,
+                    &signature,
+                    CompressionMethod::None,
+                )
+                .map_err(Error::core)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Reject reserved paths and paths this writer already added.
+    fn claim_path(&mut self, path: &str) -> Result<()> {
+        if index::is_reserved(path) {
+            return Err(Error::InvalidInput(format!(
+                "paths under {} are reserved: {path}",
+                index::RESERVED_PREFIX
+            )));
+        }
+        if !self.written.insert(path.to_string()) {
+            return Err(Error::InvalidInput(format!("entry already added: {path}")));
+        }
+        Ok(())
+    }
+
+    /// Add a payload under a new path and record its hash, storing it as an
+    /// alias when deduplication finds an identical payload.
+    fn add_new(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        method: Option<CompressionMethod>,
+        level: Option<i32>,
+    ) -> Result<()> {
+        self.claim_path(path)?;
+        let digest: [u8; 32] = Sha256::digest(data).into();
+
+        // Databases are opened by the VFS straight from engram-core, which
+        // knows nothing about aliases.
+        let deduplicate = self.options.deduplicate && !data.starts_with(SQLITE_HEADER);
+        if let Some(target) = self.digests.get(&digest).filter(|_| deduplicate) {
+            // Aliases of encrypted entries take the target's record, which
+            // describes the sealed bytes rather than the payload.
+            let record = match &self.options.encryption {
+                Some(_) => IndexEntry {
+                    alias_of: Some(target.clone()),
+                    ..self.index.entries[target].clone()
+                },
+                None => IndexEntry {
+                    alias_of: Some(target.clone()),
+                    ..IndexEntry::new(data, &digest)
+                },
+            };
+            self.index.entries.insert(path.to_string(), record);
+            return Ok(());
+        }
+
+        self.index
+            .entries
+            .insert(path.to_string(), IndexEntry::new(data, &digest));
+        if let Err(err) = self.add_entry(path, data, method, level) {
+            self.index.entries.remove(path);
+            self.written.remove(path);
+            return Err(err);
+        }
+
+        if deduplicate {
+            self.digests.insert(digest, path.to_string());
+        }
+        Ok(())
+    }
+
+    fn add_entry(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        method: Option<CompressionMethod>,
+        level: Option<i32>,
+    ) -> Result<()> {
+        // Databases must stay readable by the SQLite VFS, which reads entries
+        // straight from engram-core, so they never take a binding codec.
+        // Encrypted databases are loaded into memory instead.
+        let encrypt = self.options.encryption.is_some();
+        if data.starts_with(SQLITE_HEADER) && !encrypt {
+            return self.add_core(path, data, method);
+        }
+
+        let level = level.or(self.options.level);
+        if matches!(method, None | Some(CompressionMethod::Zstd))
+            && self.accepts_dictionary(path, data)
+        {
+            return self.add_with_dictionary(path, data, level);
+        }
+
+        let codec = match (method, level) {
+            (Some(method), Some(level)) => Codec::for_level(method, level)?,
+            (None, Some(level)) => Codec::for_level(CompressionMethod::Zstd, level)?,
+            (_, None) => None,
+        };
+
+        match codec {
+            Some(codec) => self.add_encoded(path, data, Some(codec), None),
+            // engram-core would compress after encryption, to no effect.
+            None if encrypt => {
+                let codec = match method {
+                    Some(CompressionMethod::None) => None,
+                    Some(CompressionMethod::Deflate) => Some(Codec::Deflate {
+                        level: DEFAULT_DEFLATE_LEVEL,
+                    }),
+                    _ => Some(Codec::Zstd {
+                        level: DEFAULT_ZSTD_LEVEL,
+                        dictionary: false,
+                    }),
+                };
+                self.add_encoded(path, data, codec, None)
+            }
+            None => self.add_core(path, data, method),
+        }
+    }
+
+    fn accepts_dictionary(&self, path: &str, data: &[u8]) -> bool {
+        match (&self.dictionary, &self.options.dictionary) {
+            (DictionaryState::Collecting(_) | DictionaryState::Trained(_), Some(options)) => {
+                options.accepts(path, data)
+            }
+            _ => false,
+        }
+    }
+
+    fn add_with_dictionary(&mut self, path: &str, data: &[u8], level: Option<i32>) -> Result<()> {
+        let sample_count = self
+            .options
+            .dictionary
+            .as_ref()
+            .map_or(0, |options| options.sample_count);
+
+        match &mut self.dictionary {
+            DictionaryState::Collecting(pending) => {
+                pending.push(PendingEntry {
+                    path: path.to_string(),
+                    data: data.to_vec(),
+                    level,
+                });
+                if pending.len() >= sample_count {
+                    let pending = std::mem::take(pending);
+                    self.train_and_flush(pending)?;
+                }
+                Ok(())
+            }
+            DictionaryState::Trained(dictionary) => {
+                let dictionary = std::mem::take(dictionary);
+                let codec = Codec::Zstd {
+                    level: level.unwrap_or(DEFAULT_ZSTD_LEVEL),
+                    dictionary: true,
+                };
+                let result = self.add_encoded(path, data, Some(codec), Some(&dictionary));
+                self.dictionary = DictionaryState::Trained(dictionary);
+                result
+            }
+            DictionaryState::Disabled | DictionaryState::Unavailable => {
+                self.add_entry(path, data, Some(CompressionMethod::Zstd), level)
+            }
+        }
+    }
+
+    /// Train the dictionary from the collected entries and write them out.
+    fn train_and_flush(&mut self, pending: Vec<PendingEntry>) -> Result<()> {
+        let max_size = self
+            .options
+            .dictionary
+            .as_ref()
+            .map_or(0, |options| options.max_size);
+
+        let dictionary = if pending.len() >= MIN_DICTIONARY_SAMPLES {
+            let samples: Vec<Vec<u8>> = pending.iter().map(|entry| entry.data.clone()).collect();
+            codec::train_dictionary(&samples, max_size).ok()
+        } else {
+            None
+        };
+
+        match dictionary {
+            Some(dictionary) => {
+                for entry in &pending {
+                    let codec = Codec::Zstd {
+                        level: entry.level.unwrap_or(DEFAULT_ZSTD_LEVEL),
+                        dictionary: true,
+                    };
+                    self.add_encoded(&entry.path, &entry.data, Some(codec), Some(&dictionary))?;
+                }
+                self.dictionary = DictionaryState::Trained(dictionary);
+            }
+            None => {
+                // Too few or too dissimilar samples: fall back to plain zstd.
+                self.dictionary = DictionaryState::Unavailable;
+                for entry in &pending {
+                    self.add_entry(
+                        &entry.path,
+                        &entry.data,
+                        Some(CompressionMethod::Zstd),
+                        entry.level,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add an entry the caller already compressed: `stored` is `data` encoded
+    /// with `codec`, without a dictionary. Saves compressing it a second time
+    /// when the caller needed the compressed size anyway. Entries added this
+    /// way are not deduplicated.
+    pub(crate) fn add_precompressed(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        codec: Codec,
+        stored: Vec<u8>,
+    ) -> Result<()> {
+        self.claim_path(path)?;
+        if let Err(err) = self.add_stored(path, data, Some(codec), stored) {
+            self.written.remove(path);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Store `data` encoded by the bindings: compressed with `codec` and
+    /// encrypted when the writer encrypts.
+    fn add_encoded(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        codec: Option<Codec>,
+        dictionary: Option<&[u8]>,
+    ) -> Result<()> {
+        let stored = match codec {
+            Some(codec) => codec.encode(data, dictionary)?,
+            None => data.to_vec(),
+        };
+        self.add_stored(path, data, codec, stored)
+    }
+
+    /// Store `stored`, the encoding of `data` with `codec`, encrypting it when
+    /// the writer encrypts.
+    fn add_stored(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        codec: Option<Codec>,
+        mut stored: Vec<u8>,
+    ) -> Result<()> {
+        let encryption = match &self.options.encryption {
+            Some(options) => {
+                let (sealed, record) = options.encrypt(path, codec, data.len() as u64, &stored)?;
+                stored = sealed;
+                Some(record)
+            }
+            None => None,
+        };
+
+        self.inner
+            .add_file_with_compression(path, &stored, CompressionMethod::None)
+            .map_err(Error::core)?;
+
+        let record = if encryption.is_some() {
+            IndexEntry::new(&stored, &Sha256::digest(&stored).into())
+        } else {
+            IndexEntry::new(data, &Sha256::digest(data).into())
+        };
+        self.index.entries.insert(
+            path.to_string(),
+            IndexEntry {
+                codec,
+                encryption,
+                ..record
+            },
+        );
+        Ok(())
+    }
+
+    /// Copy the base entries that were neither replaced nor deleted,
+    /// returning how many were carried over.
+    fn copy_base(&mut self) -> Result<usize> {
+        let Some(mut base) = self.base.take() else {
+            return Ok(0);
+        };
+
+        let kept: BTreeSet<String> = base
+            .reader
+            .list_files()
+            .iter()
+            .chain(base.index.aliases())
+            .filter(|path| {
+                !index::is_reserved(path)
+                    && !base.removed.contains(*path)
+                    && !self.written.contains(*path)
+            })
+            .cloned()
+            .collect();
+
+        for path in &kept {
+            match base.index.alias_target(path) {
+                Some(target) if kept.contains(target) => {
+                    let record = base.index.entries[path].clone();
+                    self.index.entries.insert(path.clone(), record);
+                }
+                // The entry holding the shared data is gone, so the alias
+                // takes over a copy of it.
+                Some(target) => {
+                    let target = target.to_string();
+                    self.copy_stored(&mut base, path, &target)?;
+                }
+                None => self.copy_stored(&mut base, path, path)?,
+            }
+        }
+
+        Ok(kept.len())
+    }
+
+    /// Copy the data stored for base entry `stored` to `path`.
+    ///
+    /// Data encoded by the bindings is copied as-is along with its index
+    /// record. The core writer has no way to take already compressed bytes,
+    /// so entries it compressed are decompressed and compressed again with
+    /// their original method; the data is unchanged but the compressed bytes
+    /// and directory record may differ. Entries written before hashes were
+    /// recorded are hashed on the way.
+    fn copy_stored(&mut self, base: &mut UpdateBase, path: &str, stored: &str) -> Result<()> {
+        let data = base.reader.read_file(stored).map_err(Error::core)?;
+        let mut record = match base.index.entries.get(stored) {
+            Some(record) => IndexEntry {
+                alias_of: None,
+                ..record.clone()
+            },
+            None => IndexEntry::new(&data, &index::entry_digest(stored, &data)?),
+        };
+
+        if record.sha256.is_none() {
+            let digest = match record.codec {
+                Some(codec) => {
+                    let dictionary = match &self.dictionary {
+                        DictionaryState::Trained(dictionary) => Some(dictionary.as_slice()),
+                        _ => None,
+                    };
+                    let payload = codec.decode(&data, record.size as usize, dictionary)?;
+                    index::entry_digest(stored, &payload)?
+                }
+                None => index::entry_digest(stored, &data)?,
+            };
+            record.sha256 = Some(hex::encode(digest));
+        }
+
+        // Sealed data is bound to its path, so an alias taking over the data
+        // of a removed entry must be sealed again.
+        let (data, record) = if record.encryption.is_some() && path != stored {
+            self.reseal(path, stored, &data, &record)?
+        } else {
+            (data, record)
+        };
+
+        let method = if record.is_encoded() {
+            CompressionMethod::None
+        } else {
+            base.reader
+                .get_entry(stored)
+                .map_or(CompressionMethod::None, |entry| {
+                    codec::copy_method(&entry.compression)
+                })
+        };
+        self.inner
+            .add_file_with_compression(path, &data, method)
+            .map_err(Error::core)?;
+        self.index.entries.insert(path.to_string(), record);
+        Ok(())
+    }
+
+    /// Seal the data of encrypted base entry `stored` again for `path`, with
+    /// the key of this writer.
+    fn reseal(
+        &self,
+        path: &str,
+        stored: &str,
+        data: &[u8],
+        record: &IndexEntry,
+    ) -> Result<(Vec<u8>, IndexEntry)> {
+        let (Some(options), Some(encryption)) = (&self.options.encryption, &record.encryption)
+        else {
+            return Err(Error::Encryption(format!(
+                "{path} shares encrypted data with {stored}, which is being removed; \
+                 update the archive with its encryption key"
+            )));
+        };
+
+        let (size, encoded) = encryption.decrypt(
+            stored,
+            record.codec,
+            data,
+            std::slice::from_ref(&options.key),
+        )?;
+        let (sealed, encryption) = options.encrypt(path, record.codec, size, &encoded)?;
+        let record = IndexEntry {
+            codec: record.codec,
+            encryption: Some(encryption),
+            ..IndexEntry::new(&sealed, &Sha256::digest(&sealed).into())
+        };
+        Ok((sealed, record))
+    }
+
+    fn add_core(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        method: Option<CompressionMethod>,
+    ) -> Result<()> {
+        match method {
+            Some(method) => self.inner.add_file_with_compression(path, data, method),
+            None => self.inner.add_file(path, data),
+        }
+        .map_err(Error::core)
+    }
+}
+
+/// Record a file on disk without loading it into memory.
+fn hash_file(path: &Path) -> Result<IndexEntry> {
+    let mut file = File::open(path)?;
+    let mut sha256 = Sha256::new();
+    let mut crc32 = crc32fast::Hasher::new();
+    let mut size = 0u64;
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        sha256.update(&buffer[..read]);
+        crc32.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok(IndexEntry {
+        size,
+        crc32: crc32.finalize(),
+        sha256: Some(hex::encode(sha256.finalize())),
+        codec: None,
+        encryption: None,
+        alias_of: None,
+    })
+}
@@ -0,0 +1,65 @@
+//! Run arbitrary SQL with arbitrary JSON parameters against an archived
+//! database.
+//!
+//! Input layout: one selector byte (even runs `query`, odd runs `execute`),
+//! then the SQL, a NUL byte and the parameter JSON. Without a NUL the
+//! parameters are omitted.
+
+#![no_main]
+
+use std::os::raw::c_char;
+use std::ptr;
+
+use engram_ffi::*;
+use engram_ffi_fuzz::{c_bytes, clear_error, open_fixture};
+use libfuzzer_sys::fuzz_target;
+
+struct Fixture(*mut EngramDatabaseHandle);
+
+// The handle is only used from the fuzzing thread.
+unsafe impl Send for Fixture {}
+unsafe impl Sync for Fixture {}
+
+fn open_database() -> Fixture {
+    let archive = open_fixture();
+    let mut db = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    let path = c"data/app.db";
+    assert_eq!(
+        engram_archive_open_database(archive, path.as_ptr(), &mut db, &mut error),
+        0
+    );
+    Fixture(db)
+}
+
+fuzz_target!(|data: &[u8]| {
+    static FIXTURE: std::sync::OnceLock<Fixture> = std::sync::OnceLock::new();
+    let db = FIXTURE.get_or_init(open_database).0;
+
+    let Some((&selector, rest)) = data.split_first() else {
+        return;
+    };
+    let (sql, params) = match rest.iter().position(|&b| b == 0) {
+        Some(split) => (&rest[..split], Some(c_bytes(&rest[split + 1..]))),
+        None => (rest, None),
+    };
+    let sql = c_bytes(sql);
+    let params_ptr = params
+        .as_ref()
+        .map_or(ptr::null(), |params| params.as_ptr() as *const c_char);
+
+    let mut error = ptr::null_mut();
+    if selector % 2 == 0 {
+        let mut json = ptr::null_mut();
+        if engram_database_query(db, sql.as_ptr() as _, params_ptr, &mut json, &mut error) == 0 {
+            engram_free_cstring(json);
+        } else {
+            clear_error(&mut error);
+        }
+    } else {
+        let mut rows = 0;
+        if engram_database_execute(db, sql.as_ptr() as _, params_ptr, &mut rows, &mut error) != 0 {
+            clear_error(&mut error);
+        }
+    }
+});
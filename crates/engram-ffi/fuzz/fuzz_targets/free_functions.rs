@@ -0,0 +1,114 @@
+//! Drive allocation and release through the C ABI in arbitrary order,
+//! including null and empty values a careless caller might pass.
+
+#![no_main]
+
+use std::ptr;
+
+use engram_ffi::*;
+use engram_ffi_fuzz::{c_path, clear_error, open_fixture, scratch_dir};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|ops: &[u8]| {
+    let archive = open_fixture();
+    let mut error = ptr::null_mut();
+
+    for &op in ops.iter().take(64) {
+        match op % 10 {
+            0 => {
+                let mut list = EngramStringList {
+                    data: ptr::null_mut(),
+                    len: 0,
+                };
+                if engram_archive_list_files(archive, &mut list, &mut error) == 0 {
+                    engram_string_list_free(list);
+                }
+            }
+            1 => {
+                let mut list = EngramStringList {
+                    data: ptr::null_mut(),
+                    len: 0,
+                };
+                // Matches nothing, returning an empty list.
+                let prefix = c"missing/";
+                if engram_archive_list_prefix(archive, prefix.as_ptr(), &mut list, &mut error) == 0
+                {
+                    engram_string_list_free(list);
+                }
+            }
+            2 => {
+                let mut buffer = EngramBuffer {
+                    data: ptr::null_mut(),
+                    len: 0,
+                };
+                let path = c"hello.txt";
+                if engram_archive_read_file(archive, path.as_ptr(), &mut buffer, &mut error) == 0 {
+                    engram_buffer_free(buffer);
+                }
+            }
+            3 => {
+                let mut json = ptr::null_mut();
+                let path = c"data/config.json";
+                if engram_archive_get_metadata(archive, path.as_ptr(), &mut json, &mut error) == 0 {
+                    engram_free_cstring(json);
+                }
+            }
+            4 => {
+                // A second failure must free the previous message.
+                let path = c"missing.txt";
+                let mut buffer = EngramBuffer {
+                    data: ptr::null_mut(),
+                    len: 0,
+                };
+                engram_archive_read_file(archive, path.as_ptr(), &mut buffer, &mut error);
+                engram_archive_read_file(archive, path.as_ptr(), &mut buffer, &mut error);
+            }
+            5 => {
+                engram_free_cstring(ptr::null_mut());
+                engram_buffer_free(EngramBuffer {
+                    data: ptr::null_mut(),
+                    len: 0,
+                });
+                engram_string_list_free(EngramStringList {
+                    data: ptr::null_mut(),
+                    len: 3,
+                });
+            }
+            6 => {
+                engram_close_archive(ptr::null_mut());
+                engram_database_close(ptr::null_mut());
+                engram_writer_free(ptr::null_mut());
+            }
+            7 => {
+                let mut db = ptr::null_mut();
+                let path = c"data/app.db";
+                if engram_archive_open_database(archive, path.as_ptr(), &mut db, &mut error) == 0 {
+                    engram_database_close(db);
+                }
+            }
+            8 => {
+                // Writers dropped before finalizing discard their output.
+                let path = c_path(&scratch_dir().join("abandoned.eng"));
+                let mut writer = ptr::null_mut();
+                if engram_writer_create(path.as_ptr(), &mut writer, &mut error) == 0 {
+                    let name = c"a.txt";
+                    engram_writer_add_file(writer, name.as_ptr(), b"a".as_ptr(), 1, &mut error);
+                    engram_writer_free(writer);
+                }
+            }
+            _ => {
+                // Calls on a finalized writer fail instead of touching freed state.
+                let path = c_path(&scratch_dir().join("finalized.eng"));
+                let mut writer = ptr::null_mut();
+                if engram_writer_create(path.as_ptr(), &mut writer, &mut error) == 0 {
+                    engram_writer_finalize(writer, &mut error);
+                    engram_writer_finalize(writer, &mut error);
+                    engram_writer_free(writer);
+                }
+            }
+        }
+        clear_error(&mut error);
+    }
+
+    engram_close_archive(archive);
+});
@@ -0,0 +1,98 @@
+//! Open arbitrary bytes as an archive and read everything it claims to hold.
+
+#![no_main]
+
+use std::ffi::CStr;
+use std::ptr;
+
+use engram_ffi::*;
+use engram_ffi_fuzz::{c_path, clear_error, open_options, scratch_dir};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let path = scratch_dir().join("input.eng");
+    std::fs::write(&path, data).expect("write input");
+    let path = c_path(&path);
+
+    let mut handle = ptr::null_mut();
+    let mut error = ptr::null_mut();
+
+    // Without limits, a size claimed by an entry is allocated as-is when it
+    // is read, so the default open only gets its directory walked.
+    if engram_open_archive(path.as_ptr(), &mut handle, &mut error) == 0 {
+        visit(handle, false);
+        engram_close_archive(handle);
+    } else {
+        clear_error(&mut error);
+    }
+
+    let options = open_options();
+    if engram_open_archive_with_options(path.as_ptr(), &options, &mut handle, &mut error) == 0 {
+        visit(handle, true);
+        engram_close_archive(handle);
+    } else {
+        clear_error(&mut error);
+    }
+});
+
+/// List every entry and fetch its metadata, reading and hashing the entries
+/// too when `read` is set.
+fn visit(handle: *mut EngramArchiveHandle, read: bool) {
+    let mut error = ptr::null_mut();
+
+    let mut count = 0;
+    if engram_archive_entry_count(handle, &mut count, &mut error) != 0 {
+        clear_error(&mut error);
+    }
+
+    let mut list = EngramStringList {
+        data: ptr::null_mut(),
+        len: 0,
+    };
+    if engram_archive_list_files(handle, &mut list, &mut error) == 0 {
+        for i in 0..list.len {
+            let name = unsafe { *list.data.add(i) };
+            let name = unsafe { CStr::from_ptr(name) };
+
+            let mut json = ptr::null_mut();
+            if engram_archive_get_metadata(handle, name.as_ptr(), &mut json, &mut error) == 0 {
+                engram_free_cstring(json);
+            } else {
+                clear_error(&mut error);
+            }
+
+            if !read {
+                continue;
+            }
+
+            let mut buffer = EngramBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            if engram_archive_read_file(handle, name.as_ptr(), &mut buffer, &mut error) == 0 {
+                engram_buffer_free(buffer);
+            } else {
+                clear_error(&mut error);
+            }
+
+            let mut hash = ptr::null_mut();
+            if engram_archive_hash_file(handle, name.as_ptr(), &mut hash, &mut error) == 0 {
+                engram_free_cstring(hash);
+            } else {
+                clear_error(&mut error);
+            }
+        }
+        engram_string_list_free(list);
+    } else {
+        clear_error(&mut error);
+    }
+
+    if read {
+        let mut manifest = ptr::null_mut();
+        if engram_archive_read_manifest(handle, &mut manifest, &mut error) == 0 {
+            engram_free_cstring(manifest);
+        } else {
+            clear_error(&mut error);
+        }
+    }
+}
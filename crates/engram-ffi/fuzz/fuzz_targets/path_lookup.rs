@@ -0,0 +1,78 @@
+//! Look up arbitrary paths and prefixes in a valid archive.
+
+#![no_main]
+
+use std::os::raw::c_char;
+use std::ptr;
+
+use engram_ffi::*;
+use engram_ffi_fuzz::{c_bytes, clear_error, open_fixture};
+use libfuzzer_sys::fuzz_target;
+
+struct Fixture(*mut EngramArchiveHandle);
+
+// The handle is only used from the fuzzing thread.
+unsafe impl Send for Fixture {}
+unsafe impl Sync for Fixture {}
+
+fuzz_target!(|data: &[u8]| {
+    static FIXTURE: std::sync::OnceLock<Fixture> = std::sync::OnceLock::new();
+    let handle = FIXTURE.get_or_init(|| Fixture(open_fixture())).0;
+
+    let path = c_bytes(data);
+    let path = path.as_ptr() as *const c_char;
+    let mut error = ptr::null_mut();
+
+    let mut found = false;
+    if engram_archive_contains(handle, path, &mut found, &mut error) != 0 {
+        clear_error(&mut error);
+    }
+
+    let mut json = ptr::null_mut();
+    if engram_archive_get_metadata(handle, path, &mut json, &mut error) == 0 {
+        engram_free_cstring(json);
+    } else {
+        clear_error(&mut error);
+    }
+
+    let mut buffer = EngramBuffer {
+        data: ptr::null_mut(),
+        len: 0,
+    };
+    if engram_archive_read_file(handle, path, &mut buffer, &mut error) == 0 {
+        engram_buffer_free(buffer);
+    } else {
+        clear_error(&mut error);
+    }
+
+    let mut text = ptr::null_mut();
+    if engram_archive_read_text(handle, path, &mut text, &mut error) == 0 {
+        engram_free_cstring(text);
+    } else {
+        clear_error(&mut error);
+    }
+
+    let mut json = ptr::null_mut();
+    if engram_archive_read_json(handle, path, &mut json, &mut error) == 0 {
+        engram_free_cstring(json);
+    } else {
+        clear_error(&mut error);
+    }
+
+    let mut list = EngramStringList {
+        data: ptr::null_mut(),
+        len: 0,
+    };
+    if engram_archive_list_prefix(handle, path, &mut list, &mut error) == 0 {
+        engram_string_list_free(list);
+    } else {
+        clear_error(&mut error);
+    }
+
+    let mut db = ptr::null_mut();
+    if engram_archive_open_database(handle, path, &mut db, &mut error) == 0 {
+        engram_database_close(db);
+    } else {
+        clear_error(&mut error);
+    }
+});
@@ -0,0 +1,67 @@
+//! Regenerate the binary seeds of the `open_archive` corpus.
+//!
+//! Run from the fuzz directory with `cargo run --bin seed_corpus` after
+//! changing the archive format. The text corpora of the other targets are
+//! maintained by hand.
+
+use std::path::Path;
+
+use engram_common::{Cipher, EncryptionKey, EncryptionOptions, Writer, WriterOptions};
+use engram_core::CompressionMethod;
+use engram_ffi_fuzz::{write_archive, SEED_KEY, SEED_SIGNING_KEY};
+
+fn main() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus/open_archive");
+    std::fs::create_dir_all(&corpus).expect("create corpus directory");
+
+    let archive = corpus.join("fixture.eng");
+    write_archive(&archive);
+    let _ = std::fs::remove_file(archive.with_extension("db"));
+    println!("wrote {}", archive.display());
+
+    write_seed(&corpus.join("encoded.eng"), WriterOptions::default(), false);
+    write_seed(&corpus.join("signed.eng"), WriterOptions::default(), true);
+    write_seed(
+        &corpus.join("deduplicated.eng"),
+        WriterOptions {
+            deduplicate: true,
+            ..Default::default()
+        },
+        false,
+    );
+    write_seed(
+        &corpus.join("encrypted.eng"),
+        WriterOptions {
+            deduplicate: true,
+            encryption: Some(EncryptionOptions {
+                cipher: Cipher::ChaCha20Poly1305,
+                key: EncryptionKey::new(&SEED_KEY, Some("seed".into())).expect("seed key"),
+            }),
+            ..Default::default()
+        },
+        true,
+    );
+}
+
+/// Write an archive exercising the binding-side index: explicit zstd and
+/// deflate levels, duplicate payloads and a manifest.
+fn write_seed(path: &Path, options: WriterOptions, sign: bool) {
+    let text = b"Seed text that compresses well. ".repeat(32);
+    let mut writer = Writer::create(path, options).expect("create seed");
+    writer.add_file("text.txt", &text).expect("add text");
+    writer.add_file("copy.txt", &text).expect("add copy");
+    writer
+        .add_file_with_compression("zstd.txt", &text, CompressionMethod::Zstd, Some(19))
+        .expect("add zstd");
+    writer
+        .add_file_with_compression("deflate.txt", &text, CompressionMethod::Deflate, Some(9))
+        .expect("add deflate");
+    writer
+        .add_manifest(&serde_json::json!({ "name": "seed", "version": "1.0.0" }))
+        .expect("add manifest");
+    if sign {
+        writer.sign_with(&SEED_SIGNING_KEY).expect("sign seed");
+    }
+    writer.finalize().expect("finalize seed");
+    println!("wrote {}", path.display());
+}
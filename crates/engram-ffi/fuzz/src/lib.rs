@@ -0,0 +1,139 @@
+//! Shared fixtures for the engram-ffi fuzz targets.
+//!
+//! Everything goes through the C ABI, the way foreign callers use it.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::OnceLock;
+
+use engram_ffi::*;
+
+/// Limits applied to fuzzed archives so huge claimed sizes are rejected
+/// instead of exhausting memory.
+pub const LIMITS: EngramLimits = EngramLimits {
+    max_entries: 4096,
+    max_path_length: 4096,
+    max_entry_size: 16 * 1024 * 1024,
+    max_compression_ratio: 1100.0,
+    max_total_bytes: 64 * 1024 * 1024,
+};
+
+/// Key the encrypted seed archives are written with, so fuzzed variants of
+/// them are decrypted rather than rejected up front.
+pub static SEED_KEY: [u8; 32] = [0x5e; 32];
+
+/// Ed25519 secret key the signed seed archives are written with.
+pub static SEED_SIGNING_KEY: [u8; 32] = [0x51; 32];
+
+/// Open options carrying [`LIMITS`] and [`SEED_KEY`].
+pub fn open_options() -> EngramOpenOptions {
+    EngramOpenOptions {
+        trusted_keys: ptr::null(),
+        trusted_key_count: 0,
+        require_signature: false,
+        keys: SEED_KEY.as_ptr(),
+        key_count: 1,
+        limits: LIMITS,
+    }
+}
+
+/// Directory for files written by the fuzz targets.
+pub fn scratch_dir() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("engram-fuzz-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create scratch directory");
+        dir
+    })
+}
+
+/// NUL-terminated copy of arbitrary bytes, read by the C ABI up to the first
+/// NUL like any C string.
+pub fn c_bytes(data: &[u8]) -> Vec<u8> {
+    let mut bytes = data.to_vec();
+    bytes.push(0);
+    bytes
+}
+
+pub fn c_path(path: &Path) -> CString {
+    CString::new(path.to_str().expect("UTF-8 path")).expect("path without NUL")
+}
+
+/// Free an error message set by a failed call.
+pub fn clear_error(error: &mut *mut c_char) {
+    engram_free_cstring(*error);
+    *error = ptr::null_mut();
+}
+
+/// Write a small archive with text, JSON, a manifest and a SQLite database.
+pub fn write_archive(path: &Path) {
+    let db_path = path.with_extension("db");
+    let _ = std::fs::remove_file(&db_path);
+    let conn = rusqlite::Connection::open(&db_path).expect("create database");
+    conn.execute_batch(
+        "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL, data BLOB);
+         INSERT INTO items (name, price, data) VALUES ('alpha', 1.5, x'00ff'), ('beta', NULL, NULL);",
+    )
+    .expect("populate database");
+    drop(conn);
+
+    let archive = c_path(path);
+    let mut writer = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    assert_eq!(
+        engram_writer_create(archive.as_ptr(), &mut writer, &mut error),
+        0
+    );
+
+    let files: [(&str, &[u8]); 3] = [
+        ("hello.txt", b"Hello, fuzzer!"),
+        ("data/config.json", br#"{"key":"value","list":[1,2,3]}"#),
+        ("data/blob.bin", &[0, 1, 2, 3, 255, 254]),
+    ];
+    for (name, data) in files {
+        let name = CString::new(name).unwrap();
+        assert_eq!(
+            engram_writer_add_file(writer, name.as_ptr(), data.as_ptr(), data.len(), &mut error),
+            0
+        );
+    }
+
+    let name = CString::new("data/app.db").unwrap();
+    let disk = c_path(&db_path);
+    assert_eq!(
+        engram_writer_add_file_from_disk(writer, name.as_ptr(), disk.as_ptr(), &mut error),
+        0
+    );
+
+    let manifest = CString::new(r#"{"name":"fuzz","version":"1.0.0"}"#).unwrap();
+    assert_eq!(
+        engram_writer_add_manifest(writer, manifest.as_ptr(), &mut error),
+        0
+    );
+    assert_eq!(engram_writer_finalize(writer, &mut error), 0);
+    engram_writer_free(writer);
+}
+
+/// Archive shared by targets that fuzz lookups rather than archive bytes.
+pub fn fixture_archive() -> &'static Path {
+    static ARCHIVE: OnceLock<PathBuf> = OnceLock::new();
+    ARCHIVE.get_or_init(|| {
+        let path = scratch_dir().join("fixture.eng");
+        write_archive(&path);
+        path
+    })
+}
+
+/// Open handle to [`fixture_archive`].
+pub fn open_fixture() -> *mut EngramArchiveHandle {
+    let path = c_path(fixture_archive());
+    let mut handle = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    assert_eq!(
+        engram_open_archive(path.as_ptr(), &mut handle, &mut error),
+        0
+    );
+    handle
+}
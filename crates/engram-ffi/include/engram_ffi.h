@@ -11,6 +11,22 @@ extern "C" {
 
 typedef struct EngramArchiveHandle EngramArchiveHandle;
 typedef struct EngramDatabaseHandle EngramDatabaseHandle;
+typedef struct EngramOverlayHandle EngramOverlayHandle;
+typedef struct EngramWriterHandle EngramWriterHandle;
+
+#define ENGRAM_OK 0
+#define ENGRAM_ERR 1
+#define ENGRAM_ERR_SIGNATURE 2
+#define ENGRAM_ERR_LIMIT 3
+#define ENGRAM_PANIC -1
+
+#define ENGRAM_COMPRESSION_NONE 0
+#define ENGRAM_COMPRESSION_LZ4 1
+#define ENGRAM_COMPRESSION_ZSTD 2
+#define ENGRAM_COMPRESSION_DEFLATE 3
+
+#define ENGRAM_CIPHER_AES_256_GCM 0
+#define ENGRAM_CIPHER_CHACHA20_POLY1305 1
 
 typedef struct {
     uint8_t *data;
@@ -22,7 +38,36 @@ typedef struct {
     size_t len;
 } EngramStringList;
 
+/* `write` consumes `len` bytes from `data` and returns 0 on success. */
+typedef struct {
+    void *context;
+    int32_t (*write)(void *context, const uint8_t *data, size_t len);
+} EngramByteSink;
+
+/* Zero disables a limit. */
+typedef struct {
+    uint64_t max_entries;
+    uint64_t max_path_length;
+    uint64_t max_entry_size;
+    double max_compression_ratio;
+    uint64_t max_total_bytes;
+} EngramLimits;
+
+/* Zero-initialise for the defaults. `trusted_keys` and `keys` hold consecutive 32-byte keys. */
+typedef struct {
+    const uint8_t *trusted_keys;
+    size_t trusted_key_count;
+    bool require_signature;
+    const uint8_t *keys;
+    size_t key_count;
+    EngramLimits limits;
+} EngramOpenOptions;
+
+/* `options` may be NULL for the defaults. */
 int32_t engram_open_archive(const char *path, EngramArchiveHandle **out_handle, char **out_error);
+int32_t engram_open_archive_with_options(const char *path, const EngramOpenOptions *options, EngramArchiveHandle **out_handle, char **out_error);
+/* Copies `data`. Linux only; other platforms return ENGRAM_ERR. */
+int32_t engram_open_archive_from_memory(const uint8_t *data, size_t len, const EngramOpenOptions *options, EngramArchiveHandle **out_handle, char **out_error);
 void engram_close_archive(EngramArchiveHandle *handle);
 
 int32_t engram_archive_entry_count(EngramArchiveHandle *handle, uint32_t *out_count, char **out_error);
@@ -31,12 +76,44 @@ int32_t engram_archive_list_files(EngramArchiveHandle *handle, EngramStringList
 int32_t engram_archive_list_prefix(EngramArchiveHandle *handle, const char *prefix, EngramStringList *out_list, char **out_error);
 int32_t engram_archive_read_file(EngramArchiveHandle *handle, const char *path, EngramBuffer *out_buffer, char **out_error);
 int32_t engram_archive_read_text(EngramArchiveHandle *handle, const char *path, char **out_text, char **out_error);
+int32_t engram_archive_hash_file(EngramArchiveHandle *handle, const char *path, char **out_hash, char **out_error);
 int32_t engram_archive_read_json(EngramArchiveHandle *handle, const char *path, char **out_json, char **out_error);
 int32_t engram_archive_get_metadata(EngramArchiveHandle *handle, const char *path, char **out_json, char **out_error);
 int32_t engram_archive_read_manifest(EngramArchiveHandle *handle, char **out_json, char **out_error);
+int32_t engram_archive_diff(EngramArchiveHandle *old_handle, EngramArchiveHandle *new_handle, bool databases, char **out_json, char **out_error);
+
+/* `level` may be NULL for the default zstd level. */
+int32_t engram_create_patch(EngramArchiveHandle *old_handle, EngramArchiveHandle *new_handle, const char *patch_path, const int32_t *level, char **out_json, char **out_error);
+int32_t engram_apply_patch(EngramArchiveHandle *base_handle, EngramArchiveHandle *patch_handle, const char *out_path, char **out_error);
+
+/* Layers are listed highest precedence first. Each layer is opened with `options`, which may be NULL for the defaults. */
+int32_t engram_open_overlay(const char *const *paths, size_t count, const EngramOpenOptions *options, EngramOverlayHandle **out_handle, char **out_error);
+void engram_overlay_close(EngramOverlayHandle *handle);
+int32_t engram_overlay_contains(EngramOverlayHandle *handle, const char *path, bool *out_result, char **out_error);
+int32_t engram_overlay_list_files(EngramOverlayHandle *handle, EngramStringList *out_list, char **out_error);
+int32_t engram_overlay_list_prefix(EngramOverlayHandle *handle, const char *prefix, EngramStringList *out_list, char **out_error);
+int32_t engram_overlay_read_file(EngramOverlayHandle *handle, const char *path, EngramBuffer *out_buffer, char **out_error);
+int32_t engram_overlay_get_metadata(EngramOverlayHandle *handle, const char *path, char **out_json, char **out_error);
+int32_t engram_overlay_open_database(EngramOverlayHandle *handle, const char *path, EngramDatabaseHandle **out_db, char **out_error);
+
+int32_t engram_writer_create(const char *path, EngramWriterHandle **out_writer, char **out_error);
+int32_t engram_writer_create_encrypted(const char *path, int32_t cipher, const uint8_t *key, size_t key_len, const char *key_id, EngramWriterHandle **out_writer, char **out_error);
+/* Linux only. Buffers the whole archive in memory and calls `sink` only from engram_writer_finalize. */
+int32_t engram_writer_create_to_sink(const EngramByteSink *sink, EngramWriterHandle **out_writer, char **out_error);
+int32_t engram_writer_open_for_update(const char *path, EngramWriterHandle **out_writer, char **out_error);
+int32_t engram_writer_add_file(EngramWriterHandle *writer, const char *path, const uint8_t *data, size_t len, char **out_error);
+int32_t engram_writer_add_file_with_compression(EngramWriterHandle *writer, const char *path, const uint8_t *data, size_t len, int32_t compression, const int32_t *level, char **out_error);
+int32_t engram_writer_add_file_from_disk(EngramWriterHandle *writer, const char *path, const char *disk_path, char **out_error);
+int32_t engram_writer_add_manifest(EngramWriterHandle *writer, const char *manifest_json, char **out_error);
+int32_t engram_writer_delete_file(EngramWriterHandle *writer, const char *path, bool *out_deleted, char **out_error);
+int32_t engram_writer_add_whiteout(EngramWriterHandle *writer, const char *path, char **out_error);
+int32_t engram_writer_sign(EngramWriterHandle *writer, const uint8_t *secret_key, size_t key_len, char **out_error);
+int32_t engram_writer_finalize(EngramWriterHandle *writer, char **out_error);
+void engram_writer_free(EngramWriterHandle *writer);
 
 int32_t engram_archive_open_database(EngramArchiveHandle *handle, const char *path, EngramDatabaseHandle **out_db, char **out_error);
 void engram_database_close(EngramDatabaseHandle *db);
+/* Archive errors raised by engram_read() and the other engram_* functions keep ENGRAM_ERR_SIGNATURE and ENGRAM_ERR_LIMIT. */
 int32_t engram_database_query(EngramDatabaseHandle *db, const char *sql, const char *params_json, char **out_json, char **out_error);
 int32_t engram_database_execute(EngramDatabaseHandle *db, const char *sql, const char *params_json, int64_t *out_rows, char **out_error);
 
@@ -1,25 +1,30 @@
 //! Engram FFI
 //!
-//! Exposes the archive reader and SQLite helper functionality via a C ABI that
+//! Exposes the archive reader, writer and SQLite helper functionality via a C ABI that
 //! can be consumed from Java (FFM), Python, or any other language capable of
-//! interoperating with C.
+//! interoperating with C. Python code is better served by the `engram-py` crate,
+//! which wraps the same archive logic in native classes.
 
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_void};
 use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
-use std::sync::{Arc, Mutex};
-
-use engram_core::{ArchiveReader, CompressionMethod};
-use engram_vfs::EngramVfs;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use engram_common::diff::{self, DiffOptions};
+use engram_common::patch::{self, PatchOptions};
+use engram_common::{
+    codec, database, signature, sql, Archive, Cipher, EncryptionKey, EncryptionOptions, EntryInfo,
+    Limits, OpenOptions, Overlay, Writer, WriterOptions,
+};
+use engram_core::CompressionMethod;
 use rusqlite::Connection;
 use serde_json::json;
 
 /// Opaque handle types exposed through the C API.
 #[repr(C)]
 pub struct EngramArchiveHandle {
-    reader: Arc<Mutex<ArchiveReader>>,
-    path: String,
+    reader: Arc<Mutex<Archive>>,
 }
 
 #[repr(C)]
@@ -27,6 +32,18 @@ pub struct EngramDatabaseHandle {
     conn: Arc<Mutex<Connection>>,
 }
 
+#[repr(C)]
+pub struct EngramOverlayHandle {
+    overlay: Arc<Mutex<Overlay>>,
+}
+
+#[repr(C)]
+pub struct EngramWriterHandle {
+    writer: Mutex<Option<Writer>>,
+    /// Receives the archive of an in-memory writer on finalize.
+    sink: Option<EngramByteSink>,
+}
+
 /// Byte buffer returned to foreign callers.
 #[repr(C)]
 pub struct EngramBuffer {
@@ -34,6 +51,69 @@ pub struct EngramBuffer {
     pub len: usize,
 }
 
+/// Resource limits for archives from untrusted sources. Zero disables a
+/// limit.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EngramLimits {
+    pub max_entries: u64,
+    pub max_path_length: u64,
+    pub max_entry_size: u64,
+    pub max_compression_ratio: f64,
+    pub max_total_bytes: u64,
+}
+
+/// Options for opening an archive. A zeroed struct opens with the defaults.
+///
+/// `trusted_keys` holds `trusted_key_count` consecutive 32-byte Ed25519 public
+/// keys and `keys` holds `key_count` consecutive 32-byte encryption keys;
+/// either may be null when its count is zero.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EngramOpenOptions {
+    pub trusted_keys: *const u8,
+    pub trusted_key_count: usize,
+    pub require_signature: bool,
+    pub keys: *const u8,
+    pub key_count: usize,
+    pub limits: EngramLimits,
+}
+
+/// Destination for archive bytes provided by the caller.
+///
+/// `write` consumes `len` bytes from `data` and returns 0 on success. It is
+/// called with consecutive chunks of the archive, all from within
+/// `engram_writer_finalize`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EngramByteSink {
+    pub context: *mut c_void,
+    pub write: Option<unsafe extern "C" fn(*mut c_void, *const u8, usize) -> c_int>,
+}
+
+/// Chunk size used to hand an archive to an `EngramByteSink`.
+const SINK_CHUNK_SIZE: usize = 1024 * 1024;
+
+impl EngramByteSink {
+    fn write_all(&self, data: &[u8]) -> Result<(), String> {
+        let write = self
+            .write
+            .ok_or_else(|| "byte sink has no write callback".to_string())?;
+        for (index, chunk) in data.chunks(SINK_CHUNK_SIZE).enumerate() {
+            match unsafe { write(self.context, chunk.as_ptr(), chunk.len()) } {
+                0 => {}
+                code => {
+                    return Err(format!(
+                        "write callback failed with {code} at offset {}",
+                        index * SINK_CHUNK_SIZE
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 /// List of strings (UTF-8). Caller must free via `engram_string_list_free`.
 #[repr(C)]
 pub struct EngramStringList {
@@ -43,6 +123,8 @@ pub struct EngramStringList {
 
 const OK: c_int = 0;
 const ERR: c_int = 1;
+const ERR_SIGNATURE: c_int = 2;
+const ERR_LIMIT: c_int = 3;
 const PANIC: c_int = -1;
 
 // -------------------------------------------------------------------------------------------------
@@ -74,12 +156,20 @@ fn set_error(out_error: *mut *mut c_char, message: impl ToString) {
 fn ffi_guard<F>(out_error: *mut *mut c_char, f: F) -> c_int
 where
     F: FnOnce() -> Result<(), String>,
+{
+    ffi_guard_with_code(out_error, || f().map_err(|err| (ERR, err)))
+}
+
+/// Like `ffi_guard`, for functions that report errors with specific codes.
+fn ffi_guard_with_code<F>(out_error: *mut *mut c_char, f: F) -> c_int
+where
+    F: FnOnce() -> Result<(), (c_int, String)>,
 {
     match panic::catch_unwind(AssertUnwindSafe(f)) {
         Ok(Ok(())) => OK,
-        Ok(Err(err)) => {
+        Ok(Err((code, err))) => {
             set_error(out_error, err);
-            ERR
+            code
         }
         Err(_) => {
             set_error(out_error, "internal panic in engram-ffi");
@@ -98,35 +188,220 @@ unsafe fn cstr_to_string(ptr: *const c_char) -> Result<String, String> {
         .map_err(|e| format!("invalid UTF-8: {e}"))
 }
 
+unsafe fn bytes_from_raw<'a>(data: *const u8, len: usize) -> Result<&'a [u8], String> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err("received null pointer for data".to_string());
+    }
+    Ok(std::slice::from_raw_parts(data, len))
+}
+
 // -------------------------------------------------------------------------------------------------
 // Archive functions
 // -------------------------------------------------------------------------------------------------
 
+/// Error raised by the archive, keeping specific codes for signature and
+/// resource limit failures.
+fn archive_error(context: &str, err: engram_common::Error) -> (c_int, String) {
+    let code = match err {
+        engram_common::Error::Signature(_) => ERR_SIGNATURE,
+        engram_common::Error::Limit(_) => ERR_LIMIT,
+        _ => ERR,
+    };
+    (code, format!("{context}: {err}"))
+}
+
+/// Error from running a statement, keeping the codes of archive errors
+/// raised by the `engram_*` SQL functions and table.
+fn statement_error(context: &str, err: rusqlite::Error) -> (c_int, String) {
+    match engram_common::sql::function_error(&err) {
+        Some(archive) => archive_error(context, archive),
+        None => (ERR, format!("{context}: {err}")),
+    }
+}
+
+/// Hand `data` to the caller, who frees it with `engram_buffer_free`.
+unsafe fn write_buffer(out_buffer: *mut EngramBuffer, data: Vec<u8>) {
+    let len = data.len();
+    let mut boxed = data.into_boxed_slice();
+    let data_ptr = boxed.as_mut_ptr();
+    std::mem::forget(boxed);
+
+    (*out_buffer).data = data_ptr;
+    (*out_buffer).len = len;
+}
+
+/// Hand `files` to the caller, who frees them with `engram_string_list_free`.
+unsafe fn write_string_list(
+    out_list: *mut EngramStringList,
+    files: &[String],
+) -> Result<(), String> {
+    let mut strings: Vec<*mut c_char> = Vec::with_capacity(files.len());
+    for file in files {
+        match CString::new(file.as_str()) {
+            Ok(cstring) => strings.push(cstring.into_raw()),
+            Err(_) => {
+                for string in strings {
+                    drop(CString::from_raw(string));
+                }
+                return Err(format!("file path contains interior null byte: {file}"));
+            }
+        }
+    }
+
+    let len = strings.len();
+    let data_ptr = if len == 0 {
+        ptr::null_mut()
+    } else {
+        let boxed = strings.into_boxed_slice();
+        Box::into_raw(boxed) as *mut *mut c_char
+    };
+
+    (*out_list).data = data_ptr;
+    (*out_list).len = len;
+    Ok(())
+}
+
+fn metadata_json(entry: EntryInfo) -> Result<CString, String> {
+    let metadata = json!({
+        "path": entry.path,
+        "uncompressedSize": entry.uncompressed_size,
+        "compressedSize": entry.compressed_size,
+        "compression": codec::method_name(&entry.compression),
+        "compressionLevel": entry.compression_level,
+        "dictionary": entry.dictionary,
+        "aliasOf": entry.alias_of,
+        "encrypted": entry.encrypted,
+        "modifiedTime": entry.modified_time,
+        "crc32": entry.crc32,
+        "hash": entry.sha256,
+    });
+
+    CString::new(
+        serde_json::to_string(&metadata)
+            .map_err(|e| format!("failed to serialize metadata: {e}"))?,
+    )
+    .map_err(|_| "metadata contains interior null byte".to_string())
+}
+
+/// Convert C open options, where a null pointer means the defaults.
+fn open_options(options: *const EngramOpenOptions) -> Result<OpenOptions, (c_int, String)> {
+    if options.is_null() {
+        return Ok(OpenOptions::default());
+    }
+    let options = unsafe { *options };
+
+    let len = options
+        .trusted_key_count
+        .checked_mul(32)
+        .ok_or((ERR, "trusted_key_count is too large".to_string()))?;
+    let trusted_keys = unsafe { bytes_from_raw(options.trusted_keys, len) }
+        .map_err(|err| (ERR, err))?
+        .chunks_exact(32)
+        .map(|key| signature::public_key(key).map_err(|e| (ERR, e.to_string())))
+        .collect::<Result<_, _>>()?;
+
+    let len = options
+        .key_count
+        .checked_mul(32)
+        .ok_or((ERR, "key_count is too large".to_string()))?;
+    let keys = unsafe { bytes_from_raw(options.keys, len) }
+        .map_err(|err| (ERR, err))?
+        .chunks_exact(32)
+        .map(|key| EncryptionKey::new(key, None).map_err(|e| (ERR, e.to_string())))
+        .collect::<Result<_, _>>()?;
+
+    let limits = options.limits;
+    let enabled = |max: u64| (max > 0).then_some(max);
+    Ok(OpenOptions {
+        trusted_keys,
+        require_signature: options.require_signature,
+        keys,
+        limits: Limits {
+            max_entries: enabled(limits.max_entries).map(|max| max as usize),
+            max_path_length: enabled(limits.max_path_length).map(|max| max as usize),
+            max_entry_size: enabled(limits.max_entry_size),
+            max_compression_ratio: (limits.max_compression_ratio > 0.0)
+                .then_some(limits.max_compression_ratio),
+            max_total_bytes: enabled(limits.max_total_bytes),
+        },
+    })
+}
+
+fn store_archive_handle(
+    reader: Archive,
+    out_handle: *mut *mut EngramArchiveHandle,
+) -> Result<(), (c_int, String)> {
+    let handle = EngramArchiveHandle {
+        reader: Arc::new(Mutex::new(reader)),
+    };
+
+    unsafe {
+        *out_handle = Box::into_raw(Box::new(handle));
+    }
+
+    Ok(())
+}
+
 #[no_mangle]
 pub extern "C" fn engram_open_archive(
     path: *const c_char,
     out_handle: *mut *mut EngramArchiveHandle,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    engram_open_archive_with_options(path, ptr::null(), out_handle, out_error)
+}
+
+/// Open an archive with signature requirements, decryption keys and resource
+/// limits. `options` may be null for the defaults.
+///
+/// A signature in the archive is always verified; requiring one needs at
+/// least one trusted key. Signature failures return `ERR_SIGNATURE` and
+/// exceeded limits `ERR_LIMIT`, both here and from later reads.
+#[no_mangle]
+pub extern "C" fn engram_open_archive_with_options(
+    path: *const c_char,
+    options: *const EngramOpenOptions,
+    out_handle: *mut *mut EngramArchiveHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
         if out_handle.is_null() {
-            return Err("out_handle pointer cannot be null".into());
+            return Err((ERR, "out_handle pointer cannot be null".into()));
         }
 
-        let path_str = unsafe { cstr_to_string(path)? };
-        let reader = ArchiveReader::open(&path_str)
-            .map_err(|e| format!("failed to open archive: {e}"))?;
-
-        let handle = EngramArchiveHandle {
-            reader: Arc::new(Mutex::new(reader)),
-            path: path_str,
-        };
+        let options = open_options(options)?;
+        let path_str = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
+        let reader = Archive::open_with(&path_str, &options)
+            .map_err(|e| archive_error("failed to open archive", e))?;
+        store_archive_handle(reader, out_handle)
+    })
+}
 
-        unsafe {
-            *out_handle = Box::into_raw(Box::new(handle));
+/// Open an archive held in memory, with `options` as for
+/// `engram_open_archive_with_options`. `data` is copied, so the caller may
+/// free it once this returns. Only supported on Linux, where the copy stays
+/// in memory; other platforms return `ERR`.
+#[no_mangle]
+pub extern "C" fn engram_open_archive_from_memory(
+    data: *const u8,
+    len: usize,
+    options: *const EngramOpenOptions,
+    out_handle: *mut *mut EngramArchiveHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if out_handle.is_null() {
+            return Err((ERR, "out_handle pointer cannot be null".into()));
         }
 
-        Ok(())
+        let options = open_options(options)?;
+        let bytes = unsafe { bytes_from_raw(data, len) }.map_err(|err| (ERR, err))?;
+        let reader = Archive::open_memory(bytes, &options)
+            .map_err(|e| archive_error("failed to open archive", e))?;
+        store_archive_handle(reader, out_handle)
     })
 }
 
@@ -210,27 +485,7 @@ pub extern "C" fn engram_archive_list_files(
             .lock()
             .map_err(|_| "reader poisoned".to_string())?;
 
-        let mut strings: Vec<*mut c_char> = Vec::with_capacity(reader.list_files().len());
-        for file in reader.list_files() {
-            let cstring = CString::new(file.as_str())
-                .map_err(|_| format!("file path contains interior null byte: {file}"))?;
-            strings.push(cstring.into_raw());
-        }
-
-        let len = strings.len();
-        let data_ptr = if len == 0 {
-            ptr::null_mut()
-        } else {
-            let boxed = strings.into_boxed_slice();
-            Box::into_raw(boxed) as *mut *mut c_char
-        };
-
-        unsafe {
-            (*out_list).data = data_ptr;
-            (*out_list).len = len;
-        }
-
-        Ok(())
+        unsafe { write_string_list(out_list, reader.list_files()) }
     })
 }
 
@@ -241,33 +496,24 @@ pub extern "C" fn engram_archive_read_file(
     out_buffer: *mut EngramBuffer,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_buffer.is_null() {
-            return Err("null pointer passed to read_file".into());
+            return Err((ERR, "null pointer passed to read_file".into()));
         }
 
-        let query_path = unsafe { cstr_to_string(path)? };
+        let query_path = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
         let archive = unsafe { &*handle };
 
         let mut reader = archive
             .reader
             .lock()
-            .map_err(|_| "reader poisoned".to_string())?;
+            .map_err(|_| (ERR, "reader poisoned".to_string()))?;
 
         let data = reader
             .read_file(&query_path)
-            .map_err(|e| format!("failed to read file: {e}"))?;
-
-        let len = data.len();
-        let mut boxed = data.into_boxed_slice();
-        let data_ptr = boxed.as_mut_ptr();
-        std::mem::forget(boxed);
-
-        unsafe {
-            (*out_buffer).data = data_ptr;
-            (*out_buffer).len = len;
-        }
+            .map_err(|e| archive_error("failed to read file", e))?;
 
+        unsafe { write_buffer(out_buffer, data) };
         Ok(())
     })
 }
@@ -279,24 +525,25 @@ pub extern "C" fn engram_archive_read_text(
     out_text: *mut *mut c_char,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_text.is_null() {
-            return Err("null pointer passed to read_text".into());
+            return Err((ERR, "null pointer passed to read_text".into()));
         }
 
-        let query_path = unsafe { cstr_to_string(path)? };
+        let query_path = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
         let archive = unsafe { &*handle };
         let mut reader = archive
             .reader
             .lock()
-            .map_err(|_| "reader poisoned".to_string())?;
+            .map_err(|_| (ERR, "reader poisoned".to_string()))?;
 
         let data = reader
             .read_file(&query_path)
-            .map_err(|e| format!("failed to read file: {e}"))?;
+            .map_err(|e| archive_error("failed to read file", e))?;
 
-        let text = String::from_utf8(data).map_err(|e| format!("utf-8 error: {e}"))?;
-        let cstring = CString::new(text).map_err(|e| format!("failed to convert text: {e}"))?;
+        let text = String::from_utf8(data).map_err(|e| (ERR, format!("utf-8 error: {e}")))?;
+        let cstring =
+            CString::new(text).map_err(|e| (ERR, format!("failed to convert text: {e}")))?;
 
         unsafe {
             *out_text = cstring.into_raw();
@@ -306,6 +553,41 @@ pub extern "C" fn engram_archive_read_text(
     })
 }
 
+/// Hex-encoded SHA-256 of an entry. The entry is read in full and checked
+/// against the hash recorded by the writer.
+#[no_mangle]
+pub extern "C" fn engram_archive_hash_file(
+    handle: *mut EngramArchiveHandle,
+    path: *const c_char,
+    out_hash: *mut *mut c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if handle.is_null() || out_hash.is_null() {
+            return Err((ERR, "null pointer passed to hash_file".into()));
+        }
+
+        let query_path = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
+        let archive = unsafe { &*handle };
+        let mut reader = archive
+            .reader
+            .lock()
+            .map_err(|_| (ERR, "reader poisoned".to_string()))?;
+
+        let hash = reader
+            .hash_file(&query_path)
+            .map_err(|e| archive_error("failed to hash file", e))?;
+        let cstring =
+            CString::new(hash).map_err(|e| (ERR, format!("failed to convert hash: {e}")))?;
+
+        unsafe {
+            *out_hash = cstring.into_raw();
+        }
+
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn engram_archive_read_json(
     handle: *mut EngramArchiveHandle,
@@ -313,29 +595,30 @@ pub extern "C" fn engram_archive_read_json(
     out_json: *mut *mut c_char,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_json.is_null() {
-            return Err("null pointer passed to read_json".into());
+            return Err((ERR, "null pointer passed to read_json".into()));
         }
 
-        let query_path = unsafe { cstr_to_string(path)? };
+        let query_path = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
         let archive = unsafe { &*handle };
         let mut reader = archive
             .reader
             .lock()
-            .map_err(|_| "reader poisoned".to_string())?;
+            .map_err(|_| (ERR, "reader poisoned".to_string()))?;
 
         let data = reader
             .read_file(&query_path)
-            .map_err(|e| format!("failed to read file: {e}"))?;
+            .map_err(|e| archive_error("failed to read file", e))?;
 
         let json_value: serde_json::Value =
-            serde_json::from_slice(&data).map_err(|e| format!("invalid JSON: {e}"))?;
+            serde_json::from_slice(&data).map_err(|e| (ERR, format!("invalid JSON: {e}")))?;
 
         let json = serde_json::to_string(&json_value)
-            .map_err(|e| format!("failed to serialize JSON: {e}"))?;
+            .map_err(|e| (ERR, format!("failed to serialize JSON: {e}")))?;
 
-        let cstring = CString::new(json).map_err(|e| format!("failed to convert JSON: {e}"))?;
+        let cstring =
+            CString::new(json).map_err(|e| (ERR, format!("failed to convert JSON: {e}")))?;
 
         unsafe {
             *out_json = cstring.into_raw();
@@ -368,28 +651,8 @@ pub extern "C" fn engram_archive_get_metadata(
             .get_entry(&query_path)
             .ok_or_else(|| format!("entry not found: {query_path}"))?;
 
-        let metadata = json!({
-            "path": entry.path,
-            "uncompressedSize": entry.uncompressed_size,
-            "compressedSize": entry.compressed_size,
-            "compression": match entry.compression {
-                CompressionMethod::None => "none",
-                CompressionMethod::Lz4 => "lz4",
-                CompressionMethod::Zstd => "zstd",
-                CompressionMethod::Deflate => "deflate",
-            },
-            "modifiedTime": entry.modified_time,
-            "crc32": entry.crc32,
-        });
-
-        let cstring = CString::new(
-            serde_json::to_string(&metadata)
-                .map_err(|e| format!("failed to serialize metadata: {e}"))?,
-        )
-        .map_err(|_| "metadata contains interior null byte".to_string())?;
-
         unsafe {
-            *out_json = cstring.into_raw();
+            *out_json = metadata_json(entry)?.into_raw();
         }
 
         Ok(())
@@ -402,29 +665,29 @@ pub extern "C" fn engram_archive_read_manifest(
     out_json: *mut *mut c_char,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_json.is_null() {
-            return Err("null pointer passed to read_manifest".into());
+            return Err((ERR, "null pointer passed to read_manifest".into()));
         }
 
         let archive = unsafe { &*handle };
         let mut reader = archive
             .reader
             .lock()
-            .map_err(|_| "reader poisoned".to_string())?;
+            .map_err(|_| (ERR, "reader poisoned".to_string()))?;
 
         let manifest = reader
             .read_manifest()
-            .map_err(|e| format!("failed to read manifest: {e}"))?;
+            .map_err(|e| archive_error("failed to read manifest", e))?;
 
         let manifest_json = manifest
-            .ok_or_else(|| "manifest.json not found in archive".to_string())?;
+            .ok_or_else(|| (ERR, "manifest.json not found in archive".to_string()))?;
 
         let cstring = CString::new(
             serde_json::to_string(&manifest_json)
-                .map_err(|e| format!("failed to serialize manifest: {e}"))?,
+                .map_err(|e| (ERR, format!("failed to serialize manifest: {e}")))?,
         )
-        .map_err(|_| "manifest contains interior null byte".to_string())?;
+        .map_err(|_| (ERR, "manifest contains interior null byte".to_string()))?;
 
         unsafe {
             *out_json = cstring.into_raw();
@@ -453,31 +716,717 @@ pub extern "C" fn engram_archive_list_prefix(
             .lock()
             .map_err(|_| "reader poisoned".to_string())?;
 
-        let matches = reader.list_prefix(&prefix_str);
-        let mut strings: Vec<*mut c_char> = Vec::with_capacity(matches.len());
-        for file in matches {
-            let cstring = CString::new(file.as_str())
-                .map_err(|_| format!("file path contains interior null byte: {file}"))?;
-            strings.push(cstring.into_raw());
+        unsafe { write_string_list(out_list, &reader.list_prefix(&prefix_str)) }
+    })
+}
+
+/// Lock the readers of two archive handles. They are always locked in
+/// address order, so calls passing the same handles the other way round
+/// cannot deadlock, and the same archive is refused rather than locked twice.
+fn lock_readers<'a>(
+    first: &'a EngramArchiveHandle,
+    second: &'a EngramArchiveHandle,
+) -> Result<(MutexGuard<'a, Archive>, MutexGuard<'a, Archive>), (c_int, String)> {
+    if Arc::ptr_eq(&first.reader, &second.reader) {
+        return Err((ERR, "both handles refer to the same archive".into()));
+    }
+
+    let lock = |handle: &'a EngramArchiveHandle| {
+        handle
+            .reader
+            .lock()
+            .map_err(|_| (ERR, "reader poisoned".to_string()))
+    };
+    if Arc::as_ptr(&first.reader) < Arc::as_ptr(&second.reader) {
+        let first = lock(first)?;
+        Ok((first, lock(second)?))
+    } else {
+        let second = lock(second)?;
+        Ok((lock(first)?, second))
+    }
+}
+
+/// Compare two open archives, writing the changes from `old_handle` to
+/// `new_handle` as JSON. With `databases`, SQLite databases whose content
+/// changed are also compared row by row.
+#[no_mangle]
+pub extern "C" fn engram_archive_diff(
+    old_handle: *mut EngramArchiveHandle,
+    new_handle: *mut EngramArchiveHandle,
+    databases: bool,
+    out_json: *mut *mut c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if old_handle.is_null() || new_handle.is_null() || out_json.is_null() {
+            return Err((ERR, "null pointer passed to archive_diff".into()));
         }
+        if old_handle == new_handle {
+            return Err((ERR, "cannot diff an archive handle with itself".into()));
+        }
+
+        let (old, new) = unsafe { (&*old_handle, &*new_handle) };
+        let (mut old_reader, mut new_reader) = lock_readers(old, new)?;
+
+        let changes = diff::diff(&mut old_reader, &mut new_reader, DiffOptions { databases })
+            .map_err(|e| archive_error("failed to diff archives", e))?;
+        let json = serde_json::to_string(&changes)
+            .map_err(|e| (ERR, format!("failed to serialize diff: {e}")))?;
+        let cstring =
+            CString::new(json).map_err(|_| (ERR, "diff contains interior null byte".to_string()))?;
+
+        unsafe {
+            *out_json = cstring.into_raw();
+        }
+
+        Ok(())
+    })
+}
+
+/// Write a patch to `patch_path` that rebuilds the archive of `new_handle`
+/// from the archive of `old_handle`. `level` may be null for the default zstd
+/// level. On success `out_json` receives the patch statistics as JSON.
+#[no_mangle]
+pub extern "C" fn engram_create_patch(
+    old_handle: *mut EngramArchiveHandle,
+    new_handle: *mut EngramArchiveHandle,
+    patch_path: *const c_char,
+    level: *const i32,
+    out_json: *mut *mut c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if old_handle.is_null() || new_handle.is_null() || out_json.is_null() {
+            return Err((ERR, "null pointer passed to create_patch".into()));
+        }
+        if old_handle == new_handle {
+            return Err((ERR, "cannot patch an archive handle against itself".into()));
+        }
+        let patch_path = unsafe { cstr_to_string(patch_path) }.map_err(|err| (ERR, err))?;
+        let options = PatchOptions {
+            level: if level.is_null() {
+                None
+            } else {
+                Some(unsafe { *level })
+            },
+        };
+
+        let (old, new) = unsafe { (&*old_handle, &*new_handle) };
+        let (mut old_reader, mut new_reader) = lock_readers(old, new)?;
+
+        let stats = patch::create_patch(
+            &mut old_reader,
+            &mut new_reader,
+            std::path::Path::new(&patch_path),
+            &options,
+        )
+        .map_err(|e| archive_error("failed to create patch", e))?;
+        let json = serde_json::to_string(&stats)
+            .map_err(|e| (ERR, format!("failed to serialize patch stats: {e}")))?;
+        let cstring = CString::new(json)
+            .map_err(|_| (ERR, "patch stats contain interior null byte".to_string()))?;
+
+        unsafe {
+            *out_json = cstring.into_raw();
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuild the archive described by the patch archive of `patch_handle` from
+/// the archive of `base_handle`, verifying every entry, and write it to
+/// `out_path`.
+#[no_mangle]
+pub extern "C" fn engram_apply_patch(
+    base_handle: *mut EngramArchiveHandle,
+    patch_handle: *mut EngramArchiveHandle,
+    out_path: *const c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if base_handle.is_null() || patch_handle.is_null() {
+            return Err((ERR, "null pointer passed to apply_patch".into()));
+        }
+        if base_handle == patch_handle {
+            return Err((ERR, "an archive cannot be its own patch".into()));
+        }
+        let out_path = unsafe { cstr_to_string(out_path) }.map_err(|err| (ERR, err))?;
+
+        let (base, patch_archive) = unsafe { (&*base_handle, &*patch_handle) };
+        let (mut base_reader, mut patch_reader) = lock_readers(base, patch_archive)?;
+
+        patch::apply_patch(
+            &mut base_reader,
+            &mut patch_reader,
+            std::path::Path::new(&out_path),
+        )
+        .map_err(|e| archive_error("failed to apply patch", e))?;
+
+        Ok(())
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+// Archive overlays
+// -------------------------------------------------------------------------------------------------
+
+fn lock_overlay<'a>(
+    handle: *mut EngramOverlayHandle,
+) -> Result<MutexGuard<'a, Overlay>, String> {
+    if handle.is_null() {
+        return Err("overlay handle cannot be null".into());
+    }
+
+    let handle: &'a EngramOverlayHandle = unsafe { &*handle };
+    handle
+        .overlay
+        .lock()
+        .map_err(|_| "overlay poisoned".to_string())
+}
+
+/// Open `count` archives as one overlay, the first of which takes precedence.
+/// A `.wh.<name>` whiteout entry in a layer hides `<name>` in the layers
+/// below it. Every layer is opened with `options`, as for
+/// `engram_open_archive_with_options`; it may be null for the defaults.
+#[no_mangle]
+pub extern "C" fn engram_open_overlay(
+    paths: *const *const c_char,
+    count: usize,
+    options: *const EngramOpenOptions,
+    out_handle: *mut *mut EngramOverlayHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if paths.is_null() || out_handle.is_null() {
+            return Err((ERR, "null pointer passed to open_overlay".into()));
+        }
+
+        let options = open_options(options)?;
+        let paths = unsafe { std::slice::from_raw_parts(paths, count) };
+        let mut layers = Vec::with_capacity(count);
+        for &path in paths {
+            let path = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
+            let layer = Archive::open_with(&path, &options)
+                .map_err(|e| archive_error(&format!("failed to open layer {path}"), e))?;
+            layers.push(layer);
+        }
+        let overlay =
+            Overlay::new(layers).map_err(|e| archive_error("failed to create overlay", e))?;
+
+        let handle = EngramOverlayHandle {
+            overlay: Arc::new(Mutex::new(overlay)),
+        };
+
+        unsafe {
+            *out_handle = Box::into_raw(Box::new(handle));
+        }
+
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_overlay_close(handle: *mut EngramOverlayHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    unsafe {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn engram_overlay_contains(
+    handle: *mut EngramOverlayHandle,
+    path: *const c_char,
+    out_result: *mut bool,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if out_result.is_null() {
+            return Err("null pointer passed to overlay_contains".into());
+        }
+
+        let query_path = unsafe { cstr_to_string(path)? };
+        let overlay = lock_overlay(handle)?;
 
-        let len = strings.len();
-        let data_ptr = if len == 0 {
-            ptr::null_mut()
+        unsafe {
+            *out_result = overlay.contains(&query_path);
+        }
+
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_overlay_list_files(
+    handle: *mut EngramOverlayHandle,
+    out_list: *mut EngramStringList,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if out_list.is_null() {
+            return Err("null pointer passed to overlay_list_files".into());
+        }
+
+        let overlay = lock_overlay(handle)?;
+        unsafe { write_string_list(out_list, &overlay.list_files()) }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_overlay_list_prefix(
+    handle: *mut EngramOverlayHandle,
+    prefix: *const c_char,
+    out_list: *mut EngramStringList,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if out_list.is_null() {
+            return Err("null pointer passed to overlay_list_prefix".into());
+        }
+
+        let prefix_str = unsafe { cstr_to_string(prefix)? };
+        let overlay = lock_overlay(handle)?;
+        unsafe { write_string_list(out_list, &overlay.list_prefix(&prefix_str)) }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_overlay_read_file(
+    handle: *mut EngramOverlayHandle,
+    path: *const c_char,
+    out_buffer: *mut EngramBuffer,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if out_buffer.is_null() {
+            return Err((ERR, "null pointer passed to overlay_read_file".into()));
+        }
+
+        let query_path = unsafe { cstr_to_string(path) }.map_err(|err| (ERR, err))?;
+        let mut overlay = lock_overlay(handle).map_err(|err| (ERR, err))?;
+        let data = overlay
+            .read_file(&query_path)
+            .map_err(|e| archive_error("failed to read file", e))?;
+
+        unsafe { write_buffer(out_buffer, data) };
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_overlay_get_metadata(
+    handle: *mut EngramOverlayHandle,
+    path: *const c_char,
+    out_json: *mut *mut c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if out_json.is_null() {
+            return Err("null pointer passed to overlay_get_metadata".into());
+        }
+
+        let query_path = unsafe { cstr_to_string(path)? };
+        let overlay = lock_overlay(handle)?;
+        let entry = overlay
+            .get_entry(&query_path)
+            .ok_or_else(|| format!("entry not found: {query_path}"))?;
+
+        unsafe {
+            *out_json = metadata_json(entry)?.into_raw();
+        }
+
+        Ok(())
+    })
+}
+
+/// Open a SQLite database from the layer that provides it.
+#[no_mangle]
+pub extern "C" fn engram_overlay_open_database(
+    handle: *mut EngramOverlayHandle,
+    db_path: *const c_char,
+    out_db: *mut *mut EngramDatabaseHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard_with_code(out_error, || {
+        if out_db.is_null() {
+            return Err((ERR, "null pointer passed to overlay_open_database".into()));
+        }
+
+        let db_path_str = unsafe { cstr_to_string(db_path) }.map_err(|err| (ERR, err))?;
+        let mut overlay = lock_overlay(handle).map_err(|err| (ERR, err))?;
+        let conn = overlay
+            .open_database(&db_path_str)
+            .map_err(|e| archive_error("failed to open database", e))?;
+        sql::register(&conn, unsafe { &*handle }.overlay.clone())
+            .map_err(|e| archive_error("failed to open database", e))?;
+
+        let handle = EngramDatabaseHandle {
+            conn: Arc::new(Mutex::new(conn)),
+        };
+
+        unsafe {
+            *out_db = Box::into_raw(Box::new(handle));
+        }
+
+        Ok(())
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+// Archive writer
+// -------------------------------------------------------------------------------------------------
+
+fn compression_from_code(code: c_int) -> Result<CompressionMethod, String> {
+    match code {
+        0 => Ok(CompressionMethod::None),
+        1 => Ok(CompressionMethod::Lz4),
+        2 => Ok(CompressionMethod::Zstd),
+        3 => Ok(CompressionMethod::Deflate),
+        other => Err(format!("unknown compression method: {other}")),
+    }
+}
+
+fn with_writer<T>(
+    handle: *mut EngramWriterHandle,
+    f: impl FnOnce(&mut Writer) -> Result<T, String>,
+) -> Result<T, String> {
+    if handle.is_null() {
+        return Err("writer handle cannot be null".into());
+    }
+
+    let handle = unsafe { &*handle };
+    let mut writer = handle
+        .writer
+        .lock()
+        .map_err(|_| "writer poisoned".to_string())?;
+    let writer = writer
+        .as_mut()
+        .ok_or_else(|| "writer already finalized".to_string())?;
+    f(writer)
+}
+
+fn writer_handle(
+    out_writer: *mut *mut EngramWriterHandle,
+    open: impl FnOnce() -> engram_common::Result<Writer>,
+) -> Result<(), String> {
+    sink_writer_handle(out_writer, None, open)
+}
+
+fn sink_writer_handle(
+    out_writer: *mut *mut EngramWriterHandle,
+    sink: Option<EngramByteSink>,
+    open: impl FnOnce() -> engram_common::Result<Writer>,
+) -> Result<(), String> {
+    if out_writer.is_null() {
+        return Err("out_writer pointer cannot be null".into());
+    }
+
+    let writer = open().map_err(|e| format!("failed to open writer: {e}"))?;
+    let handle = EngramWriterHandle {
+        writer: Mutex::new(Some(writer)),
+        sink,
+    };
+
+    unsafe {
+        *out_writer = Box::into_raw(Box::new(handle));
+    }
+
+    Ok(())
+}
+
+#[no_mangle]
+pub extern "C" fn engram_writer_create(
+    path: *const c_char,
+    out_writer: *mut *mut EngramWriterHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        writer_handle(out_writer, || Writer::create(&path_str, Default::default()))
+    })
+}
+
+/// Create a writer that encrypts every entry.
+///
+/// `cipher` is `ENGRAM_CIPHER_AES_256_GCM` or `ENGRAM_CIPHER_CHACHA20_POLY1305`,
+/// `key` must be 32 bytes and `key_id` may be null.
+#[no_mangle]
+pub extern "C" fn engram_writer_create_encrypted(
+    path: *const c_char,
+    cipher: c_int,
+    key: *const u8,
+    key_len: usize,
+    key_id: *const c_char,
+    out_writer: *mut *mut EngramWriterHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        let cipher = match cipher {
+            0 => Cipher::Aes256Gcm,
+            1 => Cipher::ChaCha20Poly1305,
+            other => return Err(format!("unknown cipher: {other}")),
+        };
+        let key = unsafe { bytes_from_raw(key, key_len)? };
+        let key_id = if key_id.is_null() {
+            None
         } else {
-            let boxed = strings.into_boxed_slice();
-            Box::into_raw(boxed) as *mut *mut c_char
+            Some(unsafe { cstr_to_string(key_id)? })
+        };
+        let key = EncryptionKey::new(key, key_id).map_err(|e| e.to_string())?;
+
+        let options = WriterOptions {
+            encryption: Some(EncryptionOptions { cipher, key }),
+            ..Default::default()
         };
+        writer_handle(out_writer, || Writer::create(&path_str, options))
+    })
+}
+
+/// Create a writer that builds the archive in memory and passes it to `sink`
+/// when it is finalized.
+///
+/// This buffers rather than streams: the archive index is written last, so
+/// the whole archive is kept in memory and read back into one buffer before
+/// the first call to `write`. Peak memory is about twice the archive size;
+/// write to a path with `engram_writer_create` for archives that may not fit.
+///
+/// The sink is copied; its context must stay valid until
+/// `engram_writer_finalize` returns. Nothing is written if the writer is
+/// freed without being finalized. Only supported on Linux.
+#[no_mangle]
+pub extern "C" fn engram_writer_create_to_sink(
+    sink: *const EngramByteSink,
+    out_writer: *mut *mut EngramWriterHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if sink.is_null() {
+            return Err("sink cannot be null".into());
+        }
+
+        let sink = unsafe { *sink };
+        if sink.write.is_none() {
+            return Err("byte sink has no write callback".into());
+        }
+        sink_writer_handle(out_writer, Some(sink), || {
+            Writer::create_in_memory(Default::default())
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_writer_open_for_update(
+    path: *const c_char,
+    out_writer: *mut *mut EngramWriterHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        writer_handle(out_writer, || {
+            Writer::open_for_update(&path_str, Default::default())
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_writer_add_file(
+    handle: *mut EngramWriterHandle,
+    path: *const c_char,
+    data: *const u8,
+    len: usize,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        let bytes = unsafe { bytes_from_raw(data, len)? };
+        with_writer(handle, |writer| {
+            writer
+                .add_file(&path_str, bytes)
+                .map_err(|e| format!("failed to add file: {e}"))
+        })
+    })
+}
+
+/// `level` may be null to use the method's default level.
+#[no_mangle]
+pub extern "C" fn engram_writer_add_file_with_compression(
+    handle: *mut EngramWriterHandle,
+    path: *const c_char,
+    data: *const u8,
+    len: usize,
+    compression: c_int,
+    level: *const i32,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        let bytes = unsafe { bytes_from_raw(data, len)? };
+        let method = compression_from_code(compression)?;
+        let level = if level.is_null() {
+            None
+        } else {
+            Some(unsafe { *level })
+        };
+        with_writer(handle, |writer| {
+            writer
+                .add_file_with_compression(&path_str, bytes, method, level)
+                .map_err(|e| format!("failed to add file: {e}"))
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_writer_add_file_from_disk(
+    handle: *mut EngramWriterHandle,
+    path: *const c_char,
+    disk_path: *const c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        let disk_path_str = unsafe { cstr_to_string(disk_path)? };
+        with_writer(handle, |writer| {
+            writer
+                .add_file_from_disk(&path_str, std::path::Path::new(&disk_path_str))
+                .map_err(|e| format!("failed to add file from disk: {e}"))
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_writer_add_manifest(
+    handle: *mut EngramWriterHandle,
+    manifest_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let json_str = unsafe { cstr_to_string(manifest_json)? };
+        let manifest: serde_json::Value = serde_json::from_str(&json_str)
+            .map_err(|e| format!("failed to parse manifest: {e}"))?;
+        with_writer(handle, |writer| {
+            writer
+                .add_manifest(&manifest)
+                .map_err(|e| format!("failed to add manifest: {e}"))
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn engram_writer_delete_file(
+    handle: *mut EngramWriterHandle,
+    path: *const c_char,
+    out_deleted: *mut bool,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if out_deleted.is_null() {
+            return Err("null pointer passed to delete_file".into());
+        }
+
+        let path_str = unsafe { cstr_to_string(path)? };
+        let deleted = with_writer(handle, |writer| {
+            writer
+                .delete_file(&path_str)
+                .map_err(|e| format!("failed to delete file: {e}"))
+        })?;
 
         unsafe {
-            (*out_list).data = data_ptr;
-            (*out_list).len = len;
+            *out_deleted = deleted;
         }
 
         Ok(())
     })
 }
 
+/// Add a whiteout entry hiding `path` in the layers below this archive when
+/// it is opened with `engram_open_overlay`.
+#[no_mangle]
+pub extern "C" fn engram_writer_add_whiteout(
+    handle: *mut EngramWriterHandle,
+    path: *const c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let path_str = unsafe { cstr_to_string(path)? };
+        with_writer(handle, |writer| {
+            writer
+                .add_whiteout(&path_str)
+                .map_err(|e| format!("failed to add whiteout: {e}"))
+        })
+    })
+}
+
+/// Sign the archive with a 32-byte Ed25519 secret key when it is finalized.
+#[no_mangle]
+pub extern "C" fn engram_writer_sign(
+    handle: *mut EngramWriterHandle,
+    secret_key: *const u8,
+    key_len: usize,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        let key = unsafe { bytes_from_raw(secret_key, key_len)? };
+        with_writer(handle, |writer| {
+            writer
+                .sign_with(key)
+                .map_err(|e| format!("failed to set signing key: {e}"))
+        })
+    })
+}
+
+/// Finish the archive and atomically move it to its target path, or write it
+/// to the sink of a writer from `engram_writer_create_to_sink`. The handle
+/// must still be released with `engram_writer_free`.
+#[no_mangle]
+pub extern "C" fn engram_writer_finalize(
+    handle: *mut EngramWriterHandle,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    ffi_guard(out_error, || {
+        if handle.is_null() {
+            return Err("writer handle cannot be null".into());
+        }
+
+        let handle = unsafe { &*handle };
+        let writer = handle
+            .writer
+            .lock()
+            .map_err(|_| "writer poisoned".to_string())?
+            .take()
+            .ok_or_else(|| "writer already finalized".to_string())?;
+
+        match &handle.sink {
+            Some(sink) => {
+                let (_, data) = writer
+                    .finalize_to_bytes()
+                    .map_err(|e| format!("failed to finalize archive: {e}"))?;
+                sink.write_all(&data)
+            }
+            None => writer
+                .finalize()
+                .map(|_| ())
+                .map_err(|e| format!("failed to finalize archive: {e}")),
+        }
+    })
+}
+
+/// Release a writer. An archive that was not finalized is discarded.
+#[no_mangle]
+pub extern "C" fn engram_writer_free(handle: *mut EngramWriterHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    unsafe {
+        drop(Box::from_raw(handle));
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // SQLite database access
 // -------------------------------------------------------------------------------------------------
@@ -489,17 +1438,21 @@ pub extern "C" fn engram_archive_open_database(
     out_db: *mut *mut EngramDatabaseHandle,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_db.is_null() {
-            return Err("null pointer passed to open_database".into());
+            return Err((ERR, "null pointer passed to open_database".into()));
         }
 
-        let db_path_str = unsafe { cstr_to_string(db_path)? };
+        let db_path_str = unsafe { cstr_to_string(db_path) }.map_err(|err| (ERR, err))?;
         let archive = unsafe { &*handle };
-        let vfs = EngramVfs::new(&archive.path);
-        let conn = vfs
-            .open_database(&db_path_str)
-            .map_err(|e| format!("failed to open database: {e}"))?;
+        let mut reader = archive
+            .reader
+            .lock()
+            .map_err(|_| (ERR, "reader poisoned".to_string()))?;
+        let conn = database::open_database(&mut reader, &db_path_str)
+            .map_err(|e| archive_error("failed to open database", e))?;
+        sql::register(&conn, archive.reader.clone())
+            .map_err(|e| archive_error("failed to open database", e))?;
 
         let handle = EngramDatabaseHandle {
             conn: Arc::new(Mutex::new(conn)),
@@ -532,30 +1485,31 @@ pub extern "C" fn engram_database_query(
     out_json: *mut *mut c_char,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_json.is_null() {
-            return Err("null pointer passed to database_query".into());
+            return Err((ERR, "null pointer passed to database_query".into()));
         }
 
-        let sql_str = unsafe { cstr_to_string(sql)? };
+        let sql_str = unsafe { cstr_to_string(sql) }.map_err(|err| (ERR, err))?;
         let params_str = if params_json.is_null() {
             None
         } else {
-            Some(unsafe { cstr_to_string(params_json)? })
+            Some(unsafe { cstr_to_string(params_json) }.map_err(|err| (ERR, err))?)
         };
 
         let db = unsafe { &*handle };
         let conn = db
             .conn
             .lock()
-            .map_err(|_| "database connection poisoned".to_string())?;
+            .map_err(|_| (ERR, "database connection poisoned".to_string()))?;
 
         let mut stmt = conn
             .prepare(&sql_str)
-            .map_err(|e| format!("failed to prepare statement: {e}"))?;
+            .map_err(|e| (ERR, format!("failed to prepare statement: {e}")))?;
 
         let param_values: Vec<serde_json::Value> = if let Some(params) = params_str {
-            serde_json::from_str(&params).map_err(|e| format!("failed to parse params: {e}"))?
+            serde_json::from_str(&params)
+                .map_err(|e| (ERR, format!("failed to parse params: {e}")))?
         } else {
             Vec::new()
         };
@@ -582,15 +1536,17 @@ pub extern "C" fn engram_database_query(
                 }
                 Ok(serde_json::Value::Object(obj))
             })
-            .map_err(|e| format!("query failed: {e}"))?;
+            .map_err(|e| statement_error("query failed", e))?;
 
         let results: Vec<serde_json::Value> =
-            rows.collect::<Result<Vec<_>, _>>().map_err(|e| format!("{e}"))?;
+            rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| statement_error("query failed", e))?;
 
         let json = serde_json::to_string(&results)
-            .map_err(|e| format!("failed to serialize results: {e}"))?;
+            .map_err(|e| (ERR, format!("failed to serialize results: {e}")))?;
 
-        let cstring = CString::new(json).map_err(|_| "query results contain null byte".to_string())?;
+        let cstring = CString::new(json)
+            .map_err(|_| (ERR, "query results contain null byte".to_string()))?;
 
         unsafe {
             *out_json = cstring.into_raw();
@@ -608,26 +1564,27 @@ pub extern "C" fn engram_database_execute(
     out_rows: *mut i64,
     out_error: *mut *mut c_char,
 ) -> c_int {
-    ffi_guard(out_error, || {
+    ffi_guard_with_code(out_error, || {
         if handle.is_null() || out_rows.is_null() {
-            return Err("null pointer passed to database_execute".into());
+            return Err((ERR, "null pointer passed to database_execute".into()));
         }
 
-        let sql_str = unsafe { cstr_to_string(sql)? };
+        let sql_str = unsafe { cstr_to_string(sql) }.map_err(|err| (ERR, err))?;
         let params_str = if params_json.is_null() {
             None
         } else {
-            Some(unsafe { cstr_to_string(params_json)? })
+            Some(unsafe { cstr_to_string(params_json) }.map_err(|err| (ERR, err))?)
         };
 
         let db = unsafe { &*handle };
         let conn = db
             .conn
             .lock()
-            .map_err(|_| "database connection poisoned".to_string())?;
+            .map_err(|_| (ERR, "database connection poisoned".to_string()))?;
 
         let param_values: Vec<serde_json::Value> = if let Some(params) = params_str {
-            serde_json::from_str(&params).map_err(|e| format!("failed to parse params: {e}"))?
+            serde_json::from_str(&params)
+                .map_err(|e| (ERR, format!("failed to parse params: {e}")))?
         } else {
             Vec::new()
         };
@@ -642,7 +1599,7 @@ pub extern "C" fn engram_database_execute(
 
         let changed = conn
             .execute(&sql_str, param_refs.as_slice())
-            .map_err(|e| format!("execute failed: {e}"))?;
+            .map_err(|e| statement_error("execute failed", e))?;
 
         unsafe {
             *out_rows = changed as i64;
@@ -691,7 +1648,10 @@ pub extern "C" fn engram_string_list_free(list: EngramStringList) {
                 drop(CString::from_raw(*ptr));
             }
         }
-        drop(Box::from_raw(list.data));
+        // The list was allocated as a boxed slice of `len` pointers.
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            list.data, list.len,
+        )));
     }
 }
 
@@ -2,12 +2,76 @@
 //!
 //! NAPI-RS bindings for accessing .eng archives from Node.js/TypeScript
 
-use engram_core::{ArchiveReader, ArchiveWriter, CompressionMethod as CoreCompressionMethod};
-use engram_vfs::EngramVfs;
+use engram_common::{
+    database, diff, patch, policy, signature, sql, Archive, DictionaryOptions as CommonDictionaryOptions, EntryInfo,
+    Overlay, Writer,
+};
+use engram_core::CompressionMethod as CoreCompressionMethod;
 use napi::bindgen_prelude::*;
+use napi::{Env, JsBigInt, JsBuffer, JsError, JsObject, JsUnknown, Ref, Task, ValueType};
 use napi_derive::napi;
+use rusqlite::functions::{Aggregate, Context as FunctionContext, FunctionFlags};
 use rusqlite::Connection;
-use std::sync::{Arc, Mutex};
+use std::cell::Cell;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+
+/// `code` of errors raised for archives whose signature is missing,
+/// untrusted or invalid
+const SIGNATURE_ERROR_CODE: &str = "ERR_ENGRAM_SIGNATURE";
+const LIMIT_ERROR_CODE: &str = "ERR_ENGRAM_LIMIT";
+
+/// Convert an archive error, keeping a dedicated `code` for signature and
+/// resource limit failures
+fn archive_error(context: &str, e: engram_common::Error) -> Error<String> {
+    let code = match e {
+        engram_common::Error::Signature(_) => SIGNATURE_ERROR_CODE,
+        engram_common::Error::Limit(_) => LIMIT_ERROR_CODE,
+        _ => Status::GenericFailure.as_ref(),
+    };
+    Error::new(code.to_string(), format!("{}: {}", context, e))
+}
+
+/// Archive operation run on the libuv thread pool.
+///
+/// Errors of an `async fn` only carry a `Status`, so failures are converted
+/// to JavaScript errors here instead, keeping the `code` set by
+/// [`archive_error`].
+pub struct ArchiveTask<T: Send + 'static> {
+    context: &'static str,
+    work: Option<Box<dyn FnOnce() -> engram_common::Result<T> + Send>>,
+}
+
+impl<T: ToNapiValue + TypeName + Send + 'static> ArchiveTask<T> {
+    fn new(
+        context: &'static str,
+        work: impl FnOnce() -> engram_common::Result<T> + Send + 'static,
+    ) -> AsyncTask<Self> {
+        AsyncTask::new(Self {
+            context,
+            work: Some(Box::new(work)),
+        })
+    }
+}
+
+impl<T: ToNapiValue + TypeName + Send + 'static> Task for ArchiveTask<T> {
+    type Output = engram_common::Result<T>;
+    type JsValue = T;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let work = self
+            .work
+            .take()
+            .ok_or_else(|| Error::from_reason("Task already ran"))?;
+        Ok(work())
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<T> {
+        output.map_err(|e| {
+            Error::from(JsError::from(archive_error(self.context, e)).into_unknown(env))
+        })
+    }
+}
 
 /// Compression method enum exposed to JavaScript
 #[napi]
@@ -29,6 +93,245 @@ impl From<CompressionMethod> for CoreCompressionMethod {
     }
 }
 
+/// Compression method applied to files with matching extensions
+#[napi(object)]
+pub struct CompressionRule {
+    /// File extensions without the leading dot, e.g. `["png", "jpg"]`
+    pub extensions: Vec<String>,
+    pub compression: CompressionMethod,
+}
+
+/// Declarative policy used to pick compression for each added file
+#[napi(object)]
+pub struct CompressionPolicy {
+    /// Extension rules checked in order (replaces the built-in rules when set)
+    pub rules: Option<Vec<CompressionRule>>,
+    /// Compression for files no rule matches (engram-core decides when unset)
+    pub default_compression: Option<CompressionMethod>,
+    /// Files smaller than this many bytes are stored uncompressed
+    pub store_below: Option<i64>,
+    /// Store files whose sampled entropy (bits per byte) reaches this value
+    pub entropy_threshold: Option<f64>,
+    /// Number of bytes sampled by the entropy check
+    pub entropy_sample_size: Option<u32>,
+    /// Disable the entropy check when `false`
+    pub sample_entropy: Option<bool>,
+}
+
+impl From<CompressionPolicy> for policy::CompressionPolicy {
+    fn from(options: CompressionPolicy) -> Self {
+        let mut policy = policy::CompressionPolicy::default();
+
+        if let Some(rules) = options.rules {
+            policy.rules = rules
+                .into_iter()
+                .map(|rule| policy::CompressionRule::new(rule.extensions, rule.compression.into()))
+                .collect();
+        }
+        if let Some(method) = options.default_compression {
+            policy.default_compression = Some(method.into());
+        }
+        if let Some(store_below) = options.store_below {
+            policy.store_below = store_below.max(0) as u64;
+        }
+        if let Some(threshold) = options.entropy_threshold {
+            policy.entropy_threshold = Some(threshold);
+        }
+        if let Some(sample_size) = options.entropy_sample_size {
+            policy.sample_size = sample_size as usize;
+        }
+        if options.sample_entropy == Some(false) {
+            policy.entropy_threshold = None;
+        }
+
+        policy
+    }
+}
+
+/// Shared zstd dictionary trained from the first entries written
+#[napi(object)]
+pub struct DictionaryOptions {
+    /// Maximum dictionary size in bytes (default 110 KiB)
+    pub max_size: Option<u32>,
+    /// Number of entries sampled before training (default 1000)
+    pub sample_count: Option<u32>,
+    /// Entries larger than this are compressed without the dictionary (default 64 KiB)
+    pub max_entry_size: Option<u32>,
+    /// Extensions eligible for the dictionary (all when unset)
+    pub extensions: Option<Vec<String>>,
+}
+
+impl From<DictionaryOptions> for CommonDictionaryOptions {
+    fn from(options: DictionaryOptions) -> Self {
+        let mut dictionary = CommonDictionaryOptions::default();
+
+        if let Some(max_size) = options.max_size {
+            dictionary.max_size = max_size as usize;
+        }
+        if let Some(sample_count) = options.sample_count {
+            dictionary.sample_count = sample_count as usize;
+        }
+        if let Some(max_entry_size) = options.max_entry_size {
+            dictionary.max_entry_size = max_entry_size as usize;
+        }
+        if let Some(extensions) = options.extensions {
+            dictionary.extensions = extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect();
+        }
+
+        dictionary
+    }
+}
+
+/// Options accepted when creating an archive writer
+#[napi(object)]
+pub struct WriterOptions {
+    /// Pick compression automatically for `addFile`, `addFileFromDisk` and `addDirectory`
+    pub compression_policy: Option<CompressionPolicy>,
+    /// Compression level for zstd (1-22) and deflate (0-9) entries
+    pub compression_level: Option<i32>,
+    /// Train a zstd dictionary from the written entries and store it in the archive
+    pub dictionary: Option<DictionaryOptions>,
+    /// Store byte-identical files once, sharing their data between paths
+    pub deduplicate: Option<bool>,
+    /// Encrypt every entry with AES-256-GCM or ChaCha20-Poly1305
+    pub encryption: Option<EncryptionOptions>,
+}
+
+impl TryFrom<WriterOptions> for engram_common::WriterOptions {
+    type Error = Error;
+
+    fn try_from(options: WriterOptions) -> Result<Self> {
+        let encryption = options
+            .encryption
+            .map(|encryption| {
+                Ok::<_, Error>(engram_common::EncryptionOptions {
+                    cipher: encryption.cipher.into(),
+                    key: encryption_key(&encryption.key, encryption.key_id)?,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            policy: options.compression_policy.map(Into::into),
+            level: options.compression_level,
+            dictionary: options.dictionary.map(Into::into),
+            deduplicate: options.deduplicate.unwrap_or(false),
+            encryption,
+        })
+    }
+}
+
+/// Cipher used to encrypt archive entries
+#[napi]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl From<Cipher> for engram_common::Cipher {
+    fn from(cipher: Cipher) -> Self {
+        match cipher {
+            Cipher::Aes256Gcm => engram_common::Cipher::Aes256Gcm,
+            Cipher::ChaCha20Poly1305 => engram_common::Cipher::ChaCha20Poly1305,
+        }
+    }
+}
+
+/// Encryption settings for a writer
+#[napi(object)]
+pub struct EncryptionOptions {
+    pub cipher: Cipher,
+    /// 256-bit key (32 bytes)
+    pub key: Buffer,
+    /// Identifier stored with each entry to select the key when reading
+    pub key_id: Option<String>,
+}
+
+/// Key used to decrypt archive entries
+#[napi(object)]
+pub struct EncryptionKey {
+    /// 256-bit key (32 bytes)
+    pub key: Buffer,
+    /// Identifier matching the `keyId` the entries were written with
+    pub key_id: Option<String>,
+}
+
+fn encryption_key(key: &[u8], key_id: Option<String>) -> Result<engram_common::EncryptionKey> {
+    engram_common::EncryptionKey::new(key, key_id)
+        .map_err(|e| Error::from_reason(format!("Invalid encryption key: {}", e)))
+}
+
+/// Summary returned by `EngramWriter.finalize`
+#[napi(object)]
+pub struct FinalizeStats {
+    /// Number of entries in the archive
+    pub entries: u32,
+    /// Number of entries sharing the data of an identical entry
+    pub deduplicated_entries: u32,
+    /// Uncompressed bytes not stored thanks to deduplication
+    pub bytes_saved: i64,
+}
+
+impl From<engram_common::FinalizeStats> for FinalizeStats {
+    fn from(stats: engram_common::FinalizeStats) -> Self {
+        Self {
+            entries: stats.entries as u32,
+            deduplicated_entries: stats.deduplicated as u32,
+            bytes_saved: stats.bytes_saved as i64,
+        }
+    }
+}
+
+/// Options accepted when opening an archive
+#[napi(object)]
+pub struct OpenOptions {
+    /// Ed25519 public keys (32 bytes each) allowed to have signed the archive
+    pub trusted_keys: Option<Vec<Buffer>>,
+    /// Reject archives that are not signed
+    pub require_signature: Option<bool>,
+    /// Keyring used to decrypt encrypted entries
+    pub keys: Option<Vec<EncryptionKey>>,
+    /// Resource limits for archives from untrusted sources
+    pub limits: Option<ArchiveLimits>,
+}
+
+/// Resource limits enforced while an archive is open
+#[napi(object)]
+pub struct ArchiveLimits {
+    /// Maximum number of entries
+    pub max_entries: Option<u32>,
+    /// Maximum length of an entry path in bytes
+    pub max_path_length: Option<u32>,
+    /// Maximum uncompressed size of a single entry
+    pub max_entry_size: Option<i64>,
+    /// Maximum ratio of uncompressed to stored size of an entry
+    pub max_compression_ratio: Option<f64>,
+    /// Maximum number of uncompressed bytes read while the archive is open
+    pub max_total_bytes: Option<i64>,
+}
+
+impl From<ArchiveLimits> for engram_common::Limits {
+    fn from(limits: ArchiveLimits) -> Self {
+        Self {
+            max_entries: limits.max_entries.map(|max| max as usize),
+            max_path_length: limits.max_path_length.map(|max| max as usize),
+            max_entry_size: limits.max_entry_size.map(|max| max.max(0) as u64),
+            max_compression_ratio: limits.max_compression_ratio,
+            max_total_bytes: limits.max_total_bytes.map(|max| max.max(0) as u64),
+        }
+    }
+}
+
+/// Options accepted when finalizing an archive
+#[napi(object)]
+pub struct FinalizeOptions {
+    /// Ed25519 secret key (32 bytes) to sign the archive with
+    pub signing_key: Option<Buffer>,
+}
+
 /// Archive entry metadata
 #[napi(object)]
 pub struct EntryMetadata {
@@ -36,27 +339,92 @@ pub struct EntryMetadata {
     pub uncompressed_size: i64,
     pub compressed_size: i64,
     pub compression_method: String,
+    /// Compression level, when the entry was written with an explicit level
+    pub compression_level: Option<i32>,
+    /// Path of the identical entry whose data this entry shares
+    pub alias_of: Option<String>,
+    /// Whether the entry is encrypted
+    pub encrypted: bool,
+    /// Hex-encoded SHA-256 of the file contents
+    pub hash: Option<String>,
     pub modified_time: i64,
 }
 
+impl From<EntryInfo> for EntryMetadata {
+    fn from(entry: EntryInfo) -> Self {
+        Self {
+            path: entry.path,
+            uncompressed_size: entry.uncompressed_size as i64,
+            compressed_size: entry.compressed_size as i64,
+            compression_method: format!("{:?}", entry.compression),
+            compression_level: entry.compression_level,
+            alias_of: entry.alias_of,
+            encrypted: entry.encrypted,
+            hash: entry.sha256,
+            modified_time: entry.modified_time as i64,
+        }
+    }
+}
+
+fn open_options(options: Option<OpenOptions>) -> Result<engram_common::OpenOptions, String> {
+    let open_error = |e| archive_error("Failed to open archive", e);
+
+    let mut open_options = engram_common::OpenOptions::default();
+    if let Some(options) = options {
+        for key in options.trusted_keys.unwrap_or_default() {
+            open_options
+                .trusted_keys
+                .push(signature::public_key(&key).map_err(open_error)?);
+        }
+        open_options.require_signature = options.require_signature.unwrap_or(false);
+        for key in options.keys.unwrap_or_default() {
+            open_options.keys.push(
+                engram_common::EncryptionKey::new(&key.key, key.key_id).map_err(open_error)?,
+            );
+        }
+        if let Some(limits) = options.limits {
+            open_options.limits = limits.into();
+        }
+    }
+
+    Ok(open_options)
+}
+
 /// Engram archive reader for accessing files and databases
 #[napi]
 pub struct EngramArchive {
-    inner: Arc<Mutex<ArchiveReader>>,
-    path: String,
+    inner: Arc<Mutex<Archive>>,
 }
 
 #[napi]
 impl EngramArchive {
-    /// Open an existing archive file
+    /// Open an existing archive file, optionally requiring a trusted signature
+    ///
+    /// Signature failures are raised with `code` set to `ERR_ENGRAM_SIGNATURE`,
+    /// exceeded limits with `ERR_ENGRAM_LIMIT`.
     #[napi(constructor)]
-    pub fn new(path: String) -> Result<Self> {
-        let reader = ArchiveReader::open(&path)
-            .map_err(|e| Error::from_reason(format!("Failed to open archive: {}", e)))?;
+    pub fn new(path: String, options: Option<OpenOptions>) -> Result<Self, String> {
+        let open_options = open_options(options)?;
+        let reader = Archive::open_with(&path, &open_options)
+            .map_err(|e| archive_error("Failed to open archive", e))?;
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(reader)),
+        })
+    }
+
+    /// Open an archive from a buffer
+    ///
+    /// The buffer is copied, so it can be reused once this returns. Only
+    /// supported on Linux, where the copy stays in memory.
+    #[napi(factory)]
+    pub fn from_buffer(data: Buffer, options: Option<OpenOptions>) -> Result<Self, String> {
+        let open_options = open_options(options)?;
+        let reader = Archive::open_memory(&data, &open_options)
+            .map_err(|e| archive_error("Failed to open archive", e))?;
 
         Ok(Self {
             inner: Arc::new(Mutex::new(reader)),
-            path,
         })
     }
 
@@ -85,77 +453,66 @@ impl EngramArchive {
     #[napi]
     pub fn get_metadata(&self, path: String) -> Result<Option<EntryMetadata>> {
         let reader = self.inner.lock().unwrap();
-        match reader.get_entry(&path) {
-            Some(entry) => Ok(Some(EntryMetadata {
-                path: entry.path.clone(),
-                uncompressed_size: entry.uncompressed_size as i64,
-                compressed_size: entry.compressed_size as i64,
-                compression_method: format!("{:?}", entry.compression),
-                modified_time: entry.modified_time as i64,
-            })),
-            None => Ok(None),
-        }
+        Ok(reader.get_entry(&path).map(Into::into))
     }
 
     /// Read a file from the archive (synchronous)
     #[napi]
-    pub fn read_file_sync(&self, path: String) -> Result<Buffer> {
+    pub fn read_file_sync(&self, path: String) -> Result<Buffer, String> {
         let mut reader = self.inner.lock().unwrap();
         let data = reader
             .read_file(&path)
-            .map_err(|e| Error::from_reason(format!("Failed to read file: {}", e)))?;
+            .map_err(|e| archive_error("Failed to read file", e))?;
 
         Ok(data.into())
     }
 
     /// Read a file from the archive (asynchronous)
-    #[napi]
-    pub async fn read_file(&self, path: String) -> Result<Buffer> {
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn read_file(&self, path: String) -> AsyncTask<ArchiveTask<Buffer>> {
         let inner = self.inner.clone();
-        tokio::task::spawn_blocking(move || {
+        ArchiveTask::new("Failed to read file", move || {
             let mut reader = inner.lock().unwrap();
-            reader
-                .read_file(&path)
-                .map_err(|e| Error::from_reason(format!("Failed to read file: {}", e)))
+            reader.read_file(&path).map(Buffer::from)
         })
-        .await
-        .map_err(|e| Error::from_reason(format!("Task failed: {}", e)))?
-        .map(|v| v.into())
     }
 
-    /// Read multiple files from the archive (batch operation)
-    #[napi]
-    pub async fn read_files(&self, paths: Vec<String>) -> Result<Vec<Buffer>> {
+    /// Hex-encoded SHA-256 of a file, verified against the recorded hash
+    #[napi(ts_return_type = "Promise<string>")]
+    pub fn hash_file(&self, path: String) -> AsyncTask<ArchiveTask<String>> {
         let inner = self.inner.clone();
-        tokio::task::spawn_blocking(move || {
+        ArchiveTask::new("Failed to hash file", move || {
             let mut reader = inner.lock().unwrap();
-            let mut results = Vec::with_capacity(paths.len());
-
-            for path in paths {
-                let data = reader
-                    .read_file(&path)
-                    .map_err(|e| Error::from_reason(format!("Failed to read {}: {}", path, e)))?;
-                results.push(data.into());
-            }
+            reader.hash_file(&path)
+        })
+    }
 
-            Ok(results)
+    /// Read multiple files from the archive (batch operation)
+    #[napi(ts_return_type = "Promise<Buffer[]>")]
+    pub fn read_files(&self, paths: Vec<String>) -> AsyncTask<ArchiveTask<Vec<Buffer>>> {
+        let inner = self.inner.clone();
+        ArchiveTask::new("Failed to read files", move || {
+            let mut reader = inner.lock().unwrap();
+            paths
+                .iter()
+                .map(|path| reader.read_file(path).map(Buffer::from))
+                .collect()
         })
-        .await
-        .map_err(|e| Error::from_reason(format!("Task failed: {}", e)))?
     }
 
     /// Read and parse manifest.json (returns JSON string)
     #[napi]
-    pub fn read_manifest(&self) -> Result<Option<String>> {
+    pub fn read_manifest(&self) -> Result<Option<String>, String> {
         let mut reader = self.inner.lock().unwrap();
         let manifest = reader
             .read_manifest()
-            .map_err(|e| Error::from_reason(format!("Failed to read manifest: {}", e)))?;
+            .map_err(|e| archive_error("Failed to read manifest", e))?;
 
         match manifest {
             Some(value) => {
-                let json_str = serde_json::to_string(&value)
-                    .map_err(|e| Error::from_reason(format!("Failed to serialize manifest: {}", e)))?;
+                let json_str = serde_json::to_string(&value).map_err(|e| {
+                    archive_error("Failed to serialize manifest", e.into())
+                })?;
                 Ok(Some(json_str))
             }
             None => Ok(None),
@@ -166,16 +523,121 @@ impl EngramArchive {
     #[napi]
     pub fn list_prefix(&self, prefix: String) -> Result<Vec<String>> {
         let reader = self.inner.lock().unwrap();
-        Ok(reader.list_prefix(&prefix).into_iter().map(|s| s.clone()).collect())
+        Ok(reader.list_prefix(&prefix))
     }
 
     /// Open a SQLite database from the archive
+    ///
+    /// Encrypted databases are decrypted into a read-only in-memory copy.
     #[napi]
-    pub fn open_database(&self, db_path: String) -> Result<EngramDatabase> {
-        let vfs = EngramVfs::new(&self.path);
-        let conn = vfs
+    pub fn open_database(&self, db_path: String) -> Result<EngramDatabase, String> {
+        let mut reader = self.inner.lock().unwrap();
+        let conn = database::open_database(&mut reader, &db_path)
+            .map_err(|e| archive_error("Failed to open database", e))?;
+        sql::register(&conn, self.inner.clone())
+            .map_err(|e| archive_error("Failed to open database", e))?;
+
+        Ok(EngramDatabase {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+/// Union view over archives, the first of which takes precedence
+///
+/// A `.wh.<name>` whiteout entry in a layer hides `<name>` in the layers
+/// below it.
+#[napi]
+pub struct EngramOverlay {
+    inner: Arc<Mutex<Overlay>>,
+}
+
+#[napi]
+impl EngramOverlay {
+    /// Open every layer with the same options
+    #[napi(constructor)]
+    pub fn new(layers: Vec<String>, options: Option<OpenOptions>) -> Result<Self, String> {
+        let open_options = open_options(options)?;
+        let archives = layers
+            .iter()
+            .map(|path| {
+                Archive::open_with(path, &open_options)
+                    .map_err(|e| archive_error(&format!("Failed to open layer {}", path), e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let overlay = Overlay::new(archives)
+            .map_err(|e| archive_error("Failed to create overlay", e))?;
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(overlay)),
+        })
+    }
+
+    /// List the visible files of every layer
+    #[napi]
+    pub fn list_files(&self) -> Result<Vec<String>> {
+        let overlay = self.inner.lock().unwrap();
+        Ok(overlay.list_files())
+    }
+
+    /// List visible files with a given prefix
+    #[napi]
+    pub fn list_prefix(&self, prefix: String) -> Result<Vec<String>> {
+        let overlay = self.inner.lock().unwrap();
+        Ok(overlay.list_prefix(&prefix))
+    }
+
+    /// Check if a file is visible in any layer
+    #[napi]
+    pub fn contains(&self, path: String) -> Result<bool> {
+        let overlay = self.inner.lock().unwrap();
+        Ok(overlay.contains(&path))
+    }
+
+    /// Index of the layer a file is read from, or null if it is not visible
+    #[napi]
+    pub fn layer_of(&self, path: String) -> Result<Option<u32>> {
+        let overlay = self.inner.lock().unwrap();
+        Ok(overlay.layer_of(&path).map(|layer| layer as u32))
+    }
+
+    /// Get metadata for a file from the layer that provides it
+    #[napi]
+    pub fn get_metadata(&self, path: String) -> Result<Option<EntryMetadata>> {
+        let overlay = self.inner.lock().unwrap();
+        Ok(overlay.get_entry(&path).map(Into::into))
+    }
+
+    /// Read a file from the layer that provides it (synchronous)
+    #[napi]
+    pub fn read_file_sync(&self, path: String) -> Result<Buffer, String> {
+        let mut overlay = self.inner.lock().unwrap();
+        let data = overlay
+            .read_file(&path)
+            .map_err(|e| archive_error("Failed to read file", e))?;
+
+        Ok(data.into())
+    }
+
+    /// Read a file from the layer that provides it (asynchronous)
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn read_file(&self, path: String) -> AsyncTask<ArchiveTask<Buffer>> {
+        let inner = self.inner.clone();
+        ArchiveTask::new("Failed to read file", move || {
+            let mut overlay = inner.lock().unwrap();
+            overlay.read_file(&path).map(Buffer::from)
+        })
+    }
+
+    /// Open a SQLite database from the layer that provides it
+    #[napi]
+    pub fn open_database(&self, db_path: String) -> Result<EngramDatabase, String> {
+        let mut overlay = self.inner.lock().unwrap();
+        let conn = overlay
             .open_database(&db_path)
-            .map_err(|e| Error::from_reason(format!("Failed to open database: {}", e)))?;
+            .map_err(|e| archive_error("Failed to open database", e))?;
+        sql::register(&conn, self.inner.clone())
+            .map_err(|e| archive_error("Failed to open database", e))?;
 
         Ok(EngramDatabase {
             conn: Arc::new(Mutex::new(conn)),
@@ -183,111 +645,483 @@ impl EngramArchive {
     }
 }
 
+/// What `diffArchives` compares beyond entry metadata
+#[napi(object)]
+pub struct DiffOptions {
+    /// Compare the rows of SQLite databases that changed
+    pub databases: Option<bool>,
+}
+
+/// Compare two archives, returning the changes from `old_path` to `new_path`
+/// as a JSON string
+#[napi]
+pub fn diff_archives(
+    old_path: String,
+    new_path: String,
+    options: Option<DiffOptions>,
+    open: Option<OpenOptions>,
+) -> Result<String, String> {
+    let open_options = open_options(open)?;
+    let mut old = Archive::open_with(&old_path, &open_options)
+        .map_err(|e| archive_error("Failed to open old archive", e))?;
+    let mut new = Archive::open_with(&new_path, &open_options)
+        .map_err(|e| archive_error("Failed to open new archive", e))?;
+
+    let options = diff::DiffOptions {
+        databases: options.and_then(|options| options.databases).unwrap_or(false),
+    };
+    let changes = diff::diff(&mut old, &mut new, options)
+        .map_err(|e| archive_error("Failed to diff archives", e))?;
+    serde_json::to_string(&changes)
+        .map_err(|e| archive_error("Failed to serialize diff", e.into()))
+}
+
+/// Options for `createPatch`
+#[napi(object)]
+pub struct PatchOptions {
+    /// zstd level for deltas and full contents (default: 19)
+    pub level: Option<i32>,
+}
+
+/// Summary returned by `createPatch`
+#[napi(object)]
+pub struct PatchStats {
+    /// Entries taken unchanged from the base archive
+    pub copied: u32,
+    /// Entries stored as deltas against a base entry
+    pub deltas: u32,
+    /// Entries stored in full
+    pub literals: u32,
+    /// Total uncompressed size of the new archive's entries
+    pub new_bytes: i64,
+    /// Total size of the deltas and full contents in the patch
+    pub patch_bytes: i64,
+}
+
+impl From<patch::PatchStats> for PatchStats {
+    fn from(stats: patch::PatchStats) -> Self {
+        Self {
+            copied: stats.copied as u32,
+            deltas: stats.deltas as u32,
+            literals: stats.literals as u32,
+            new_bytes: stats.new_bytes as i64,
+            patch_bytes: stats.patch_bytes as i64,
+        }
+    }
+}
+
+/// Write a patch to `patch_path` that rebuilds `new_path` from `old_path`
+#[napi]
+pub fn create_patch(
+    old_path: String,
+    new_path: String,
+    patch_path: String,
+    options: Option<PatchOptions>,
+    open: Option<OpenOptions>,
+) -> Result<PatchStats, String> {
+    let open_options = open_options(open)?;
+    let mut old = Archive::open_with(&old_path, &open_options)
+        .map_err(|e| archive_error("Failed to open old archive", e))?;
+    let mut new = Archive::open_with(&new_path, &open_options)
+        .map_err(|e| archive_error("Failed to open new archive", e))?;
+
+    let options = patch::PatchOptions {
+        level: options.and_then(|options| options.level),
+    };
+    patch::create_patch(&mut old, &mut new, std::path::Path::new(&patch_path), &options)
+        .map(Into::into)
+        .map_err(|e| archive_error("Failed to create patch", e))
+}
+
+/// Rebuild the archive described by the patch at `patch_path` from the base
+/// archive at `base_path`, verifying every entry, and write it to `out_path`
+#[napi]
+pub fn apply_patch(
+    base_path: String,
+    patch_path: String,
+    out_path: String,
+    open: Option<OpenOptions>,
+) -> Result<FinalizeStats, String> {
+    let open_options = open_options(open)?;
+    let mut base = Archive::open_with(&base_path, &open_options)
+        .map_err(|e| archive_error("Failed to open base archive", e))?;
+    let mut patch_archive = Archive::open_with(&patch_path, &open_options)
+        .map_err(|e| archive_error("Failed to open patch", e))?;
+
+    patch::apply_patch(&mut base, &mut patch_archive, std::path::Path::new(&out_path))
+        .map(Into::into)
+        .map_err(|e| archive_error("Failed to apply patch", e))
+}
+
 /// SQLite database connection from archive
 #[napi]
 pub struct EngramDatabase {
     conn: Arc<Mutex<Connection>>,
 }
 
+impl EngramDatabase {
+    /// Lock the connection. Statements run synchronously on the JavaScript
+    /// thread and the connection never leaves it, so the lock is only ever
+    /// held here while a user-defined function queries the database that is
+    /// calling it. Waiting for the lock would then deadlock the thread, so
+    /// that query fails instead and the outer statement carries on.
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn.try_lock().map_err(|e| match e {
+            TryLockError::WouldBlock => {
+                Error::from_reason("Database is busy running a statement")
+            }
+            TryLockError::Poisoned(_) => Error::from_reason("Database connection was poisoned"),
+        })
+    }
+}
+
 #[napi]
 impl EngramDatabase {
-    /// Execute a query and return results as JSON string
-    #[napi]
-    pub fn query(&self, sql: String, params: Option<String>) -> Result<String> {
-        let conn = self.conn.lock().unwrap();
+    /// Execute a query and return its rows as objects keyed by column name.
+    /// Parameters and values are converted as for user-defined functions
+    #[napi(ts_return_type = "Record<string, unknown>[]")]
+    pub fn query(
+        &self,
+        env: Env,
+        sql: String,
+        params: Option<Vec<JsUnknown>>,
+    ) -> Result<Vec<JsObject>> {
+        let conn = self.connection()?;
+        let params = statement_params(params)?;
 
         let mut stmt = conn
             .prepare(&sql)
             .map_err(|e| Error::from_reason(format!("Failed to prepare statement: {}", e)))?;
+        let column_names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
 
-        // Parse parameters from JSON if provided
-        let param_values: Vec<serde_json::Value> = if let Some(params_json) = params {
-            serde_json::from_str(&params_json)
-                .map_err(|e| Error::from_reason(format!("Failed to parse params: {}", e)))?
-        } else {
-            Vec::new()
-        };
+        let mut rows = stmt
+            .query(rusqlite::params_from_iter(&params))
+            .map_err(|e| Error::from_reason(format!("Query failed: {}", e)))?;
 
-        let sqlite_params: Vec<rusqlite::types::Value> = param_values
-            .into_iter()
-            .map(json_to_sqlite_value)
-            .collect();
+        let mut results = Vec::new();
+        while let Some(row) =
+            rows.next().map_err(|e| Error::from_reason(format!("Query failed: {}", e)))?
+        {
+            let mut object = env.create_object()?;
+            for (i, name) in column_names.iter().enumerate() {
+                let value = row
+                    .get_ref(i)
+                    .map_err(|e| Error::from_reason(format!("Failed to read row: {}", e)))?;
+                object.set_named_property(name, sqlite_to_js_value(&env, value)?)?;
+            }
+            results.push(object);
+        }
 
-        let param_refs: Vec<&dyn rusqlite::ToSql> = sqlite_params
-            .iter()
-            .map(|v| v as &dyn rusqlite::ToSql)
-            .collect();
-
-        // Execute query
-        let column_count = stmt.column_count();
-        let column_names: Vec<String> = (0..column_count)
-            .map(|i| stmt.column_name(i).unwrap().to_string())
-            .collect();
-
-        let rows = stmt
-            .query_map(param_refs.as_slice(), |row| {
-                let mut obj = serde_json::Map::new();
-                for (i, name) in column_names.iter().enumerate() {
-                    let value = sqlite_value_to_json(row, i)?;
-                    obj.insert(name.clone(), value);
-                }
-                Ok(serde_json::Value::Object(obj))
-            })
-            .map_err(|e| Error::from_reason(format!("Query failed: {}", e)))?;
+        Ok(results)
+    }
+
+    /// Execute a non-query SQL statement (INSERT, UPDATE, DELETE, etc.)
+    #[napi]
+    pub fn execute(&self, sql: String, params: Option<Vec<JsUnknown>>) -> Result<i64> {
+        let conn = self.connection()?;
+        let params = statement_params(params)?;
 
-        let results: Vec<serde_json::Value> = rows
-            .collect::<std::result::Result<Vec<_>, _>>()
-            .map_err(|e| Error::from_reason(format!("Failed to collect rows: {}", e)))?;
+        let rows_affected = conn
+            .execute(&sql, rusqlite::params_from_iter(&params))
+            .map_err(|e| Error::from_reason(format!("Execute failed: {}", e)))?;
 
-        let json = serde_json::to_string(&results)
-            .map_err(|e| Error::from_reason(format!("Failed to serialize results: {}", e)))?;
+        Ok(rows_affected as i64)
+    }
 
-        Ok(json)
+    /// Attach a read-only snapshot of another database as `alias`
+    #[napi]
+    pub fn attach_snapshot(&self, source: &EngramDatabase, alias: String) -> Result<()> {
+        if Arc::ptr_eq(&self.conn, &source.conn) {
+            return Err(Error::from_reason("Cannot attach a database to itself"));
+        }
+        let mut conn = self.connection()?;
+        let source = source.connection()?;
+        database::attach_snapshot(&mut conn, &source, &alias)
+            .map_err(|e| Error::from_reason(format!("Failed to attach database: {}", e)))
     }
 
-    /// Execute a non-query SQL statement (INSERT, UPDATE, DELETE, etc.)
+    /// Attach a SQLite database file on disk as `alias`
+    #[napi]
+    pub fn attach_file(&self, path: String, alias: String) -> Result<()> {
+        let conn = self.connection()?;
+        database::attach_file(&conn, std::path::Path::new(&path), &alias)
+            .map_err(|e| Error::from_reason(format!("Failed to attach database: {}", e)))
+    }
+
+    /// Register a scalar SQL function. `callback` receives the arguments as
+    /// an array and returns `{ value }` or `{ error }`
+    #[napi]
+    pub fn function(
+        &self,
+        env: Env,
+        name: String,
+        n_args: i32,
+        deterministic: bool,
+        callback: JsFunction,
+    ) -> Result<()> {
+        let callback = AssertUnwindSafe(JsCallback::new(env, callback)?);
+        self.connection()?
+            .create_scalar_function(
+                &name,
+                n_args,
+                function_flags(deterministic),
+                move |ctx| callback.call(|env| Ok(vec![function_args(env, ctx)?])),
+            )
+            .map_err(|e| Error::from_reason(format!("Failed to register function: {}", e)))
+    }
+
+    /// Register an aggregate SQL function. `callback` is called with
+    /// `'step'` and the arguments of each row, then with `'result'`, along
+    /// with a number identifying the group being aggregated
     #[napi]
-    pub fn execute(&self, sql: String, params: Option<String>) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
-
-        // Parse parameters from JSON if provided
-        let param_values: Vec<serde_json::Value> = if let Some(params_json) = params {
-            serde_json::from_str(&params_json)
-                .map_err(|e| Error::from_reason(format!("Failed to parse params: {}", e)))?
-        } else {
-            Vec::new()
+    pub fn aggregate(
+        &self,
+        env: Env,
+        name: String,
+        n_args: i32,
+        deterministic: bool,
+        callback: JsFunction,
+    ) -> Result<()> {
+        let aggregate = JsAggregate {
+            callback: JsCallback::new(env, callback)?,
+            next_group: Cell::new(0),
         };
+        self.connection()?
+            .create_aggregate_function(&name, n_args, function_flags(deterministic), aggregate)
+            .map_err(|e| Error::from_reason(format!("Failed to register aggregate: {}", e)))
+    }
+}
 
-        let sqlite_params: Vec<rusqlite::types::Value> = param_values
-            .into_iter()
-            .map(json_to_sqlite_value)
-            .collect();
+/// JavaScript function called by SQLite for a user-defined function
+struct JsCallback {
+    env: napi::sys::napi_env,
+    function: Ref<()>,
+}
 
-        let param_refs: Vec<&dyn rusqlite::ToSql> = sqlite_params
-            .iter()
-            .map(|v| v as &dyn rusqlite::ToSql)
-            .collect();
+// SAFETY: rusqlite requires functions to be `Send` because a `Connection`
+// may be moved to another thread, which would take the raw `env` with it.
+// The only connection that holds a `JsCallback` is the one inside the
+// `EngramDatabase` it was registered on. That is a napi class, reachable
+// only from the JavaScript thread that created it, and its `Arc` is never
+// cloned into an `AsyncTask` or any other thread. Statements therefore run,
+// and call the function, on the thread `env` belongs to, and the connection
+// drops the function there when the class is finalized. Anything that moves
+// the connection off that thread must first stop registering JavaScript
+// functions on it.
+unsafe impl Send for JsCallback {}
 
-        let rows_affected = conn
-            .execute(&sql, param_refs.as_slice())
-            .map_err(|e| Error::from_reason(format!("Execute failed: {}", e)))?;
+impl JsCallback {
+    fn new(env: Env, function: JsFunction) -> Result<Self> {
+        Ok(Self {
+            env: env.raw(),
+            function: env.create_reference(function)?,
+        })
+    }
 
-        Ok(rows_affected as i64)
+    /// Call the function and unwrap the `{ value }` or `{ error }` object it
+    /// returns, turning errors into SQLite errors
+    fn call(
+        &self,
+        args: impl FnOnce(&Env) -> Result<Vec<JsUnknown>>,
+    ) -> rusqlite::Result<rusqlite::types::Value> {
+        let env = unsafe { Env::from_raw(self.env) };
+        let returned = (|| {
+            let function: JsFunction = env.get_reference_value(&self.function)?;
+            let returned = function.call(None, &args(&env)?)?.coerce_to_object()?;
+            if returned.has_named_property("error")? {
+                let error: JsUnknown = returned.get_named_property("error")?;
+                return Ok(Err(error.coerce_to_string()?.into_utf8()?.into_owned()?));
+            }
+            js_to_sqlite_value(returned.get_named_property("value")?).map(Ok)
+        })()
+        .map_err(|e| rusqlite::Error::UserFunctionError(e.to_string().into()))?;
+        returned.map_err(|message| rusqlite::Error::UserFunctionError(message.into()))
+    }
+}
+
+impl Drop for JsCallback {
+    fn drop(&mut self) {
+        let _ = self.function.unref(unsafe { Env::from_raw(self.env) });
+    }
+}
+
+struct JsAggregate {
+    callback: JsCallback,
+    next_group: Cell<u32>,
+}
+
+impl JsAggregate {
+    fn call(
+        &self,
+        event: &str,
+        group: u32,
+        args: impl FnOnce(&Env) -> Result<JsUnknown>,
+    ) -> rusqlite::Result<rusqlite::types::Value> {
+        self.callback.call(|env| {
+            Ok(vec![
+                env.create_string(event)?.into_unknown(),
+                env.create_uint32(group)?.into_unknown(),
+                args(env)?,
+            ])
+        })
+    }
+
+    fn new_group(&self) -> u32 {
+        let group = self.next_group.get();
+        self.next_group.set(group.wrapping_add(1));
+        group
+    }
+}
+
+impl Aggregate<u32, rusqlite::types::Value> for JsAggregate {
+    fn init(&self, _ctx: &mut FunctionContext<'_>) -> rusqlite::Result<u32> {
+        Ok(self.new_group())
+    }
+
+    fn step(&self, ctx: &mut FunctionContext<'_>, group: &mut u32) -> rusqlite::Result<()> {
+        self.call("step", *group, |env| function_args(env, ctx))
+            .map(drop)
+    }
+
+    fn finalize(
+        &self,
+        _ctx: &mut FunctionContext<'_>,
+        group: Option<u32>,
+    ) -> rusqlite::Result<rusqlite::types::Value> {
+        // Groups without rows were never initialized.
+        let group = group.unwrap_or_else(|| self.new_group());
+        self.call("result", group, |env| {
+            Ok(env.create_array_with_length(0)?.into_unknown())
+        })
+    }
+}
+
+fn function_flags(deterministic: bool) -> FunctionFlags {
+    if deterministic {
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC
+    } else {
+        FunctionFlags::SQLITE_UTF8
+    }
+}
+
+/// Largest integer a JavaScript number holds exactly
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// Arguments of a user-defined function as a JavaScript array
+fn function_args(env: &Env, ctx: &FunctionContext<'_>) -> Result<JsUnknown> {
+    let mut args = env.create_array_with_length(ctx.len())?;
+    for i in 0..ctx.len() {
+        args.set_element(i as u32, sqlite_to_js_value(env, ctx.get_raw(i))?)?;
+    }
+    Ok(args.into_unknown())
+}
+
+/// Convert an SQLite value for JavaScript. Integers become numbers, or
+/// BigInts beyond `Number.MAX_SAFE_INTEGER`, and blobs become Buffers.
+fn sqlite_to_js_value(env: &Env, value: rusqlite::types::ValueRef) -> Result<JsUnknown> {
+    use rusqlite::types::ValueRef;
+
+    match value {
+        ValueRef::Null => Ok(env.get_null()?.into_unknown()),
+        ValueRef::Integer(i) if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&i) => {
+            Ok(env.create_int64(i)?.into_unknown())
+        }
+        ValueRef::Integer(i) => env.create_bigint_from_i64(i)?.into_unknown(),
+        ValueRef::Real(f) => Ok(env.create_double(f)?.into_unknown()),
+        ValueRef::Text(s) => Ok(env.create_string(&String::from_utf8_lossy(s))?.into_unknown()),
+        ValueRef::Blob(b) => Ok(env.create_buffer_with_data(b.to_vec())?.into_raw().into_unknown()),
+    }
+}
+
+/// Convert statement parameters for SQLite
+fn statement_params(params: Option<Vec<JsUnknown>>) -> Result<Vec<rusqlite::types::Value>> {
+    params
+        .unwrap_or_default()
+        .into_iter()
+        .map(js_to_sqlite_value)
+        .collect()
+}
+
+/// Convert a value returned by JavaScript for SQLite. Booleans become `1`
+/// or `0`, whole numbers and BigInts integers, and Buffers blobs.
+fn js_to_sqlite_value(value: JsUnknown) -> Result<rusqlite::types::Value> {
+    use rusqlite::types::Value;
+
+    match value.get_type()? {
+        ValueType::Undefined | ValueType::Null => Ok(Value::Null),
+        ValueType::Boolean => Ok(Value::Integer(value.coerce_to_bool()?.get_value()? as i64)),
+        ValueType::Number => {
+            let number = value.coerce_to_number()?.get_double()?;
+            if number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER as f64 {
+                Ok(Value::Integer(number as i64))
+            } else {
+                Ok(Value::Real(number))
+            }
+        }
+        ValueType::BigInt => match unsafe { value.cast::<JsBigInt>() }.get_i64()? {
+            (i, true) => Ok(Value::Integer(i)),
+            _ => Err(Error::from_reason("BigInt does not fit in a 64-bit integer")),
+        },
+        ValueType::String => Ok(Value::Text(value.coerce_to_string()?.into_utf8()?.into_owned()?)),
+        ValueType::Object if value.is_buffer()? => {
+            Ok(Value::Blob(unsafe { value.cast::<JsBuffer>() }.into_value()?.to_vec()))
+        }
+        other => Err(Error::from_reason(format!("Cannot convert {} to an SQLite value", other))),
     }
 }
 
 /// Archive writer for creating .eng files
 #[napi]
 pub struct EngramWriter {
-    inner: Option<ArchiveWriter>,
+    inner: Option<Writer>,
 }
 
 #[napi]
 impl EngramWriter {
     /// Create a new archive file
     #[napi(constructor)]
-    pub fn new(path: String) -> Result<Self> {
-        let writer = ArchiveWriter::create(&path)
+    pub fn new(path: String, options: Option<WriterOptions>) -> Result<Self> {
+        let options = options
+            .map(TryInto::try_into)
+            .transpose()?
+            .unwrap_or_default();
+        let writer = Writer::create(&path, options)
+            .map_err(|e| Error::from_reason(format!("Failed to create archive: {}", e)))?;
+
+        Ok(Self {
+            inner: Some(writer),
+        })
+    }
+
+    /// Open an existing archive to add, replace and delete entries
+    ///
+    /// Unchanged entries are carried over when the writer is finalized, which
+    /// then replaces the original archive atomically.
+    #[napi(factory)]
+    pub fn open_for_update(path: String, options: Option<WriterOptions>) -> Result<Self> {
+        let options = options
+            .map(TryInto::try_into)
+            .transpose()?
+            .unwrap_or_default();
+        let writer = Writer::open_for_update(&path, options)
+            .map_err(|e| Error::from_reason(format!("Failed to open archive for update: {}", e)))?;
+
+        Ok(Self {
+            inner: Some(writer),
+        })
+    }
+
+    /// Create a writer that keeps the archive in memory
+    ///
+    /// Finish it with `toBuffer` instead of `finalize`. Only supported on
+    /// Linux.
+    #[napi(factory)]
+    pub fn in_memory(options: Option<WriterOptions>) -> Result<Self> {
+        let options = options
+            .map(TryInto::try_into)
+            .transpose()?
+            .unwrap_or_default();
+        let writer = Writer::create_in_memory(options)
             .map_err(|e| Error::from_reason(format!("Failed to create archive: {}", e)))?;
 
         Ok(Self {
@@ -308,13 +1142,14 @@ impl EngramWriter {
             .map_err(|e| Error::from_reason(format!("Failed to add file: {}", e)))
     }
 
-    /// Add a file with specific compression
+    /// Add a file with specific compression and optional level
     #[napi]
     pub fn add_file_with_compression(
         &mut self,
         path: String,
         data: Buffer,
         compression: CompressionMethod,
+        level: Option<i32>,
     ) -> Result<()> {
         let writer = self
             .inner
@@ -322,7 +1157,7 @@ impl EngramWriter {
             .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
 
         writer
-            .add_file_with_compression(&path, &data, compression.into())
+            .add_file_with_compression(&path, &data, compression.into(), level)
             .map_err(|e| Error::from_reason(format!("Failed to add file: {}", e)))
     }
 
@@ -339,6 +1174,20 @@ impl EngramWriter {
             .map_err(|e| Error::from_reason(format!("Failed to add file from disk: {}", e)))
     }
 
+    /// Add every file below a directory, returning the number of files added
+    #[napi]
+    pub fn add_directory(&mut self, disk_path: String, prefix: Option<String>) -> Result<u32> {
+        let writer = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
+
+        writer
+            .add_directory(std::path::Path::new(&disk_path), prefix.as_deref().unwrap_or(""))
+            .map(|count| count as u32)
+            .map_err(|e| Error::from_reason(format!("Failed to add directory: {}", e)))
+    }
+
     /// Add manifest.json from a JSON string
     #[napi]
     pub fn add_manifest(&mut self, manifest: String) -> Result<()> {
@@ -355,53 +1204,122 @@ impl EngramWriter {
             .map_err(|e| Error::from_reason(format!("Failed to add manifest: {}", e)))
     }
 
-    /// Finalize the archive (must be called before the writer is dropped)
+    /// Delete an entry of the archive being updated
+    ///
+    /// Returns false if the archive has no such entry.
     #[napi]
-    pub fn finalize(&mut self) -> Result<()> {
+    pub fn delete_file(&mut self, path: String) -> Result<bool> {
         let writer = self
             .inner
-            .take()
+            .as_mut()
             .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
 
+        writer
+            .delete_file(&path)
+            .map_err(|e| Error::from_reason(format!("Failed to delete file: {}", e)))
+    }
+
+    /// Add a whiteout entry hiding a path of lower layers in an overlay
+    #[napi]
+    pub fn add_whiteout(&mut self, path: String) -> Result<()> {
+        let writer = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
+
+        writer
+            .add_whiteout(&path)
+            .map_err(|e| Error::from_reason(format!("Failed to add whiteout: {}", e)))
+    }
+
+    /// Path the archive is written to by `finalize`, or null for a writer
+    /// created with `inMemory`
+    #[napi(getter)]
+    pub fn target_path(&self) -> Result<Option<String>> {
+        let writer = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
+        Ok(writer
+            .target()
+            .map(|path| path.to_string_lossy().into_owned()))
+    }
+
+    /// Finalize the archive and move it into place at the target path
+    ///
+    /// Until this is called the archive is written to a temporary sibling file,
+    /// which is removed if the writer is aborted or garbage collected. With a
+    /// `signingKey` the archive is signed with Ed25519.
+    #[napi]
+    pub fn finalize(&mut self, options: Option<FinalizeOptions>) -> Result<FinalizeStats> {
+        self.prepare_finalize(false, options)?;
+        let writer = self.inner.take().expect("checked by prepare_finalize");
+
         writer
             .finalize()
+            .map(Into::into)
             .map_err(|e| Error::from_reason(format!("Failed to finalize archive: {}", e)))
     }
-}
 
-// Helper functions for converting between JSON and SQLite values
+    /// Finalize an in-memory archive and return its bytes
+    #[napi]
+    pub fn to_buffer(&mut self, options: Option<FinalizeOptions>) -> Result<Buffer> {
+        self.prepare_finalize(true, options)?;
+        let writer = self.inner.take().expect("checked by prepare_finalize");
 
-fn json_to_sqlite_value(value: serde_json::Value) -> rusqlite::types::Value {
-    use rusqlite::types::Value;
-    use serde_json::Value as JsonValue;
+        writer
+            .finalize_to_bytes()
+            .map(|(_, data)| data.into())
+            .map_err(|e| Error::from_reason(format!("Failed to finalize archive: {}", e)))
+    }
 
-    match value {
-        JsonValue::Null => Value::Null,
-        JsonValue::Bool(b) => Value::Integer(if b { 1 } else { 0 }),
-        JsonValue::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                Value::Integer(i)
-            } else if let Some(f) = n.as_f64() {
-                Value::Real(f)
-            } else {
-                Value::Null
-            }
-        }
-        JsonValue::String(s) => Value::Text(s),
-        JsonValue::Array(_) | JsonValue::Object(_) => {
-            Value::Text(serde_json::to_string(&value).unwrap())
-        }
+    /// Discard the partially written archive
+    #[napi]
+    pub fn abort(&mut self) -> Result<()> {
+        let writer = self
+            .inner
+            .take()
+            .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
+
+        writer.abort();
+        Ok(())
     }
 }
 
-fn sqlite_value_to_json(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<serde_json::Value> {
-    use rusqlite::types::ValueRef;
+impl EngramWriter {
+    /// Check that the writer can be finished the requested way and sign it.
+    /// Runs before the writer is taken, so it stays usable when this fails.
+    fn prepare_finalize(
+        &mut self,
+        in_memory: bool,
+        options: Option<FinalizeOptions>,
+    ) -> Result<()> {
+        let writer = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| Error::from_reason("Writer already finalized"))?;
 
-    match row.get_ref(idx)? {
-        ValueRef::Null => Ok(serde_json::Value::Null),
-        ValueRef::Integer(i) => Ok(serde_json::json!(i)),
-        ValueRef::Real(f) => Ok(serde_json::json!(f)),
-        ValueRef::Text(s) => Ok(serde_json::json!(String::from_utf8_lossy(s))),
-        ValueRef::Blob(b) => Ok(serde_json::json!(b)),
+        match (in_memory, writer.is_in_memory()) {
+            (true, false) => {
+                return Err(Error::from_reason(
+                    "toBuffer() needs a writer created with EngramWriter.inMemory(); \
+                     finish this one with finalize()",
+                ))
+            }
+            (false, true) => {
+                return Err(Error::from_reason(
+                    "Writers created with EngramWriter.inMemory() are finished with toBuffer() \
+                     or toStream()",
+                ))
+            }
+            _ => {}
+        }
+
+        if let Some(key) = options.and_then(|options| options.signing_key) {
+            writer
+                .sign_with(&key)
+                .map_err(|e| Error::from_reason(format!("Failed to sign archive: {}", e)))?;
+        }
+        Ok(())
     }
 }
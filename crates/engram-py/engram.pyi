@@ -0,0 +1,95 @@
+from typing import Any, Iterator, Mapping, Optional, Sequence, Union
+
+_Bytes = Union[bytes, bytearray]
+_Parameters = Union[Sequence[Any], Mapping[str, Any], None]
+
+apilevel: str
+threadsafety: int
+paramstyle: str
+
+class EngramError(Exception): ...
+class SignatureError(EngramError): ...
+class LimitError(EngramError): ...
+class DatabaseError(EngramError): ...
+
+class Archive:
+    def __init__(
+        self,
+        path: str,
+        *,
+        trusted_keys: Optional[Sequence[_Bytes]] = None,
+        require_signature: bool = False,
+        keys: Optional[Sequence[_Bytes]] = None,
+    ) -> None: ...
+    @staticmethod
+    def from_bytes(
+        data: _Bytes,
+        *,
+        trusted_keys: Optional[Sequence[_Bytes]] = None,
+        require_signature: bool = False,
+        keys: Optional[Sequence[_Bytes]] = None,
+    ) -> Archive: ...
+    @property
+    def entry_count(self) -> int: ...
+    @property
+    def closed(self) -> bool: ...
+    def list_files(self) -> list[str]: ...
+    def list_prefix(self, prefix: str) -> list[str]: ...
+    def contains(self, path: str) -> bool: ...
+    def __contains__(self, path: str) -> bool: ...
+    def __len__(self) -> int: ...
+    def get_metadata(self, path: str) -> Optional[dict[str, Any]]: ...
+    def read_file(self, path: str) -> bytes: ...
+    def read_view(self, path: str) -> memoryview: ...
+    def read_text(self, path: str) -> str: ...
+    def read_json(self, path: str) -> Any: ...
+    def hash_file(self, path: str) -> str: ...
+    def read_manifest(self) -> Optional[dict[str, Any]]: ...
+    def open_database(self, path: str) -> Database: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> Archive: ...
+    def __exit__(self, exc_type: Any = None, exc_value: Any = None, traceback: Any = None) -> bool: ...
+
+class Database:
+    def cursor(self) -> Cursor: ...
+    def execute(self, sql: str, parameters: _Parameters = None) -> Cursor: ...
+    def commit(self) -> None: ...
+    def rollback(self) -> None: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> Database: ...
+    def __exit__(self, exc_type: Any = None, exc_value: Any = None, traceback: Any = None) -> bool: ...
+
+class Cursor:
+    arraysize: int
+    @property
+    def rowcount(self) -> int: ...
+    @property
+    def description(self) -> Optional[tuple[tuple[str, None, None, None, None, None, None], ...]]: ...
+    def execute(self, sql: str, parameters: _Parameters = None) -> Cursor: ...
+    def executemany(self, sql: str, seq_of_parameters: Sequence[_Parameters]) -> Cursor: ...
+    def fetchone(self) -> Optional[tuple[Any, ...]]: ...
+    def fetchmany(self, size: Optional[int] = None) -> list[tuple[Any, ...]]: ...
+    def fetchall(self) -> list[tuple[Any, ...]]: ...
+    def close(self) -> None: ...
+    def setinputsizes(self, sizes: Any) -> None: ...
+    def setoutputsize(self, size: Any, column: Any = None) -> None: ...
+    def __iter__(self) -> Iterator[tuple[Any, ...]]: ...
+    def __next__(self) -> tuple[Any, ...]: ...
+
+class Writer:
+    def __init__(self, path: str, *, level: Optional[int] = None, deduplicate: bool = False) -> None: ...
+    @staticmethod
+    def open_for_update(path: str, *, level: Optional[int] = None, deduplicate: bool = False) -> Writer: ...
+    def add_file(self, path: str, data: _Bytes) -> None: ...
+    def add_text(self, path: str, text: str) -> None: ...
+    def add_json(self, path: str, value: Any) -> None: ...
+    def add_file_with_compression(self, path: str, data: _Bytes, method: str, level: Optional[int] = None) -> None: ...
+    def add_file_from_disk(self, path: str, disk_path: str) -> None: ...
+    def add_directory(self, directory: str, prefix: str = "") -> int: ...
+    def add_manifest(self, manifest: Mapping[str, Any]) -> None: ...
+    def delete_file(self, path: str) -> bool: ...
+    def sign(self, secret_key: _Bytes) -> None: ...
+    def finalize(self) -> dict[str, int]: ...
+    def abort(self) -> None: ...
+    def __enter__(self) -> Writer: ...
+    def __exit__(self, exc_type: Any = None, exc_value: Any = None, traceback: Any = None) -> bool: ...
@@ -0,0 +1,892 @@
+//! # Engram Python
+//!
+//! PyO3 bindings exposing archives, their SQLite databases and the archive
+//! writer as the `engram` Python module. Archive and database work runs with
+//! the GIL released, so other Python threads keep running while entries are
+//! decompressed or queries execute.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use engram_common::{
+    codec, database, signature, sql, Archive, EncryptionKey, EntryInfo, OpenOptions, Writer,
+    WriterOptions,
+};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyOSError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::pybacked::PyBackedBytes;
+use pyo3::types::{
+    PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyMemoryView, PyString, PyTuple,
+};
+use pyo3::IntoPyObjectExt;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::Connection;
+
+create_exception!(
+    engram,
+    EngramError,
+    PyException,
+    "Error raised by an archive operation."
+);
+create_exception!(
+    engram,
+    SignatureError,
+    EngramError,
+    "The archive signature is missing, untrusted or invalid."
+);
+create_exception!(
+    engram,
+    LimitError,
+    EngramError,
+    "The archive exceeds the resource limits it was opened with."
+);
+create_exception!(
+    engram,
+    DatabaseError,
+    EngramError,
+    "A SQLite operation failed."
+);
+
+fn archive_error(err: engram_common::Error) -> PyErr {
+    use engram_common::Error;
+
+    match err {
+        Error::NotFound(path) => PyKeyError::new_err(path),
+        Error::Io(err) => PyOSError::new_err(err.to_string()),
+        Error::Signature(message) => SignatureError::new_err(message),
+        Error::Limit(message) => LimitError::new_err(message),
+        Error::Database(message) => DatabaseError::new_err(message),
+        other => EngramError::new_err(other.to_string()),
+    }
+}
+
+fn sql_error(err: rusqlite::Error) -> PyErr {
+    DatabaseError::new_err(err.to_string())
+}
+
+/// Parse JSON with the `json` module, so callers get plain dicts and lists.
+fn json_loads<'py>(py: Python<'py>, json: &[u8]) -> PyResult<Bound<'py, PyAny>> {
+    py.import("json")?
+        .call_method1("loads", (PyBytes::new(py, json),))
+}
+
+fn metadata_dict<'py>(py: Python<'py>, entry: EntryInfo) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("path", entry.path)?;
+    dict.set_item("uncompressed_size", entry.uncompressed_size)?;
+    dict.set_item("compressed_size", entry.compressed_size)?;
+    dict.set_item("compression_method", codec::method_name(&entry.compression))?;
+    dict.set_item("compression_level", entry.compression_level)?;
+    dict.set_item("dictionary", entry.dictionary)?;
+    dict.set_item("encrypted", entry.encrypted)?;
+    dict.set_item("alias_of", entry.alias_of)?;
+    dict.set_item("modified_time", entry.modified_time)?;
+    dict.set_item("crc32", entry.crc32)?;
+    dict.set_item("hash", entry.sha256)?;
+    Ok(dict)
+}
+
+fn open_options(
+    trusted_keys: Option<Vec<PyBackedBytes>>,
+    require_signature: bool,
+    keys: Option<Vec<PyBackedBytes>>,
+) -> PyResult<OpenOptions> {
+    let mut options = OpenOptions {
+        require_signature,
+        ..Default::default()
+    };
+    for key in trusted_keys.unwrap_or_default() {
+        options
+            .trusted_keys
+            .push(signature::public_key(&key).map_err(archive_error)?);
+    }
+    for key in keys.unwrap_or_default() {
+        options
+            .keys
+            .push(EncryptionKey::new(&key, None).map_err(archive_error)?);
+    }
+    Ok(options)
+}
+
+/// A read-only .eng archive.
+///
+/// Archives are context managers; leaving the `with` block closes them.
+#[pyclass(name = "Archive", module = "engram", frozen)]
+struct PyArchive {
+    inner: Arc<SharedArchive>,
+}
+
+/// Archive shared with the databases opened from it, which read entries
+/// through `engram_files`. `None` once the archive is closed.
+struct SharedArchive(Mutex<Option<Archive>>);
+
+impl SharedArchive {
+    fn with<T>(
+        &self,
+        f: impl FnOnce(&mut Archive) -> engram_common::Result<T>,
+    ) -> engram_common::Result<T> {
+        let mut inner = self.0.lock().unwrap();
+        let archive = inner
+            .as_mut()
+            .ok_or_else(|| engram_common::Error::InvalidInput("archive is closed".into()))?;
+        f(archive)
+    }
+}
+
+impl sql::EntrySource for SharedArchive {
+    fn list_files(&self) -> engram_common::Result<Vec<String>> {
+        self.with(|archive| Ok(archive.list_files().to_vec()))
+    }
+
+    fn get_entry(&self, path: &str) -> engram_common::Result<Option<EntryInfo>> {
+        self.with(|archive| Ok(archive.get_entry(path)))
+    }
+
+    fn read_file(&self, path: &str) -> engram_common::Result<Vec<u8>> {
+        self.with(|archive| archive.read_file(path))
+    }
+}
+
+impl PyArchive {
+    fn new(archive: Archive) -> Self {
+        Self {
+            inner: Arc::new(SharedArchive(Mutex::new(Some(archive)))),
+        }
+    }
+
+    /// Run `f` on the open archive with the GIL released.
+    fn with_archive<T: Send>(
+        &self,
+        py: Python<'_>,
+        f: impl FnOnce(&mut Archive) -> engram_common::Result<T> + Send,
+    ) -> PyResult<T> {
+        py.allow_threads(|| {
+            let mut inner = self.inner.0.lock().unwrap();
+            let archive = inner
+                .as_mut()
+                .ok_or_else(|| PyValueError::new_err("I/O operation on closed archive"))?;
+            f(archive).map_err(archive_error)
+        })
+    }
+}
+
+#[pymethods]
+impl PyArchive {
+    /// Open the archive at `path`.
+    ///
+    /// `trusted_keys` are 32-byte Ed25519 public keys, one of which must have
+    /// signed the archive when `require_signature` is set; requiring a
+    /// signature without trusted keys raises `EngramError`. `keys` are 32-byte
+    /// keys for encrypted entries.
+    #[new]
+    #[pyo3(signature = (path, *, trusted_keys=None, require_signature=false, keys=None))]
+    fn open(
+        py: Python<'_>,
+        path: &str,
+        trusted_keys: Option<Vec<PyBackedBytes>>,
+        require_signature: bool,
+        keys: Option<Vec<PyBackedBytes>>,
+    ) -> PyResult<Self> {
+        let options = open_options(trusted_keys, require_signature, keys)?;
+        let archive = py
+            .allow_threads(|| Archive::open_with(path, &options))
+            .map_err(archive_error)?;
+        Ok(Self::new(archive))
+    }
+
+    /// Open an archive held in memory.
+    ///
+    /// The bytes are copied and kept in memory. Only supported on Linux.
+    #[staticmethod]
+    #[pyo3(signature = (data, *, trusted_keys=None, require_signature=false, keys=None))]
+    fn from_bytes(
+        py: Python<'_>,
+        data: PyBackedBytes,
+        trusted_keys: Option<Vec<PyBackedBytes>>,
+        require_signature: bool,
+        keys: Option<Vec<PyBackedBytes>>,
+    ) -> PyResult<Self> {
+        let options = open_options(trusted_keys, require_signature, keys)?;
+        let archive = py
+            .allow_threads(|| Archive::open_memory(&data, &options))
+            .map_err(archive_error)?;
+        Ok(Self::new(archive))
+    }
+
+    #[getter]
+    fn entry_count(&self, py: Python<'_>) -> PyResult<usize> {
+        self.with_archive(py, |archive| Ok(archive.entry_count()))
+    }
+
+    fn list_files(&self, py: Python<'_>) -> PyResult<Vec<String>> {
+        self.with_archive(py, |archive| Ok(archive.list_files().to_vec()))
+    }
+
+    fn list_prefix(&self, py: Python<'_>, prefix: &str) -> PyResult<Vec<String>> {
+        self.with_archive(py, |archive| Ok(archive.list_prefix(prefix)))
+    }
+
+    fn contains(&self, py: Python<'_>, path: &str) -> PyResult<bool> {
+        self.with_archive(py, |archive| Ok(archive.contains(path)))
+    }
+
+    fn __contains__(&self, py: Python<'_>, path: &str) -> PyResult<bool> {
+        self.contains(py, path)
+    }
+
+    fn __len__(&self, py: Python<'_>) -> PyResult<usize> {
+        self.entry_count(py)
+    }
+
+    /// Metadata of the entry at `path` as a dict, or `None`.
+    fn get_metadata<'py>(
+        &self,
+        py: Python<'py>,
+        path: &str,
+    ) -> PyResult<Option<Bound<'py, PyDict>>> {
+        self.with_archive(py, |archive| Ok(archive.get_entry(path)))?
+            .map(|entry| metadata_dict(py, entry))
+            .transpose()
+    }
+
+    /// Read an entry, raising `KeyError` when it does not exist.
+    fn read_file<'py>(&self, py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.with_archive(py, |archive| archive.read_file(path))?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// Read an entry as a read-only `memoryview`.
+    fn read_view<'py>(&self, py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyMemoryView>> {
+        PyMemoryView::from(self.read_file(py, path)?.as_any())
+    }
+
+    fn read_text(&self, py: Python<'_>, path: &str) -> PyResult<String> {
+        let data = self.with_archive(py, |archive| archive.read_file(path))?;
+        String::from_utf8(data)
+            .map_err(|e| PyValueError::new_err(format!("{path} is not valid UTF-8: {e}")))
+    }
+
+    fn read_json<'py>(&self, py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyAny>> {
+        let data = self.with_archive(py, |archive| archive.read_file(path))?;
+        json_loads(py, &data)
+    }
+
+    /// Hex-encoded SHA-256 of an entry, verified against the recorded hash.
+    fn hash_file(&self, py: Python<'_>, path: &str) -> PyResult<String> {
+        self.with_archive(py, |archive| archive.hash_file(path))
+    }
+
+    /// The archive manifest, or `None` when it has none.
+    fn read_manifest<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.with_archive(py, |archive| archive.read_manifest())?
+            .map(|manifest| json_loads(py, manifest.to_string().as_bytes()))
+            .transpose()
+    }
+
+    /// Open the SQLite database stored at `path`.
+    fn open_database(&self, py: Python<'_>, path: &str) -> PyResult<PyDatabase> {
+        let conn = self.with_archive(py, |archive| database::open_database(archive, path))?;
+        sql::register(&conn, self.inner.clone()).map_err(archive_error)?;
+        Ok(PyDatabase {
+            conn: Arc::new(Mutex::new(Some(conn))),
+        })
+    }
+
+    fn close(&self, py: Python<'_>) {
+        py.allow_threads(|| self.inner.0.lock().unwrap().take());
+    }
+
+    #[getter]
+    fn closed(&self, py: Python<'_>) -> bool {
+        py.allow_threads(|| self.inner.0.lock().unwrap().is_none())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type=None, _exc_value=None, _traceback=None))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close(py);
+        false
+    }
+}
+
+type SharedConnection = Arc<Mutex<Option<Connection>>>;
+
+/// Run `f` on the open connection with the GIL released.
+fn with_connection<T: Send>(
+    py: Python<'_>,
+    conn: &SharedConnection,
+    f: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send,
+) -> PyResult<T> {
+    py.allow_threads(|| {
+        let conn = conn.lock().unwrap();
+        let conn = conn
+            .as_ref()
+            .ok_or_else(|| DatabaseError::new_err("cannot operate on a closed database"))?;
+        f(conn).map_err(sql_error)
+    })
+}
+
+/// A SQLite database inside an archive.
+///
+/// Follows the DB-API 2.0 connection interface with `qmark` and named
+/// parameters.
+#[pyclass(name = "Database", module = "engram", frozen)]
+struct PyDatabase {
+    conn: SharedConnection,
+}
+
+#[pymethods]
+impl PyDatabase {
+    fn cursor(&self) -> PyCursor {
+        PyCursor::new(self.conn.clone())
+    }
+
+    /// Create a cursor and execute `sql` on it.
+    #[pyo3(signature = (sql, parameters=None))]
+    fn execute(
+        &self,
+        py: Python<'_>,
+        sql: &str,
+        parameters: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<PyCursor> {
+        let mut cursor = self.cursor();
+        cursor.run(py, sql, parameters)?;
+        Ok(cursor)
+    }
+
+    /// Databases are read-only, so there is never anything to commit.
+    fn commit(&self) {}
+
+    fn rollback(&self) {}
+
+    fn close(&self, py: Python<'_>) {
+        py.allow_threads(|| self.conn.lock().unwrap().take());
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type=None, _exc_value=None, _traceback=None))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close(py);
+        false
+    }
+}
+
+/// Parameters bound to a statement.
+enum Parameters {
+    Positional(Vec<SqlValue>),
+    Named(Vec<(String, SqlValue)>),
+}
+
+fn sql_value(value: &Bound<'_, PyAny>) -> PyResult<SqlValue> {
+    if value.is_none() {
+        Ok(SqlValue::Null)
+    } else if let Ok(value) = value.downcast::<PyBool>() {
+        Ok(SqlValue::Integer(value.is_true() as i64))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(SqlValue::Integer(value.extract()?))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(SqlValue::Real(value.extract()?))
+    } else if let Ok(value) = value.downcast::<PyString>() {
+        Ok(SqlValue::Text(value.to_cow()?.into_owned()))
+    } else if let Ok(value) = value.extract::<PyBackedBytes>() {
+        Ok(SqlValue::Blob(value.to_vec()))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "unsupported parameter type: {}",
+            value.get_type().name()?
+        )))
+    }
+}
+
+fn parameters(parameters: Option<&Bound<'_, PyAny>>) -> PyResult<Parameters> {
+    let Some(parameters) = parameters.filter(|parameters| !parameters.is_none()) else {
+        return Ok(Parameters::Positional(Vec::new()));
+    };
+    if let Ok(mapping) = parameters.downcast::<PyDict>() {
+        return mapping
+            .iter()
+            .map(|(name, value)| Ok((name.extract()?, sql_value(&value)?)))
+            .collect::<PyResult<_>>()
+            .map(Parameters::Named);
+    }
+    parameters
+        .try_iter()?
+        .map(|value| sql_value(&value?))
+        .collect::<PyResult<_>>()
+        .map(Parameters::Positional)
+}
+
+fn py_value(py: Python<'_>, value: SqlValue) -> PyResult<PyObject> {
+    match value {
+        SqlValue::Null => Ok(py.None()),
+        SqlValue::Integer(i) => i.into_py_any(py),
+        SqlValue::Real(f) => f.into_py_any(py),
+        SqlValue::Text(s) => s.into_py_any(py),
+        SqlValue::Blob(b) => PyBytes::new(py, &b).into_py_any(py),
+    }
+}
+
+/// Result of running a statement: column names, rows and changed rows.
+struct Outcome {
+    columns: Vec<String>,
+    rows: VecDeque<Vec<SqlValue>>,
+    rowcount: i64,
+}
+
+fn bind(stmt: &mut rusqlite::Statement<'_>, parameters: &Parameters) -> rusqlite::Result<()> {
+    match parameters {
+        Parameters::Positional(values) => {
+            if values.len() != stmt.parameter_count() {
+                return Err(rusqlite::Error::InvalidParameterCount(
+                    values.len(),
+                    stmt.parameter_count(),
+                ));
+            }
+            for (position, value) in values.iter().enumerate() {
+                stmt.raw_bind_parameter(position + 1, value)?;
+            }
+        }
+        Parameters::Named(values) => {
+            for (name, value) in values {
+                let index = [":", "@", "$"]
+                    .iter()
+                    .find_map(|prefix| stmt.parameter_index(&format!("{prefix}{name}")).transpose())
+                    .transpose()?
+                    .ok_or_else(|| rusqlite::Error::InvalidParameterName(name.clone()))?;
+                stmt.raw_bind_parameter(index, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_statement(
+    conn: &Connection,
+    sql: &str,
+    parameters: &Parameters,
+) -> rusqlite::Result<Outcome> {
+    let mut stmt = conn.prepare(sql)?;
+    bind(&mut stmt, parameters)?;
+
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = VecDeque::new();
+    let mut query = stmt.raw_query();
+    while let Some(row) = query.next()? {
+        rows.push_back(
+            (0..columns.len())
+                .map(|i| row.get::<_, SqlValue>(i))
+                .collect::<rusqlite::Result<_>>()?,
+        );
+    }
+
+    let rowcount = if columns.is_empty() {
+        conn.changes() as i64
+    } else {
+        -1
+    };
+    Ok(Outcome {
+        columns,
+        rows,
+        rowcount,
+    })
+}
+
+/// DB-API 2.0 cursor over a [`PyDatabase`].
+///
+/// A statement runs to completion when it is executed and its rows are
+/// buffered, so fetching never holds the database.
+#[pyclass(name = "Cursor", module = "engram")]
+struct PyCursor {
+    conn: SharedConnection,
+    /// Column names of the last query, `None` before one ran.
+    columns: Option<Vec<String>>,
+    rows: VecDeque<Vec<SqlValue>>,
+    #[pyo3(get)]
+    rowcount: i64,
+    #[pyo3(get, set)]
+    arraysize: usize,
+    closed: bool,
+}
+
+impl PyCursor {
+    fn new(conn: SharedConnection) -> Self {
+        Self {
+            conn,
+            columns: None,
+            rows: VecDeque::new(),
+            rowcount: -1,
+            arraysize: 1,
+            closed: false,
+        }
+    }
+
+    fn check_open(&self) -> PyResult<()> {
+        if self.closed {
+            return Err(DatabaseError::new_err("cannot operate on a closed cursor"));
+        }
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        py: Python<'_>,
+        sql: &str,
+        parameters: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<()> {
+        self.check_open()?;
+        let parameters = self::parameters(parameters)?;
+        let outcome =
+            with_connection(py, &self.conn, |conn| run_statement(conn, sql, &parameters))?;
+
+        self.columns = (!outcome.columns.is_empty()).then_some(outcome.columns);
+        self.rows = outcome.rows;
+        self.rowcount = outcome.rowcount;
+        Ok(())
+    }
+
+    fn row<'py>(&self, py: Python<'py>, row: Vec<SqlValue>) -> PyResult<Bound<'py, PyTuple>> {
+        let values = row
+            .into_iter()
+            .map(|value| py_value(py, value))
+            .collect::<PyResult<Vec<_>>>()?;
+        PyTuple::new(py, values)
+    }
+
+    fn rows_list<'py>(
+        &self,
+        py: Python<'py>,
+        rows: impl IntoIterator<Item = Vec<SqlValue>>,
+    ) -> PyResult<Bound<'py, PyList>> {
+        let rows = rows
+            .into_iter()
+            .map(|row| self.row(py, row))
+            .collect::<PyResult<Vec<_>>>()?;
+        PyList::new(py, rows)
+    }
+}
+
+#[pymethods]
+impl PyCursor {
+    #[pyo3(signature = (sql, parameters=None))]
+    fn execute<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        sql: &str,
+        parameters: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let py = slf.py();
+        slf.run(py, sql, parameters)?;
+        Ok(slf)
+    }
+
+    /// Execute `sql` once for each parameter set in `seq_of_parameters`.
+    fn executemany<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        sql: &str,
+        seq_of_parameters: &Bound<'py, PyAny>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let py = slf.py();
+        let mut rowcount = 0;
+        for parameters in seq_of_parameters.try_iter()? {
+            slf.run(py, sql, Some(&parameters?))?;
+            rowcount += slf.rowcount.max(0);
+        }
+        slf.rowcount = rowcount;
+        Ok(slf)
+    }
+
+    /// Column descriptions of the last query as DB-API 7-tuples, or `None`.
+    #[getter]
+    fn description<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        let Some(columns) = &self.columns else {
+            return Ok(None);
+        };
+        let columns = columns
+            .iter()
+            .map(|name| {
+                (
+                    name,
+                    py.None(),
+                    py.None(),
+                    py.None(),
+                    py.None(),
+                    py.None(),
+                    py.None(),
+                )
+                    .into_pyobject(py)
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        PyTuple::new(py, columns).map(Some)
+    }
+
+    fn fetchone<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        self.check_open()?;
+        self.rows
+            .pop_front()
+            .map(|row| self.row(py, row))
+            .transpose()
+    }
+
+    #[pyo3(signature = (size=None))]
+    fn fetchmany<'py>(
+        &mut self,
+        py: Python<'py>,
+        size: Option<usize>,
+    ) -> PyResult<Bound<'py, PyList>> {
+        self.check_open()?;
+        let count = size.unwrap_or(self.arraysize).min(self.rows.len());
+        let rows: Vec<_> = self.rows.drain(..count).collect();
+        self.rows_list(py, rows)
+    }
+
+    fn fetchall<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        self.check_open()?;
+        let rows = std::mem::take(&mut self.rows);
+        self.rows_list(py, rows)
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        self.rows.clear();
+    }
+
+    fn setinputsizes(&self, _sizes: &Bound<'_, PyAny>) {}
+
+    #[pyo3(signature = (_size, _column=None))]
+    fn setoutputsize(&self, _size: &Bound<'_, PyAny>, _column: Option<&Bound<'_, PyAny>>) {}
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        self.fetchone(py)
+    }
+}
+
+/// Builds a new .eng archive, published atomically by `finalize`.
+///
+/// Writers are context managers: leaving the `with` block finalizes the
+/// archive, or aborts it when the block raised.
+#[pyclass(name = "Writer", module = "engram", frozen)]
+struct PyWriter {
+    inner: Mutex<Option<Writer>>,
+}
+
+fn writer_options(level: Option<i32>, deduplicate: bool) -> WriterOptions {
+    WriterOptions {
+        level,
+        deduplicate,
+        ..Default::default()
+    }
+}
+
+impl PyWriter {
+    /// Run `f` on the unfinished writer with the GIL released.
+    fn with_writer<T: Send>(
+        &self,
+        py: Python<'_>,
+        f: impl FnOnce(&mut Writer) -> engram_common::Result<T> + Send,
+    ) -> PyResult<T> {
+        py.allow_threads(|| {
+            let mut inner = self.inner.lock().unwrap();
+            let writer = inner
+                .as_mut()
+                .ok_or_else(|| PyValueError::new_err("writer is already finished"))?;
+            f(writer).map_err(archive_error)
+        })
+    }
+
+    fn take(&self, py: Python<'_>) -> PyResult<Writer> {
+        py.allow_threads(|| self.inner.lock().unwrap().take())
+            .ok_or_else(|| PyValueError::new_err("writer is already finished"))
+    }
+}
+
+#[pymethods]
+impl PyWriter {
+    /// Start a new archive at `path`.
+    ///
+    /// `level` applies to zstd and deflate entries; `deduplicate` stores
+    /// identical entries once.
+    #[new]
+    #[pyo3(signature = (path, *, level=None, deduplicate=false))]
+    fn create(py: Python<'_>, path: &str, level: Option<i32>, deduplicate: bool) -> PyResult<Self> {
+        let writer = py
+            .allow_threads(|| Writer::create(path, writer_options(level, deduplicate)))
+            .map_err(archive_error)?;
+        Ok(Self {
+            inner: Mutex::new(Some(writer)),
+        })
+    }
+
+    /// Update the archive at `path`, keeping entries that are not replaced
+    /// or deleted.
+    #[staticmethod]
+    #[pyo3(signature = (path, *, level=None, deduplicate=false))]
+    fn open_for_update(
+        py: Python<'_>,
+        path: &str,
+        level: Option<i32>,
+        deduplicate: bool,
+    ) -> PyResult<Self> {
+        let writer = py
+            .allow_threads(|| Writer::open_for_update(path, writer_options(level, deduplicate)))
+            .map_err(archive_error)?;
+        Ok(Self {
+            inner: Mutex::new(Some(writer)),
+        })
+    }
+
+    fn add_file(&self, py: Python<'_>, path: &str, data: PyBackedBytes) -> PyResult<()> {
+        self.with_writer(py, |writer| writer.add_file(path, &data))
+    }
+
+    fn add_text(&self, py: Python<'_>, path: &str, text: &str) -> PyResult<()> {
+        self.with_writer(py, |writer| writer.add_file(path, text.as_bytes()))
+    }
+
+    /// Add `value` serialized with the `json` module.
+    fn add_json(&self, py: Python<'_>, path: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let json: String = py
+            .import("json")?
+            .call_method1("dumps", (value,))?
+            .extract()?;
+        self.with_writer(py, |writer| writer.add_file(path, json.as_bytes()))
+    }
+
+    /// Add an entry with an explicit method: `"none"`, `"lz4"`, `"zstd"` or
+    /// `"deflate"`.
+    #[pyo3(signature = (path, data, method, level=None))]
+    fn add_file_with_compression(
+        &self,
+        py: Python<'_>,
+        path: &str,
+        data: PyBackedBytes,
+        method: &str,
+        level: Option<i32>,
+    ) -> PyResult<()> {
+        let method = codec::method_from_name(method).map_err(archive_error)?;
+        self.with_writer(py, |writer| {
+            writer.add_file_with_compression(path, &data, method, level)
+        })
+    }
+
+    fn add_file_from_disk(&self, py: Python<'_>, path: &str, disk_path: &str) -> PyResult<()> {
+        self.with_writer(py, |writer| {
+            writer.add_file_from_disk(path, Path::new(disk_path))
+        })
+    }
+
+    /// Add every file below `directory` under `prefix`, returning how many
+    /// were added.
+    #[pyo3(signature = (directory, prefix=""))]
+    fn add_directory(&self, py: Python<'_>, directory: &str, prefix: &str) -> PyResult<usize> {
+        self.with_writer(py, |writer| {
+            writer.add_directory(Path::new(directory), prefix)
+        })
+    }
+
+    fn add_manifest(&self, py: Python<'_>, manifest: &Bound<'_, PyAny>) -> PyResult<()> {
+        let json: String = py
+            .import("json")?
+            .call_method1("dumps", (manifest,))?
+            .extract()?;
+        let manifest: serde_json::Value = serde_json::from_str(&json)
+            .map_err(|e| PyValueError::new_err(format!("invalid manifest: {e}")))?;
+        self.with_writer(py, |writer| writer.add_manifest(&manifest))
+    }
+
+    /// Delete an entry of the archive being updated, returning whether it
+    /// existed.
+    fn delete_file(&self, py: Python<'_>, path: &str) -> PyResult<bool> {
+        self.with_writer(py, |writer| writer.delete_file(path))
+    }
+
+    /// Sign the archive with a 32-byte Ed25519 secret key when it is
+    /// finalized.
+    fn sign(&self, py: Python<'_>, secret_key: PyBackedBytes) -> PyResult<()> {
+        self.with_writer(py, |writer| writer.sign_with(&secret_key))
+    }
+
+    /// Finish the archive and move it into place, returning a dict of
+    /// statistics.
+    fn finalize<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let writer = self.take(py)?;
+        let stats = py
+            .allow_threads(|| writer.finalize())
+            .map_err(archive_error)?;
+
+        let dict = PyDict::new(py);
+        dict.set_item("entries", stats.entries)?;
+        dict.set_item("deduplicated_entries", stats.deduplicated)?;
+        dict.set_item("bytes_saved", stats.bytes_saved)?;
+        Ok(dict)
+    }
+
+    /// Discard the archive without touching its target path.
+    fn abort(&self, py: Python<'_>) {
+        if let Some(writer) = py.allow_threads(|| self.inner.lock().unwrap().take()) {
+            writer.abort();
+        }
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (exc_type=None, _exc_value=None, _traceback=None))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        let finished = py.allow_threads(|| self.inner.lock().unwrap().is_none());
+        if exc_type.is_some() {
+            self.abort(py);
+        } else if !finished {
+            self.finalize(py)?;
+        }
+        Ok(false)
+    }
+}
+
+#[pymodule]
+fn engram(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_class::<PyArchive>()?;
+    m.add_class::<PyDatabase>()?;
+    m.add_class::<PyCursor>()?;
+    m.add_class::<PyWriter>()?;
+    m.add("EngramError", py.get_type::<EngramError>())?;
+    m.add("SignatureError", py.get_type::<SignatureError>())?;
+    m.add("LimitError", py.get_type::<LimitError>())?;
+    m.add("DatabaseError", py.get_type::<DatabaseError>())?;
+
+    // DB-API 2.0 module globals.
+    m.add("apilevel", "2.0")?;
+    m.add("threadsafety", 1)?;
+    m.add("paramstyle", "qmark")?;
+    Ok(())
+}
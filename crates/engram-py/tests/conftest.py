@@ -0,0 +1,37 @@
+import sqlite3
+
+import pytest
+
+import engram
+
+
+@pytest.fixture
+def database_file(tmp_path):
+    """A SQLite database with a few users and a blob column."""
+    path = tmp_path / "app.db"
+    conn = sqlite3.connect(path)
+    conn.executescript(
+        """
+        CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL, score REAL, avatar BLOB);
+        INSERT INTO users (name, score, avatar) VALUES ('alice', 9.5, x'00ff');
+        INSERT INTO users (name, score, avatar) VALUES ('bob', 7.0, NULL);
+        INSERT INTO users (name, score, avatar) VALUES ('carol', NULL, NULL);
+        """
+    )
+    conn.commit()
+    conn.close()
+    return path
+
+
+@pytest.fixture
+def archive_path(tmp_path, database_file):
+    """An archive holding text, JSON, binary data, a database and a manifest."""
+    path = tmp_path / "site.eng"
+    with engram.Writer(str(path), deduplicate=True) as writer:
+        writer.add_text("index.html", "<h1>hello</h1>")
+        writer.add_json("config.json", {"debug": False, "retries": 3})
+        writer.add_file("assets/logo.bin", bytes(range(256)))
+        writer.add_file("assets/copy.bin", bytes(range(256)))
+        writer.add_file_from_disk("data/app.db", str(database_file))
+        writer.add_manifest({"name": "site", "version": "1.0.0"})
+    return path
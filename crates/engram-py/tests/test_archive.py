@@ -0,0 +1,80 @@
+import sys
+
+import pytest
+
+import engram
+
+
+def test_reads_entries(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        assert archive.read_text("index.html") == "<h1>hello</h1>"
+        assert archive.read_json("config.json") == {"debug": False, "retries": 3}
+        assert archive.read_file("assets/logo.bin") == bytes(range(256))
+        assert archive.read_manifest() == {"name": "site", "version": "1.0.0"}
+        assert "index.html" in archive
+        assert "missing.txt" not in archive
+        assert sorted(archive.list_prefix("assets/")) == ["assets/copy.bin", "assets/logo.bin"]
+        assert len(archive) == archive.entry_count
+
+
+def test_read_view_returns_read_only_memoryview(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        view = archive.read_view("assets/logo.bin")
+        assert isinstance(view, memoryview)
+        assert view.readonly
+        assert view.nbytes == 256
+        assert view[255] == 255
+        assert view.tobytes() == bytes(range(256))
+        with pytest.raises(TypeError):
+            view[0] = 1
+
+
+def test_metadata_and_hashes(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        metadata = archive.get_metadata("assets/copy.bin")
+        assert metadata["path"] == "assets/copy.bin"
+        assert metadata["uncompressed_size"] == 256
+        assert metadata["alias_of"] == "assets/logo.bin"
+        assert metadata["encrypted"] is False
+        assert archive.get_metadata("missing.txt") is None
+        assert archive.hash_file("assets/copy.bin") == archive.hash_file("assets/logo.bin")
+        assert archive.hash_file("assets/logo.bin") == metadata["hash"]
+
+
+def test_missing_entries_raise_key_error(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        with pytest.raises(KeyError):
+            archive.read_file("missing.txt")
+
+
+def test_context_manager_closes_archive(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        assert not archive.closed
+    assert archive.closed
+    with pytest.raises(ValueError, match="closed archive"):
+        archive.read_file("index.html")
+    archive.close()
+
+
+@pytest.mark.skipif(sys.platform != "linux", reason="in-memory archives need Linux")
+def test_from_bytes(archive_path):
+    with engram.Archive.from_bytes(archive_path.read_bytes()) as archive:
+        assert archive.read_text("index.html") == "<h1>hello</h1>"
+        with archive.open_database("data/app.db") as db:
+            assert db.execute("SELECT count(*) FROM users").fetchone() == (3,)
+    with pytest.raises(engram.EngramError):
+        engram.Archive.from_bytes(b"not an archive")
+
+
+@pytest.mark.skipif(sys.platform == "linux", reason="in-memory archives work on Linux")
+def test_from_bytes_is_linux_only(archive_path):
+    with pytest.raises(OSError, match="only supported on Linux"):
+        engram.Archive.from_bytes(archive_path.read_bytes())
+
+
+def test_signature_errors(archive_path):
+    with pytest.raises(engram.EngramError):
+        engram.Archive(str(archive_path), require_signature=True)
+    with pytest.raises(engram.SignatureError):
+        engram.Archive(str(archive_path), trusted_keys=[bytes(32)], require_signature=True)
+    assert issubclass(engram.SignatureError, engram.EngramError)
@@ -0,0 +1,83 @@
+import pytest
+
+import engram
+
+
+@pytest.fixture
+def db(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        with archive.open_database("data/app.db") as db:
+            yield db
+
+
+def test_module_globals():
+    assert engram.apilevel == "2.0"
+    assert engram.paramstyle == "qmark"
+
+
+def test_execute_and_fetch(db):
+    cursor = db.execute("SELECT id, name, score, avatar FROM users ORDER BY id")
+    assert [column[0] for column in cursor.description] == ["id", "name", "score", "avatar"]
+    assert all(len(column) == 7 for column in cursor.description)
+    assert cursor.rowcount == -1
+    assert cursor.fetchone() == (1, "alice", 9.5, b"\x00\xff")
+    assert cursor.fetchmany(1) == [(2, "bob", 7.0, None)]
+    assert cursor.fetchall() == [(3, "carol", None, None)]
+    assert cursor.fetchone() is None
+
+
+def test_parameters(db):
+    assert db.execute("SELECT name FROM users WHERE id = ?", (2,)).fetchall() == [("bob",)]
+    assert db.execute("SELECT name FROM users WHERE name = :name", {"name": "carol"}).fetchall() == [
+        ("carol",)
+    ]
+    with pytest.raises(engram.DatabaseError):
+        db.execute("SELECT name FROM users WHERE id = ?", (1, 2))
+    with pytest.raises(TypeError):
+        db.execute("SELECT ?", (object(),))
+
+
+def test_cursor_iteration_and_arraysize(db):
+    cursor = db.cursor()
+    cursor.arraysize = 2
+    cursor.execute("SELECT name FROM users ORDER BY id")
+    assert cursor.fetchmany() == [("alice",), ("bob",)]
+
+    names = [name for (name,) in db.execute("SELECT name FROM users ORDER BY id")]
+    assert names == ["alice", "bob", "carol"]
+
+
+def test_executemany(db):
+    cursor = db.cursor()
+    cursor.executemany("SELECT name FROM users WHERE id = ?", [(1,), (3,)])
+    assert cursor.fetchall() == [("carol",)]
+
+
+def test_closed_cursor_raises(db):
+    cursor = db.execute("SELECT name FROM users")
+    cursor.close()
+    with pytest.raises(engram.DatabaseError, match="closed cursor"):
+        cursor.fetchone()
+
+
+def test_sql_errors_raise_database_error(db):
+    with pytest.raises(engram.DatabaseError):
+        db.execute("SELECT * FROM missing")
+    assert issubclass(engram.DatabaseError, engram.EngramError)
+
+
+def test_context_manager_closes_database(archive_path):
+    with engram.Archive(str(archive_path)) as archive:
+        with archive.open_database("data/app.db") as db:
+            cursor = db.cursor()
+            db.commit()
+            db.rollback()
+        with pytest.raises(engram.DatabaseError, match="closed database"):
+            db.execute("SELECT 1")
+        with pytest.raises(engram.DatabaseError, match="closed database"):
+            cursor.execute("SELECT 1")
+
+
+def test_engram_files_table(db):
+    paths = [path for (path,) in db.execute("SELECT path FROM engram_files ORDER BY path")]
+    assert "index.html" in paths
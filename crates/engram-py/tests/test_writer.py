@@ -0,0 +1,84 @@
+import pytest
+
+import engram
+
+
+def test_context_manager_finalizes(tmp_path):
+    path = tmp_path / "out.eng"
+    with engram.Writer(str(path)) as writer:
+        writer.add_text("hello.txt", "hello")
+    assert path.exists()
+    with pytest.raises(ValueError, match="already finished"):
+        writer.add_text("late.txt", "too late")
+
+    with engram.Archive(str(path)) as archive:
+        assert archive.read_text("hello.txt") == "hello"
+
+
+def test_context_manager_aborts_on_error(tmp_path):
+    path = tmp_path / "out.eng"
+    with pytest.raises(RuntimeError):
+        with engram.Writer(str(path)) as writer:
+            writer.add_text("hello.txt", "hello")
+            raise RuntimeError("boom")
+    assert not path.exists()
+    assert list(tmp_path.iterdir()) == []
+
+
+def test_finalize_returns_stats(tmp_path):
+    writer = engram.Writer(str(tmp_path / "out.eng"), deduplicate=True)
+    writer.add_file("a.bin", b"same")
+    writer.add_file("b.bin", b"same")
+    stats = writer.finalize()
+    assert stats["entries"] == 2
+    assert stats["deduplicated_entries"] == 1
+    with pytest.raises(ValueError, match="already finished"):
+        writer.finalize()
+
+
+def test_compression_and_directories(tmp_path):
+    source = tmp_path / "site"
+    (source / "css").mkdir(parents=True)
+    (source / "index.html").write_text("<h1>hi</h1>")
+    (source / "css" / "app.css").write_text("body { color: red; }\n" * 32)
+
+    path = tmp_path / "out.eng"
+    with engram.Writer(str(path), level=19) as writer:
+        assert writer.add_directory(str(source), "www/") == 2
+        writer.add_file_with_compression("raw.bin", b"\x00" * 1024, "none")
+        writer.add_file_with_compression("packed.txt", b"abc" * 1024, "deflate", 9)
+        with pytest.raises(engram.EngramError):
+            writer.add_file_with_compression("bad.txt", b"abc", "zstd", 99)
+
+    with engram.Archive(str(path)) as archive:
+        assert archive.read_text("www/css/app.css") == "body { color: red; }\n" * 32
+        assert archive.get_metadata("raw.bin")["compression_method"] == "none"
+        packed = archive.get_metadata("packed.txt")
+        assert packed["compression_method"] == "deflate"
+        assert packed["compression_level"] == 9
+        assert archive.read_file("packed.txt") == b"abc" * 1024
+
+
+def test_open_for_update(archive_path):
+    with engram.Writer.open_for_update(str(archive_path)) as writer:
+        writer.add_text("index.html", "<h1>updated</h1>")
+        assert writer.delete_file("config.json")
+        assert not writer.delete_file("missing.txt")
+
+    with engram.Archive(str(archive_path)) as archive:
+        assert archive.read_text("index.html") == "<h1>updated</h1>"
+        assert "config.json" not in archive
+        assert archive.read_file("assets/copy.bin") == bytes(range(256))
+        with archive.open_database("data/app.db") as db:
+            assert db.execute("SELECT count(*) FROM users").fetchone() == (3,)
+
+
+def test_sign_rejects_bad_keys(tmp_path):
+    with engram.Writer(str(tmp_path / "out.eng")) as writer:
+        with pytest.raises(engram.EngramError):
+            writer.sign(b"short")
+        writer.sign(bytes(range(32)))
+        writer.add_text("hello.txt", "hello")
+
+    with engram.Archive(str(tmp_path / "out.eng")) as archive:
+        assert archive.read_text("hello.txt") == "hello"
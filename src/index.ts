@@ -8,6 +8,10 @@ import type {
   EngramArchive as NativeArchive,
   EngramWriter as NativeWriter,
   EngramDatabase as NativeDatabase,
+  EngramOverlay as NativeOverlay,
+  diffArchives as NativeDiffArchives,
+  createPatch as NativeCreatePatch,
+  applyPatch as NativeApplyPatch,
   CompressionMethod as NativeCompressionMethod,
   EntryMetadata as NativeEntryMetadata
 } from './native';
@@ -17,13 +21,59 @@ const nativeModule = require('../dist/index.js');
 const NativeArchiveImpl = nativeModule.EngramArchive as typeof NativeArchive;
 const NativeWriterImpl = nativeModule.EngramWriter as typeof NativeWriter;
 const NativeDatabaseImpl = nativeModule.EngramDatabase as typeof NativeDatabase;
+const NativeOverlayImpl = nativeModule.EngramOverlay as typeof NativeOverlay;
+const nativeDiffArchives = nativeModule.diffArchives as typeof NativeDiffArchives;
+const nativeCreatePatch = nativeModule.createPatch as typeof NativeCreatePatch;
+const nativeApplyPatch = nativeModule.applyPatch as typeof NativeApplyPatch;
 
 // Re-export native enums and interfaces
 export const CompressionMethod = nativeModule.CompressionMethod;
-export type { EntryMetadata } from './native';
+export const Cipher = nativeModule.Cipher;
+export type {
+  EntryMetadata,
+  CompressionRule,
+  CompressionPolicy,
+  DictionaryOptions,
+  WriterOptions,
+  FinalizeStats,
+  OpenOptions,
+  FinalizeOptions,
+  EncryptionOptions,
+  EncryptionKey,
+  ArchiveLimits,
+  DiffOptions,
+  PatchOptions,
+  PatchStats
+} from './native';
+
+/**
+ * `code` of errors raised when an archive signature is missing, untrusted or invalid
+ */
+export const SIGNATURE_ERROR_CODE = 'ERR_ENGRAM_SIGNATURE';
+
+/** Chunk size used to stream an in-memory archive */
+const CHUNK_SIZE = 1024 * 1024;
+
+/**
+ * `code` of errors raised when an archive exceeds the limits it was opened with
+ */
+export const LIMIT_ERROR_CODE = 'ERR_ENGRAM_LIMIT';
 
 // Import for internal use
-import type { CompressionMethod as CompressionMethodType, EntryMetadata as EntryMetadataType } from './native';
+import type {
+  CompressionMethod as CompressionMethodType,
+  EntryMetadata as EntryMetadataType,
+  FinalizeOptions as FinalizeOptionsType,
+  FinalizeStats as FinalizeStatsType,
+  OpenOptions as OpenOptionsType,
+  DiffOptions as DiffOptionsType,
+  PatchOptions as PatchOptionsType,
+  PatchStats as PatchStatsType,
+  WriterOptions as WriterOptionsType
+} from './native';
+import * as fs from 'fs';
+import { Readable, Writable } from 'stream';
+import { pipeline } from 'stream/promises';
 
 /**
  * Archive reader for accessing files and databases from .eng archives
@@ -31,8 +81,22 @@ import type { CompressionMethod as CompressionMethodType, EntryMetadata as Entry
 export class EngramArchive {
   private native: NativeArchive;
 
-  constructor(path: string) {
-    this.native = new NativeArchiveImpl(path);
+  /**
+   * Open an archive, optionally requiring a signature from a trusted key
+   */
+  constructor(path: string, options?: OpenOptionsType) {
+    this.native = new NativeArchiveImpl(path, options);
+  }
+
+  /**
+   * Open an archive held in memory, e.g. downloaded from an object store
+   *
+   * The buffer is copied and kept in memory. Only supported on Linux.
+   */
+  static fromBuffer(data: Buffer, options?: OpenOptionsType): EngramArchive {
+    const archive: EngramArchive = Object.create(EngramArchive.prototype);
+    archive.native = NativeArchiveImpl.fromBuffer(data, options);
+    return archive;
   }
 
   /**
@@ -77,6 +141,13 @@ export class EngramArchive {
     return await this.native.readFile(path);
   }
 
+  /**
+   * Hex-encoded SHA-256 of a file, verified against the hash recorded by the writer
+   */
+  async hashFile(path: string): Promise<string> {
+    return await this.native.hashFile(path);
+  }
+
   /**
    * Read multiple files from the archive (batch operation)
    */
@@ -125,6 +196,159 @@ export class EngramArchive {
   }
 }
 
+/**
+ * Union view over archives, e.g. patch archives stacked on a base archive
+ *
+ * Layers are listed highest precedence first. A whiteout entry added with
+ * `EngramWriter.addWhiteout()` hides a file or directory of the layers below.
+ */
+export class EngramOverlay {
+  private native: NativeOverlay;
+
+  constructor(layers: string[], options?: OpenOptionsType) {
+    this.native = new NativeOverlayImpl(layers, options);
+  }
+
+  /**
+   * List the visible files of every layer
+   */
+  listFiles(): string[] {
+    return this.native.listFiles();
+  }
+
+  /**
+   * List visible files with a given prefix
+   */
+  listPrefix(prefix: string): string[] {
+    return this.native.listPrefix(prefix);
+  }
+
+  /**
+   * Check if a file is visible in any layer
+   */
+  contains(path: string): boolean {
+    return this.native.contains(path);
+  }
+
+  /**
+   * Index of the layer a file is read from, or null if it is not visible
+   */
+  layerOf(path: string): number | null {
+    return this.native.layerOf(path);
+  }
+
+  /**
+   * Get metadata for a file from the layer that provides it
+   */
+  getMetadata(path: string): EntryMetadataType | null {
+    return this.native.getMetadata(path);
+  }
+
+  /**
+   * Read a file from the layer that provides it (synchronous)
+   */
+  readFileSync(path: string): Buffer {
+    return this.native.readFileSync(path);
+  }
+
+  /**
+   * Read a file from the layer that provides it (asynchronous)
+   */
+  async readFile(path: string): Promise<Buffer> {
+    return await this.native.readFile(path);
+  }
+
+  /**
+   * Read a file as UTF-8 text
+   */
+  async readText(path: string): Promise<string> {
+    const buffer = await this.readFile(path);
+    return buffer.toString('utf-8');
+  }
+
+  /**
+   * Read a file as JSON
+   */
+  async readJson<T = any>(path: string): Promise<T> {
+    const text = await this.readText(path);
+    return JSON.parse(text);
+  }
+
+  /**
+   * Open a SQLite database from the layer that provides it
+   */
+  openDatabase(dbPath: string): EngramDatabase {
+    return new EngramDatabase(this.native.openDatabase(dbPath));
+  }
+}
+
+/**
+ * Options for user-defined SQL functions
+ */
+export interface FunctionOptions {
+  /** The function always returns the same result for the same arguments */
+  deterministic?: boolean;
+  /** Accept any number of arguments instead of the function's declared count */
+  varargs?: boolean;
+  /**
+   * Number of SQL arguments the function takes. Defaults to the declared
+   * count, or any number when that is 0, as for rest or default parameters
+   */
+  arity?: number;
+}
+
+/**
+ * User-defined aggregate SQL function
+ */
+export interface AggregateOptions<A = any> extends FunctionOptions {
+  /** Initial accumulator, or a function returning a fresh one for each group */
+  start?: A | (() => A);
+  /** Fold a row into the accumulator; returning `undefined` keeps it unchanged */
+  step: (accumulator: A, ...args: any[]) => A | void;
+  /** Map the final accumulator to the result; defaults to the accumulator itself */
+  result?: (accumulator: A) => unknown;
+}
+
+/**
+ * Result of a user-defined function as handed back to the native side, which
+ * raises errors through SQLite rather than having them thrown through it
+ */
+type CallbackResult = { value: unknown } | { error: string };
+
+/**
+ * Prepare a value for SQLite. Byte arrays other than Buffers are passed as
+ * Buffers, and other objects and arrays as JSON text.
+ */
+function sqlValue(value: unknown): unknown {
+  if (value instanceof Uint8Array && !Buffer.isBuffer(value)) {
+    return Buffer.from(value.buffer, value.byteOffset, value.byteLength);
+  }
+  if (value !== null && typeof value === 'object' && !Buffer.isBuffer(value)) {
+    return JSON.stringify(value);
+  }
+  return value;
+}
+
+/**
+ * Run a user-defined function and prepare its result for SQLite
+ */
+function callbackResult(run: () => unknown): CallbackResult {
+  try {
+    return { value: sqlValue(run()) };
+  } catch (error) {
+    return { error: error instanceof Error ? error.message : String(error) };
+  }
+}
+
+/**
+ * SQL arity of a JavaScript function taking `declared` arguments. A count of
+ * 0 may hide rest or default parameters, so it accepts any number
+ */
+function functionArity(options: FunctionOptions, declared: number): number {
+  if (options.arity !== undefined) return options.arity;
+  return options.varargs || declared === 0 ? -1 : declared;
+}
+
 /**
  * SQLite database connection from archive
  */
@@ -132,12 +356,12 @@ export class EngramDatabase {
   constructor(private native: NativeDatabase) {}
 
   /**
-   * Execute a query and return results
+   * Execute a query and return results. Integers are returned as numbers, or
+   * BigInts beyond `Number.MAX_SAFE_INTEGER`, and blobs as Buffers; parameters
+   * are converted the same way, with other objects passed as JSON text
    */
   query<T = any>(sql: string, params?: any[]): T[] {
-    const paramsJson = params ? JSON.stringify(params) : undefined;
-    const resultJson = this.native.query(sql, paramsJson);
-    return JSON.parse(resultJson) as T[];
+    return this.native.query(sql, params?.map(sqlValue)) as T[];
   }
 
   /**
@@ -145,8 +369,7 @@ export class EngramDatabase {
    * @returns Number of rows affected
    */
   execute(sql: string, params?: any[]): number {
-    const paramsJson = params ? JSON.stringify(params) : undefined;
-    return this.native.execute(sql, paramsJson);
+    return this.native.execute(sql, params?.map(sqlValue));
   }
 
   /**
@@ -177,6 +400,93 @@ export class EngramDatabase {
     );
     return result ? result > 0 : false;
   }
+
+  /**
+   * Attach another database under `alias` for queries across databases:
+   * a database stored in an archive or overlay, or a SQLite file on disk
+   *
+   * Databases from archives are attached as read-only snapshots, read into
+   * memory in full when attached.
+   */
+  attach(source: EngramArchive | EngramOverlay, dbPath: string, alias: string): this;
+  attach(filePath: string, alias: string): this;
+  attach(source: EngramArchive | EngramOverlay | string, dbPathOrAlias: string, alias?: string): this {
+    if (typeof source === 'string') {
+      this.native.attachFile(source, dbPathOrAlias);
+      return this;
+    }
+    if (alias === undefined) {
+      throw new TypeError('Expected an alias for the attached database');
+    }
+    this.native.attachSnapshot(source.openDatabase(dbPathOrAlias).native, alias);
+    return this;
+  }
+
+  /**
+   * Register a scalar SQL function implemented in JavaScript. Integers are
+   * passed as numbers, or BigInts beyond `Number.MAX_SAFE_INTEGER`, and blobs
+   * as Buffers; the result is converted back the same way. Errors thrown by
+   * `fn` fail the statement.
+   */
+  function(name: string, fn: (...args: any[]) => unknown): this;
+  function(name: string, options: FunctionOptions, fn: (...args: any[]) => unknown): this;
+  function(
+    name: string,
+    optionsOrFn: FunctionOptions | ((...args: any[]) => unknown),
+    fn?: (...args: any[]) => unknown
+  ): this {
+    const options = typeof optionsOrFn === 'function' ? {} : optionsOrFn;
+    const implementation = typeof optionsOrFn === 'function' ? optionsOrFn : fn;
+    if (typeof implementation !== 'function') {
+      throw new TypeError('Expected a function implementation');
+    }
+
+    this.native.function(
+      name,
+      functionArity(options, implementation.length),
+      options.deterministic ?? false,
+      (args: unknown[]) => callbackResult(() => implementation(...args))
+    );
+    return this;
+  }
+
+  /**
+   * Register an aggregate SQL function implemented in JavaScript
+   */
+  aggregate<A = any>(name: string, options: AggregateOptions<A>): this {
+    const { start = null, step, result } = options;
+    if (typeof step !== 'function') {
+      throw new TypeError('Expected a step function');
+    }
+
+    // Accumulators of the groups being aggregated, keyed by the native group id
+    const groups = new Map<number, A>();
+    const initial = (): A => (typeof start === 'function' ? (start as () => A)() : (start as A));
+
+    this.native.aggregate(
+      name,
+      functionArity(options, Math.max(step.length - 1, 0)),
+      options.deterministic ?? false,
+      (event: string, group: number, args: unknown[]) =>
+        callbackResult(() => {
+          const accumulator = groups.has(group) ? (groups.get(group) as A) : initial();
+          if (event === 'step') {
+            try {
+              const next = step(accumulator, ...args);
+              groups.set(group, next === undefined ? accumulator : next);
+            } catch (error) {
+              // The statement fails, so the group is never finished.
+              groups.delete(group);
+              throw error;
+            }
+            return null;
+          }
+          groups.delete(group);
+          return result ? result(accumulator) : accumulator;
+        })
+    );
+    return this;
+  }
 }
 
 /**
@@ -186,8 +496,35 @@ export class EngramWriter {
   private native: NativeWriter;
   private finalized = false;
 
-  constructor(path: string) {
-    this.native = new NativeWriterImpl(path);
+  constructor(path: string, options?: WriterOptionsType) {
+    this.native = new NativeWriterImpl(path, options);
+  }
+
+  /**
+   * Open an existing archive to add, replace and delete entries
+   *
+   * Entries that are not replaced or deleted are carried over on finalize(),
+   * which atomically replaces the original archive. Only entries written with
+   * an explicit level or a dictionary are copied without recompression.
+   */
+  static openForUpdate(path: string, options?: WriterOptionsType): EngramWriter {
+    const writer: EngramWriter = Object.create(EngramWriter.prototype);
+    writer.native = NativeWriterImpl.openForUpdate(path, options);
+    writer.finalized = false;
+    return writer;
+  }
+
+  /**
+   * Create a writer that builds the archive in memory
+   *
+   * Finish it with toBuffer() or toStream() instead of finalize(). Only
+   * supported on Linux.
+   */
+  static inMemory(options?: WriterOptionsType): EngramWriter {
+    const writer: EngramWriter = Object.create(EngramWriter.prototype);
+    writer.native = NativeWriterImpl.inMemory(options);
+    writer.finalized = false;
+    return writer;
   }
 
   /**
@@ -199,15 +536,16 @@ export class EngramWriter {
   }
 
   /**
-   * Add a file with specific compression
+   * Add a file with specific compression and optional level
    */
   addFileWithCompression(
     path: string,
     data: Buffer,
-    compression: CompressionMethodType
+    compression: CompressionMethodType,
+    level?: number
   ): void {
     this.checkNotFinalized();
-    this.native.addFileWithCompression(path, data, compression);
+    this.native.addFileWithCompression(path, data, compression, level);
   }
 
   /**
@@ -218,6 +556,15 @@ export class EngramWriter {
     this.native.addFileFromDisk(archivePath, diskPath);
   }
 
+  /**
+   * Add every file below a directory
+   * @returns Number of files added
+   */
+  addDirectory(diskPath: string, prefix?: string): number {
+    this.checkNotFinalized();
+    return this.native.addDirectory(diskPath, prefix);
+  }
+
   /**
    * Add text content as a file
    */
@@ -250,12 +597,84 @@ export class EngramWriter {
   }
 
   /**
-   * Finalize the archive (must be called before the writer is dropped)
+   * Delete an entry from an archive opened with openForUpdate()
+   * @returns false if the archive has no such entry
    */
-  finalize(): void {
+  deleteFile(path: string): boolean {
     this.checkNotFinalized();
-    this.native.finalize();
+    return this.native.deleteFile(path);
+  }
+
+  /**
+   * Add a whiteout entry that hides `path`, and everything below it, in the
+   * layers under this archive in an EngramOverlay
+   */
+  addWhiteout(path: string): void {
+    this.checkNotFinalized();
+    this.native.addWhiteout(path);
+  }
+
+  /**
+   * Finalize the archive and atomically move it to the target path
+   *
+   * Until then the archive is written to a temporary file next to the target,
+   * so readers never observe a partially written archive.
+   * Pass a `signingKey` to sign the archive with Ed25519.
+   * @returns Entry count and deduplication savings
+   */
+  finalize(options?: FinalizeOptionsType): FinalizeStatsType {
+    this.checkNotFinalized();
+    const stats = this.native.finalize(options);
     this.finalized = true;
+    return stats;
+  }
+
+  /**
+   * Finalize an archive created with inMemory() and return its bytes
+   *
+   * Pass a `signingKey` to sign the archive with Ed25519.
+   */
+  toBuffer(options?: FinalizeOptionsType): Buffer {
+    this.checkNotFinalized();
+    const data = this.native.toBuffer(options);
+    this.finalized = true;
+    return data;
+  }
+
+  /**
+   * Finalize the archive and write it to `writable`
+   *
+   * The bytes are written in chunks that respect backpressure, and the
+   * stream is ended afterwards. A writer with a path is finalized to it
+   * first and the finished file is streamed from disk. An inMemory() writer
+   * holds the whole archive in memory and copies it into one Buffer first,
+   * so its peak memory is about twice the archive size.
+   */
+  async toStream(writable: Writable, options?: FinalizeOptionsType): Promise<void> {
+    this.checkNotFinalized();
+    const target = this.native.targetPath;
+    if (target !== null) {
+      this.finalize(options);
+      await pipeline(fs.createReadStream(target, { highWaterMark: CHUNK_SIZE }), writable);
+      return;
+    }
+
+    const data = this.toBuffer(options);
+    const chunks = function* () {
+      for (let offset = 0; offset < data.length; offset += CHUNK_SIZE) {
+        yield data.subarray(offset, offset + CHUNK_SIZE);
+      }
+    };
+    await pipeline(Readable.from(chunks()), writable);
+  }
+
+  /**
+   * Discard the partially written archive, leaving the target path untouched
+   */
+  abort(): void {
+    this.checkNotFinalized();
+    this.finalized = true;
+    this.native.abort();
   }
 
   private checkNotFinalized(): void {
@@ -287,3 +706,128 @@ export function createManifest(data: EngramManifest): EngramManifest {
     created: data.created || new Date().toISOString()
   };
 }
+
+/**
+ * Entry present on only one side of a diff
+ */
+export interface DiffEntry {
+  path: string;
+  size: number;
+  compressedSize: number;
+  compression: string;
+}
+
+/**
+ * Entry whose content or compression changed
+ */
+export interface DiffEntryChange {
+  path: string;
+  oldSize: number;
+  newSize: number;
+  sizeDelta: number;
+  oldCompressedSize: number;
+  newCompressedSize: number;
+  oldCompression: string;
+  newCompression: string;
+  contentChanged: boolean;
+  compressionChanged: boolean;
+}
+
+/**
+ * Manifest value that was added (`old` is null), removed (`new` is null) or replaced
+ */
+export interface ManifestChange {
+  /** JSON pointer of the value, e.g. `/version` */
+  pointer: string;
+  old: any | null;
+  new: any | null;
+}
+
+/**
+ * Row changes of one table
+ */
+export interface TableDiff {
+  name: string;
+  /** The columns changed, so rows were not compared */
+  schemaChanged: boolean;
+  added: Record<string, any>[];
+  removed: Record<string, any>[];
+  modified: { key: any; old: Record<string, any>; new: Record<string, any> }[];
+}
+
+/**
+ * Row changes of a database that changed between the archives
+ */
+export interface DatabaseDiff {
+  path: string;
+  tablesAdded: string[];
+  tablesRemoved: string[];
+  tables: TableDiff[];
+}
+
+/**
+ * Changes from one archive to another
+ */
+export interface ArchiveDiff {
+  added: DiffEntry[];
+  removed: DiffEntry[];
+  modified: DiffEntryChange[];
+  unchanged: number;
+  /** Change in the total uncompressed size */
+  sizeDelta: number;
+  /** Change in the total stored size */
+  compressedSizeDelta: number;
+  manifest: ManifestChange[];
+  /** Only filled in with `databases: true` */
+  databases: DatabaseDiff[];
+}
+
+/**
+ * Compare two archives
+ *
+ * Entries are compared by SHA-256 when both archives recorded one, and by
+ * CRC-32 and size otherwise. With `databases: true`, SQLite databases whose
+ * content changed are also compared row by row.
+ */
+export function diffArchives(
+  oldPath: string,
+  newPath: string,
+  options?: DiffOptionsType & OpenOptionsType
+): ArchiveDiff {
+  const { databases, ...openOptions } = options ?? {};
+  return JSON.parse(nativeDiffArchives(oldPath, newPath, { databases }, openOptions));
+}
+
+/**
+ * Write a patch that rebuilds the archive at `newPath` from the one at `oldPath`
+ *
+ * The patch is itself an archive. Entries found unchanged in the old archive
+ * are referenced, changed entries are stored as zstd deltas against their old
+ * version, and new entries are stored in full.
+ */
+export function createPatch(
+  oldPath: string,
+  newPath: string,
+  patchPath: string,
+  options?: PatchOptionsType & OpenOptionsType
+): PatchStatsType {
+  const { level, ...openOptions } = options ?? {};
+  return nativeCreatePatch(oldPath, newPath, patchPath, { level }, openOptions);
+}
+
+/**
+ * Rebuild the new archive from `basePath` and the patch at `patchPath`, writing it to `outPath`
+ *
+ * Every base entry the patch uses and every rebuilt entry is checked against
+ * the SHA-256 recorded in the patch, and `outPath` is only written once the
+ * whole archive is rebuilt. Open options apply to both the base and the
+ * patch, so `trustedKeys` can require a signed patch.
+ */
+export function applyPatch(
+  basePath: string,
+  patchPath: string,
+  outPath: string,
+  options?: OpenOptionsType
+): FinalizeStatsType {
+  return nativeApplyPatch(basePath, patchPath, outPath, options);
+}
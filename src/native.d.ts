@@ -1,7 +1,8 @@
 // Type definitions for native bindings
 
 export class EngramArchive {
-  constructor(path: string);
+  constructor(path: string, options?: OpenOptions | null);
+  static fromBuffer(data: Buffer, options?: OpenOptions | null): EngramArchive;
   entryCount(): number;
   listFiles(): string[];
   contains(path: string): boolean;
@@ -9,23 +10,86 @@ export class EngramArchive {
   readFileSync(path: string): Buffer;
   readFile(path: string): Promise<Buffer>;
   readFiles(paths: string[]): Promise<Buffer[]>;
+  hashFile(path: string): Promise<string>;
   readManifest(): string | null;
   listPrefix(prefix: string): string[];
   openDatabase(dbPath: string): EngramDatabase;
 }
 
 export class EngramWriter {
-  constructor(path: string);
+  constructor(path: string, options?: WriterOptions | null);
+  static openForUpdate(path: string, options?: WriterOptions | null): EngramWriter;
+  static inMemory(options?: WriterOptions | null): EngramWriter;
   addFile(path: string, data: Buffer): void;
-  addFileWithCompression(path: string, data: Buffer, compression: CompressionMethod): void;
+  addFileWithCompression(path: string, data: Buffer, compression: CompressionMethod, level?: number | null): void;
   addFileFromDisk(archivePath: string, diskPath: string): void;
+  addDirectory(diskPath: string, prefix?: string | null): number;
   addManifest(manifest: string): void;
-  finalize(): void;
+  deleteFile(path: string): boolean;
+  addWhiteout(path: string): void;
+  finalize(options?: FinalizeOptions | null): FinalizeStats;
+  toBuffer(options?: FinalizeOptions | null): Buffer;
+  readonly targetPath: string | null;
+  abort(): void;
+}
+
+export class EngramOverlay {
+  constructor(layers: string[], options?: OpenOptions | null);
+  listFiles(): string[];
+  listPrefix(prefix: string): string[];
+  contains(path: string): boolean;
+  layerOf(path: string): number | null;
+  getMetadata(path: string): EntryMetadata | null;
+  readFileSync(path: string): Buffer;
+  readFile(path: string): Promise<Buffer>;
+  openDatabase(dbPath: string): EngramDatabase;
+}
+
+export function diffArchives(
+  oldPath: string,
+  newPath: string,
+  options?: DiffOptions | null,
+  open?: OpenOptions | null
+): string;
+
+export interface DiffOptions {
+  databases?: boolean;
+}
+
+export function createPatch(
+  oldPath: string,
+  newPath: string,
+  patchPath: string,
+  options?: PatchOptions | null,
+  open?: OpenOptions | null
+): PatchStats;
+
+export function applyPatch(
+  basePath: string,
+  patchPath: string,
+  outPath: string,
+  open?: OpenOptions | null
+): FinalizeStats;
+
+export interface PatchOptions {
+  level?: number;
+}
+
+export interface PatchStats {
+  copied: number;
+  deltas: number;
+  literals: number;
+  newBytes: number;
+  patchBytes: number;
 }
 
 export class EngramDatabase {
-  query(sql: string, params?: string): string;
-  execute(sql: string, params?: string): number;
+  query(sql: string, params?: unknown[]): Record<string, unknown>[];
+  execute(sql: string, params?: unknown[]): number;
+  attachSnapshot(source: EngramDatabase, alias: string): void;
+  attachFile(path: string, alias: string): void;
+  function(name: string, nArgs: number, deterministic: boolean, callback: (args: unknown[]) => { value: unknown } | { error: string }): void;
+  aggregate(name: string, nArgs: number, deterministic: boolean, callback: (event: 'step' | 'result', group: number, args: unknown[]) => { value: unknown } | { error: string }): void;
 }
 
 export enum CompressionMethod {
@@ -35,10 +99,84 @@ export enum CompressionMethod {
   Deflate = 3,
 }
 
+export interface CompressionRule {
+  extensions: string[];
+  compression: CompressionMethod;
+}
+
+export interface CompressionPolicy {
+  rules?: CompressionRule[];
+  defaultCompression?: CompressionMethod;
+  storeBelow?: number;
+  entropyThreshold?: number;
+  entropySampleSize?: number;
+  sampleEntropy?: boolean;
+}
+
+export interface DictionaryOptions {
+  maxSize?: number;
+  sampleCount?: number;
+  maxEntrySize?: number;
+  extensions?: string[];
+}
+
+export enum Cipher {
+  Aes256Gcm = 0,
+  ChaCha20Poly1305 = 1,
+}
+
+export interface EncryptionOptions {
+  cipher: Cipher;
+  key: Buffer;
+  keyId?: string;
+}
+
+export interface EncryptionKey {
+  key: Buffer;
+  keyId?: string;
+}
+
+export interface WriterOptions {
+  compressionPolicy?: CompressionPolicy;
+  compressionLevel?: number;
+  dictionary?: DictionaryOptions;
+  deduplicate?: boolean;
+  encryption?: EncryptionOptions;
+}
+
+export interface OpenOptions {
+  trustedKeys?: Buffer[];
+  requireSignature?: boolean;
+  keys?: EncryptionKey[];
+  limits?: ArchiveLimits;
+}
+
+export interface ArchiveLimits {
+  maxEntries?: number;
+  maxPathLength?: number;
+  maxEntrySize?: number;
+  maxCompressionRatio?: number;
+  maxTotalBytes?: number;
+}
+
+export interface FinalizeOptions {
+  signingKey?: Buffer;
+}
+
+export interface FinalizeStats {
+  entries: number;
+  deduplicatedEntries: number;
+  bytesSaved: number;
+}
+
 export interface EntryMetadata {
   path: string;
   uncompressedSize: number;
   compressedSize: number;
   compressionMethod: string;
+  compressionLevel?: number;
+  aliasOf?: string;
+  encrypted: boolean;
+  hash?: string;
   modifiedTime: number;
 }
@@ -2,16 +2,41 @@
  * Integration tests for engram-nodejs
  */
 
-import { EngramArchive, EngramWriter, createManifest, CompressionMethod } from '../src/index';
+import {
+  EngramArchive,
+  EngramWriter,
+  EngramOverlay,
+  createManifest,
+  diffArchives,
+  createPatch,
+  applyPatch,
+  CompressionMethod,
+  Cipher,
+  LIMIT_ERROR_CODE,
+  SIGNATURE_ERROR_CODE
+} from '../src/index';
 import { createTestDatabase, cleanupTestFiles } from './helpers';
+import Database from 'better-sqlite3';
+import * as crypto from 'crypto';
 import * as fs from 'fs';
 import * as path from 'path';
 import * as os from 'os';
+import { Writable } from 'stream';
 
 const TEST_DIR = path.join(os.tmpdir(), 'engram-tests');
 const TEST_ARCHIVE = path.join(TEST_DIR, 'test.eng');
 const TEST_DB = path.join(TEST_DIR, 'test.db');
 
+/** Raw 32-byte Ed25519 secret and public keys, as the writer and reader take them. */
+const rawKeyPair = () => {
+  const { privateKey, publicKey } = crypto.generateKeyPairSync('ed25519');
+  const jwk = privateKey.export({ format: 'jwk' });
+  return {
+    secretKey: Buffer.from(jwk.d as string, 'base64url'),
+    publicKey: Buffer.from(publicKey.export({ format: 'jwk' }).x as string, 'base64url')
+  };
+};
+
 describe('Engram System Integration Tests', () => {
   beforeAll(() => {
     // Create test directory
@@ -148,6 +173,745 @@ describe('Engram System Integration Tests', () => {
     });
   });
 
+  describe('Compression Policy', () => {
+    it('should pick compression from the policy', () => {
+      const archivePath = path.join(TEST_DIR, 'policy.eng');
+      const text = Buffer.from('Compressible text content. '.repeat(200));
+      const noise = crypto.randomBytes(64 * 1024);
+
+      const writer = new EngramWriter(archivePath, {
+        compressionPolicy: {
+          rules: [{ extensions: ['png'], compression: CompressionMethod.None }],
+          defaultCompression: CompressionMethod.Zstd,
+          storeBelow: 64
+        }
+      });
+      writer.addFile('image.png', text);
+      writer.addFile('notes.txt', text);
+      writer.addFile('tiny.txt', Buffer.from('tiny'));
+      writer.addFile('noise.bin', noise);
+      writer.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.getMetadata('image.png')?.compressionMethod).toBe('None');
+      expect(reader.getMetadata('notes.txt')?.compressionMethod).toBe('Zstd');
+      expect(reader.getMetadata('tiny.txt')?.compressionMethod).toBe('None');
+      expect(reader.getMetadata('noise.bin')?.compressionMethod).toBe('None');
+      expect(Buffer.compare(reader.readFileSync('noise.bin'), noise)).toBe(0);
+    });
+
+    it('should pack a directory', () => {
+      const sourceDir = path.join(TEST_DIR, 'pack-source');
+      fs.mkdirSync(path.join(sourceDir, 'nested'), { recursive: true });
+      fs.writeFileSync(path.join(sourceDir, 'index.html'), '<h1>Hello</h1>');
+      fs.writeFileSync(path.join(sourceDir, 'nested', 'data.json'), '{"ok":true}');
+
+      const archivePath = path.join(TEST_DIR, 'directory.eng');
+      const writer = new EngramWriter(archivePath, { compressionPolicy: {} });
+      expect(writer.addDirectory(sourceDir, 'site')).toBe(2);
+      writer.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.listFiles().sort()).toEqual(['site/index.html', 'site/nested/data.json']);
+      expect(reader.readFileSync('site/nested/data.json').toString('utf-8')).toBe('{"ok":true}');
+    });
+
+    it('should not follow directory symlinks when packing', () => {
+      const sourceDir = path.join(TEST_DIR, 'symlink-source');
+      fs.mkdirSync(path.join(sourceDir, 'nested'), { recursive: true });
+      fs.writeFileSync(path.join(sourceDir, 'nested', 'data.txt'), 'data');
+      fs.symlinkSync(sourceDir, path.join(sourceDir, 'nested', 'loop'), 'dir');
+      fs.symlinkSync(path.join(sourceDir, 'nested', 'data.txt'), path.join(sourceDir, 'link.txt'));
+
+      const archivePath = path.join(TEST_DIR, 'symlinks.eng');
+      const writer = new EngramWriter(archivePath);
+      expect(writer.addDirectory(sourceDir)).toBe(2);
+      writer.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.listFiles().sort()).toEqual(['link.txt', 'nested/data.txt']);
+      expect(reader.readFileSync('link.txt').toString('utf-8')).toBe('data');
+    });
+  });
+
+  describe('Compression Levels and Dictionaries', () => {
+    it('should honour per-entry and writer-wide levels', () => {
+      const archivePath = path.join(TEST_DIR, 'levels.eng');
+      const testData = Buffer.from('Level test data that compresses nicely. '.repeat(200));
+
+      const writer = new EngramWriter(archivePath, { compressionLevel: 19 });
+      writer.addFile('default.txt', testData);
+      writer.addFileWithCompression('fast.txt', testData, CompressionMethod.Zstd, 1);
+      writer.addFileWithCompression('deflate.txt', testData, CompressionMethod.Deflate, 9);
+      writer.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.entryCount).toBe(3);
+      expect(reader.getMetadata('default.txt')?.compressionLevel).toBe(19);
+      expect(reader.getMetadata('fast.txt')?.compressionLevel).toBe(1);
+      expect(reader.getMetadata('deflate.txt')?.compressionMethod).toBe('Deflate');
+      expect(reader.getMetadata('deflate.txt')?.uncompressedSize).toBe(testData.length);
+
+      for (const file of ['default.txt', 'fast.txt', 'deflate.txt']) {
+        expect(Buffer.compare(reader.readFileSync(file), testData)).toBe(0);
+      }
+    });
+
+    it('should reject out-of-range levels', () => {
+      const writer = new EngramWriter(path.join(TEST_DIR, 'bad-level.eng'));
+      expect(() => {
+        writer.addFileWithCompression('a.txt', Buffer.from('a'), CompressionMethod.Zstd, 99);
+      }).toThrow();
+    });
+
+    it('should train and transparently use a zstd dictionary', async () => {
+      const archivePath = path.join(TEST_DIR, 'dictionary.eng');
+      const docs = Array.from({ length: 200 }, (_, i) => ({
+        id: i,
+        name: `user-${i}`,
+        email: `user-${i}@example.com`,
+        roles: ['reader', 'writer'],
+        active: i % 2 === 0
+      }));
+
+      const writer = new EngramWriter(archivePath, {
+        dictionary: { sampleCount: 50, maxSize: 8 * 1024 }
+      });
+      docs.forEach((doc, i) => writer.addJson(`docs/${i}.json`, doc));
+      writer.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.entryCount).toBe(docs.length);
+      expect(reader.listFiles().some((file) => file.startsWith('.engram/'))).toBe(false);
+
+      const meta = reader.getMetadata('docs/7.json');
+      expect(meta?.compressionMethod).toBe('Zstd');
+      expect(meta?.compressedSize).toBeLessThan(meta?.uncompressedSize || 0);
+
+      expect(await reader.readJson('docs/7.json')).toEqual(docs[7]);
+      expect(await reader.readJson('docs/199.json')).toEqual(docs[199]);
+    });
+  });
+
+  describe('Deduplication', () => {
+    it('should store identical files once', async () => {
+      const archivePath = path.join(TEST_DIR, 'dedup.eng');
+      const asset = crypto.randomBytes(4096);
+
+      const writer = new EngramWriter(archivePath, { deduplicate: true });
+      writer.addFile('vendor/a/lib.bin', asset);
+      writer.addFile('vendor/b/lib.bin', asset);
+      writer.addFile('vendor/c/lib.bin', asset);
+      writer.addText('unique.txt', 'unique');
+      const stats = writer.finalize();
+
+      expect(stats.entries).toBe(4);
+      expect(stats.deduplicatedEntries).toBe(2);
+      expect(stats.bytesSaved).toBe(2 * asset.length);
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.entryCount).toBe(4);
+      expect(reader.listPrefix('vendor/').sort()).toEqual([
+        'vendor/a/lib.bin',
+        'vendor/b/lib.bin',
+        'vendor/c/lib.bin'
+      ]);
+      expect(reader.getMetadata('vendor/c/lib.bin')?.aliasOf).toBe('vendor/a/lib.bin');
+      expect((await reader.readFile('vendor/c/lib.bin')).equals(asset)).toBe(true);
+    });
+  });
+
+  describe('Updating Archives', () => {
+    it('should add, replace and delete entries in place', async () => {
+      const archivePath = path.join(TEST_DIR, 'update.eng');
+
+      const writer = new EngramWriter(archivePath, { compressionLevel: 19 });
+      writer.addText('keep.txt', 'unchanged '.repeat(100));
+      writer.addText('replace.txt', 'old content');
+      writer.addText('delete.txt', 'to be removed');
+      writer.addManifest(createManifest({ name: 'update-test', version: '1.0.0' }));
+      writer.finalize();
+
+      const updater = EngramWriter.openForUpdate(archivePath);
+      updater.addText('replace.txt', 'new content');
+      updater.addText('added.txt', 'added');
+      expect(updater.deleteFile('delete.txt')).toBe(true);
+      expect(updater.deleteFile('missing.txt')).toBe(false);
+      updater.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.contains('delete.txt')).toBe(false);
+      expect(reader.contains('added.txt')).toBe(true);
+      expect(await reader.readText('keep.txt')).toBe('unchanged '.repeat(100));
+      expect(await reader.readText('replace.txt')).toBe('new content');
+      expect(reader.getMetadata('keep.txt')?.compressionLevel).toBe(19);
+      expect(reader.readManifest()?.name).toBe('update-test');
+    });
+
+    it('should leave the original archive intact on abort', async () => {
+      const archivePath = path.join(TEST_DIR, 'update-abort.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('a.txt', 'A');
+      writer.finalize();
+
+      const updater = EngramWriter.openForUpdate(archivePath);
+      updater.deleteFile('a.txt');
+      updater.abort();
+
+      const reader = new EngramArchive(archivePath);
+      expect(await reader.readText('a.txt')).toBe('A');
+    });
+  });
+
+  describe('Signing', () => {
+    const expectSignatureError = (open: () => unknown) => {
+      try {
+        open();
+      } catch (error: any) {
+        expect(error.code).toBe(SIGNATURE_ERROR_CODE);
+        return;
+      }
+      throw new Error('expected a signature error');
+    };
+
+    it('should verify archives signed by a trusted key', async () => {
+      const archivePath = path.join(TEST_DIR, 'signed.eng');
+      const publisher = rawKeyPair();
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('release.txt', 'v1.2.3');
+      writer.addManifest(createManifest({ name: 'signed', version: '1.2.3' }));
+      writer.finalize({ signingKey: publisher.secretKey });
+
+      const reader = new EngramArchive(archivePath, {
+        trustedKeys: [publisher.publicKey],
+        requireSignature: true
+      });
+      expect(reader.listFiles()).not.toContain('.engram/signature.json');
+      expect(await reader.readText('release.txt')).toBe('v1.2.3');
+      expect(reader.readManifest()?.name).toBe('signed');
+    });
+
+    it('should reject unsigned and untrusted archives', () => {
+      const unsignedPath = path.join(TEST_DIR, 'unsigned.eng');
+      const unsigned = new EngramWriter(unsignedPath);
+      unsigned.addText('a.txt', 'A');
+      unsigned.finalize();
+
+      const signedPath = path.join(TEST_DIR, 'untrusted.eng');
+      const signed = new EngramWriter(signedPath);
+      signed.addText('a.txt', 'A');
+      signed.finalize({ signingKey: rawKeyPair().secretKey });
+
+      expect(() => new EngramArchive(unsignedPath)).not.toThrow();
+      expectSignatureError(
+        () =>
+          new EngramArchive(unsignedPath, {
+            trustedKeys: [rawKeyPair().publicKey],
+            requireSignature: true
+          })
+      );
+      expectSignatureError(
+        () => new EngramArchive(signedPath, { trustedKeys: [rawKeyPair().publicKey] })
+      );
+    });
+
+    it('should refuse to require a signature without trusted keys', () => {
+      const archivePath = path.join(TEST_DIR, 'self-signed.eng');
+      const writer = new EngramWriter(archivePath);
+      writer.addText('a.txt', 'A');
+      writer.finalize({ signingKey: rawKeyPair().secretKey });
+
+      expect(() => new EngramArchive(archivePath, { requireSignature: true })).toThrow(
+        /trusted key/
+      );
+    });
+
+    it('should check signed aliases against their own hash', async () => {
+      const archivePath = path.join(TEST_DIR, 'signed-aliases.eng');
+      const publisher = rawKeyPair();
+      const writer = new EngramWriter(archivePath, { deduplicate: true });
+      writer.addText('a.txt', 'same');
+      writer.addText('b.txt', 'same');
+      writer.addText('c.txt', 'other');
+      writer.finalize({ signingKey: publisher.secretKey });
+
+      const reader = new EngramArchive(archivePath, {
+        trustedKeys: [publisher.publicKey],
+        requireSignature: true
+      });
+      expect(reader.getMetadata('b.txt')?.aliasOf).toBe('a.txt');
+      expect(await reader.hashFile('b.txt')).toBe(await reader.hashFile('a.txt'));
+      expect(await reader.readText('b.txt')).toBe('same');
+    });
+  });
+
+  describe('Overlays', () => {
+    const basePath = path.join(TEST_DIR, 'overlay-base.eng');
+    const patchPath = path.join(TEST_DIR, 'overlay-patch.eng');
+
+    beforeAll(() => {
+      const dbPath = path.join(TEST_DIR, 'overlay.db');
+      createTestDatabase(dbPath);
+
+      const base = new EngramWriter(basePath);
+      base.addText('config.json', '{"version":1}');
+      base.addText('readme.md', 'base readme');
+      base.addText('assets/legacy/logo.png', 'old logo');
+      base.addText('assets/removed.txt', 'gone soon');
+      base.addText('assets/kept.txt', 'kept');
+      base.addDatabase('data/app.db', dbPath);
+      base.finalize();
+
+      const patch = new EngramWriter(patchPath);
+      patch.addText('config.json', '{"version":2}');
+      patch.addText('assets/new.txt', 'new');
+      patch.addWhiteout('assets/removed.txt');
+      patch.addWhiteout('assets/legacy');
+      patch.finalize();
+    });
+
+    it('should resolve entries in precedence order', async () => {
+      const overlay = new EngramOverlay([patchPath, basePath]);
+
+      expect(await overlay.readJson('config.json')).toEqual({ version: 2 });
+      expect(await overlay.readText('readme.md')).toBe('base readme');
+      expect(overlay.layerOf('config.json')).toBe(0);
+      expect(overlay.layerOf('readme.md')).toBe(1);
+      expect(overlay.getMetadata('readme.md')?.uncompressedSize).toBe(11);
+    });
+
+    it('should hide whited-out files and directories', async () => {
+      const overlay = new EngramOverlay([patchPath, basePath]);
+
+      expect(overlay.contains('assets/removed.txt')).toBe(false);
+      expect(overlay.contains('assets/legacy/logo.png')).toBe(false);
+      expect(overlay.getMetadata('assets/removed.txt')).toBeNull();
+      await expect(overlay.readFile('assets/removed.txt')).rejects.toThrow();
+
+      expect(overlay.listPrefix('assets/')).toEqual([
+        'assets/kept.txt',
+        'assets/new.txt'
+      ]);
+      expect(overlay.listFiles().some((file) => file.includes('.wh.'))).toBe(false);
+    });
+
+    it('should only apply whiteouts to lower layers', () => {
+      const overlay = new EngramOverlay([basePath, patchPath]);
+      expect(overlay.contains('assets/removed.txt')).toBe(true);
+      expect(overlay.layerOf('config.json')).toBe(0);
+    });
+
+    it('should open databases from the layer that holds them', () => {
+      const overlay = new EngramOverlay([patchPath, basePath]);
+      const users = overlay.openDatabase('data/app.db').query('SELECT name FROM users ORDER BY id');
+      expect(users[0].name).toBe('Alice');
+    });
+
+    it('should require at least one layer', () => {
+      expect(() => new EngramOverlay([])).toThrow();
+    });
+  });
+
+  describe('Diff', () => {
+    const oldPath = path.join(TEST_DIR, 'diff-old.eng');
+    const newPath = path.join(TEST_DIR, 'diff-new.eng');
+
+    beforeAll(() => {
+      const oldDb = path.join(TEST_DIR, 'diff-old.db');
+      const newDb = path.join(TEST_DIR, 'diff-new.db');
+      createTestDatabase(oldDb);
+      createTestDatabase(newDb);
+
+      const db = new Database(newDb);
+      db.prepare('UPDATE users SET age = 31 WHERE id = 1').run();
+      db.prepare('DELETE FROM users WHERE id = 3').run();
+      db.prepare('INSERT INTO users (name, email, age) VALUES (?, ?, ?)').run('Dana', 'dana@example.com', 28);
+      db.close();
+
+      const before = new EngramWriter(oldPath);
+      before.addManifest({ name: 'diff', version: '1.0.0' });
+      before.addText('same.txt', 'unchanged');
+      before.addText('changed.txt', 'short');
+      before.addText('removed.txt', 'bye');
+      before.addDatabase('data/app.db', oldDb);
+      before.finalize();
+
+      const after = new EngramWriter(newPath);
+      after.addManifest({ name: 'diff', version: '1.1.0' });
+      after.addText('same.txt', 'unchanged');
+      after.addText('changed.txt', 'a longer body');
+      after.addText('added.txt', 'hello');
+      after.addDatabase('data/app.db', newDb);
+      after.finalize();
+    });
+
+    it('should report added, removed and modified entries', () => {
+      const changes = diffArchives(oldPath, newPath);
+
+      expect(changes.added.map((entry) => entry.path)).toEqual(['added.txt']);
+      expect(changes.removed.map((entry) => entry.path)).toEqual(['removed.txt']);
+
+      const changed = changes.modified.find((entry) => entry.path === 'changed.txt');
+      expect(changed?.contentChanged).toBe(true);
+      expect(changed?.sizeDelta).toBe(8);
+      expect(changes.modified.some((entry) => entry.path === 'same.txt')).toBe(false);
+      expect(changes.unchanged).toBeGreaterThanOrEqual(1);
+      expect(changes.databases).toEqual([]);
+    });
+
+    it('should report manifest changes', () => {
+      const changes = diffArchives(oldPath, newPath);
+      expect(changes.manifest).toContainEqual({ pointer: '/version', old: '1.0.0', new: '1.1.0' });
+      expect(changes.manifest.some((change) => change.pointer === '/name')).toBe(false);
+    });
+
+    it('should diff database rows', () => {
+      const changes = diffArchives(oldPath, newPath, { databases: true });
+      const database = changes.databases.find((db) => db.path === 'data/app.db');
+      const users = database?.tables.find((table) => table.name === 'users');
+
+      expect(users?.schemaChanged).toBe(false);
+      expect(users?.added.map((row) => row.name)).toEqual(['Dana']);
+      expect(users?.removed.map((row) => row.name)).toEqual(['Charlie']);
+      expect(users?.modified).toHaveLength(1);
+      expect(users?.modified[0].old.age).toBe(30);
+      expect(users?.modified[0].new.age).toBe(31);
+    });
+
+    it('should report nothing for identical archives', () => {
+      const changes = diffArchives(oldPath, oldPath, { databases: true });
+      expect(changes.added).toEqual([]);
+      expect(changes.removed).toEqual([]);
+      expect(changes.modified).toEqual([]);
+      expect(changes.manifest).toEqual([]);
+      expect(changes.sizeDelta).toBe(0);
+    });
+  });
+
+  describe('Patches', () => {
+    const oldPath = path.join(TEST_DIR, 'patch-old.eng');
+    const newPath = path.join(TEST_DIR, 'patch-new.eng');
+    const patchPath = path.join(TEST_DIR, 'patch-update.eng');
+    const outPath = path.join(TEST_DIR, 'patch-rebuilt.eng');
+    const bigText = Array.from({ length: 5000 }, (_, i) => `line ${i}: ${crypto.createHash('sha256').update(String(i)).digest('hex')}`).join('\n');
+
+    beforeAll(() => {
+      const before = new EngramWriter(oldPath);
+      before.addManifest({ name: 'patch', version: '1.0.0' });
+      before.addText('same.txt', 'unchanged');
+      before.addText('big.txt', bigText);
+      before.addText('removed.txt', 'bye');
+      before.finalize();
+
+      const after = new EngramWriter(newPath);
+      after.addManifest({ name: 'patch', version: '1.1.0' });
+      after.addText('same.txt', 'unchanged');
+      after.addText('moved.txt', 'bye');
+      after.addFileWithCompression('big.txt', Buffer.from(bigText.replace('line 2500:', 'LINE 2500:')), CompressionMethod.Zstd, 9);
+      after.addText('added.txt', 'hello');
+      after.finalize();
+    });
+
+    it('should store only what changed', () => {
+      const stats = createPatch(oldPath, newPath, patchPath);
+
+      expect(stats.copied).toBe(2);
+      expect(stats.deltas).toBeGreaterThanOrEqual(1);
+      expect(stats.copied + stats.deltas + stats.literals).toBe(new EngramArchive(newPath).entryCount);
+      expect(stats.patchBytes).toBeLessThan(1000);
+      expect(fs.statSync(patchPath).size).toBeLessThan(fs.statSync(newPath).size);
+    });
+
+    it('should rebuild the new archive', async () => {
+      createPatch(oldPath, newPath, patchPath);
+      const stats = applyPatch(oldPath, patchPath, outPath);
+
+      const rebuilt = new EngramArchive(outPath);
+      const expected = new EngramArchive(newPath);
+      expect(stats.entries).toBe(expected.entryCount);
+      expect(rebuilt.listFiles().sort()).toEqual(expected.listFiles().sort());
+      for (const file of expected.listFiles()) {
+        expect(await rebuilt.hashFile(file)).toBe(await expected.hashFile(file));
+      }
+      expect(rebuilt.readManifest()).toEqual(expected.readManifest());
+      expect(rebuilt.contains('removed.txt')).toBe(false);
+      expect(await rebuilt.readText('moved.txt')).toBe('bye');
+
+      const big = rebuilt.getMetadata('big.txt');
+      expect(big?.compressionMethod).toBe('zstd');
+      expect(big?.compressionLevel).toBe(9);
+    });
+
+    it('should refuse a base the patch was not made from', () => {
+      createPatch(oldPath, newPath, patchPath);
+      const wrongOut = path.join(TEST_DIR, 'patch-wrong.eng');
+      expect(() => applyPatch(newPath, patchPath, wrongOut)).toThrow(/base/);
+      expect(fs.existsSync(wrongOut)).toBe(false);
+    });
+
+    it('should reject archives that are not patches', () => {
+      expect(() => applyPatch(oldPath, newPath, outPath)).toThrow(/not a patch/);
+    });
+  });
+
+  // In-memory archives need memfd, which only Linux has.
+  const describeInMemory = process.platform === 'linux' ? describe : describe.skip;
+
+  describeInMemory('In-Memory Archives', () => {
+    it('should read files and databases from a buffer', async () => {
+      const archivePath = path.join(TEST_DIR, 'memory.eng');
+      const dbPath = path.join(TEST_DIR, 'memory.db');
+      createTestDatabase(dbPath);
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('hello.txt', 'from memory');
+      writer.addDatabase('data.db', dbPath);
+      writer.addManifest(createManifest({ name: 'memory', version: '1.0.0' }));
+      writer.finalize();
+
+      const data = fs.readFileSync(archivePath);
+      const archive = EngramArchive.fromBuffer(data);
+      data.fill(0);
+
+      expect(archive.listFiles()).toContain('hello.txt');
+      expect(await archive.readText('hello.txt')).toBe('from memory');
+      expect(archive.readManifest()?.name).toBe('memory');
+
+      const users = archive.openDatabase('data.db').query('SELECT name FROM users ORDER BY id');
+      expect(users[0].name).toBe('Alice');
+    });
+
+    it('should reject buffers that are not archives', () => {
+      expect(() => EngramArchive.fromBuffer(Buffer.from('not an archive'))).toThrow();
+    });
+
+    it('should write archives to a buffer', async () => {
+      const writer = EngramWriter.inMemory();
+      writer.addText('hello.txt', 'built in memory');
+      writer.addManifest(createManifest({ name: 'buffer', version: '1.0.0' }));
+      const data = writer.toBuffer();
+
+      const archive = EngramArchive.fromBuffer(data);
+      expect(await archive.readText('hello.txt')).toBe('built in memory');
+      expect(archive.readManifest()?.name).toBe('buffer');
+      expect(() => writer.toBuffer()).toThrow('Writer already finalized');
+    });
+
+    it('should write archives to a stream', async () => {
+      const chunks: Buffer[] = [];
+      const sink = new Writable({
+        write(chunk, _encoding, callback) {
+          chunks.push(chunk);
+          callback();
+        }
+      });
+
+      const writer = EngramWriter.inMemory();
+      writer.addFile('random.bin', crypto.randomBytes(3 * 1024 * 1024));
+      await writer.toStream(sink);
+
+      expect(chunks.length).toBeGreaterThan(1);
+      const archive = EngramArchive.fromBuffer(Buffer.concat(chunks));
+      expect(archive.listFiles()).toContain('random.bin');
+    });
+
+    it('should stream archives written to a path from the finished file', async () => {
+      const chunks: Buffer[] = [];
+      const sink = new Writable({
+        write(chunk, _encoding, callback) {
+          chunks.push(chunk);
+          callback();
+        }
+      });
+
+      const archivePath = path.join(TEST_DIR, 'streamed.eng');
+      const writer = new EngramWriter(archivePath);
+      writer.addText('hello.txt', 'streamed from disk');
+      await writer.toStream(sink);
+
+      expect(Buffer.concat(chunks)).toEqual(fs.readFileSync(archivePath));
+      expect(() => writer.finalize()).toThrow('Writer already finalized');
+    });
+
+    it('should keep writers usable after finishing them the wrong way', async () => {
+      const archivePath = path.join(TEST_DIR, 'not-a-buffer.eng');
+      const pathWriter = new EngramWriter(archivePath);
+      pathWriter.addText('kept.txt', 'still here');
+      expect(() => pathWriter.toBuffer()).toThrow(/inMemory\(\)/);
+      expect(() => pathWriter.finalize({ signingKey: Buffer.alloc(3) })).toThrow(/sign/);
+      pathWriter.finalize();
+      expect(await new EngramArchive(archivePath).readText('kept.txt')).toBe('still here');
+
+      const memoryWriter = EngramWriter.inMemory();
+      memoryWriter.addText('kept.txt', 'still here');
+      expect(() => memoryWriter.finalize()).toThrow(/toBuffer\(\)/);
+      const archive = EngramArchive.fromBuffer(memoryWriter.toBuffer());
+      expect(await archive.readText('kept.txt')).toBe('still here');
+    });
+  });
+
+  if (process.platform !== 'linux') {
+    it('should refuse in-memory archives outside Linux', () => {
+      expect(() => EngramWriter.inMemory()).toThrow(/only supported on Linux/);
+      expect(() => EngramArchive.fromBuffer(Buffer.alloc(16))).toThrow(/only supported on Linux/);
+    });
+  }
+
+  describe('Resource Limits', () => {
+    const limitedArchive = () => {
+      const archivePath = path.join(TEST_DIR, 'limits.eng');
+      const writer = new EngramWriter(archivePath);
+      writer.addFileWithCompression('bomb.bin', Buffer.alloc(1024 * 1024), CompressionMethod.Zstd);
+      writer.addText('small.txt', 'small');
+      writer.addText('other.txt', 'other');
+      writer.finalize();
+      return archivePath;
+    };
+
+    const expectLimitError = async (read: () => unknown) => {
+      try {
+        await read();
+      } catch (error: any) {
+        expect(error.code).toBe(LIMIT_ERROR_CODE);
+        return;
+      }
+      throw new Error('expected a limit error');
+    };
+
+    it('should reject archives with too many entries on open', async () => {
+      const archivePath = limitedArchive();
+      await expectLimitError(() => new EngramArchive(archivePath, { limits: { maxEntries: 2 } }));
+      await expectLimitError(() => new EngramArchive(archivePath, { limits: { maxPathLength: 8 } }));
+    });
+
+    it('should reject oversized and highly compressed entries before reading', async () => {
+      const archivePath = limitedArchive();
+
+      const bySize = new EngramArchive(archivePath, { limits: { maxEntrySize: 64 * 1024 } });
+      await expectLimitError(() => bySize.readFile('bomb.bin'));
+      await expectLimitError(() => bySize.readFiles(['small.txt', 'bomb.bin']));
+      await expectLimitError(() => bySize.hashFile('bomb.bin'));
+      expect(await bySize.readText('small.txt')).toBe('small');
+
+      const byRatio = new EngramArchive(archivePath, { limits: { maxCompressionRatio: 50 } });
+      await expectLimitError(() => byRatio.readFileSync('bomb.bin'));
+    });
+
+    it('should cap the total bytes read', async () => {
+      const archivePath = limitedArchive();
+      const reader = new EngramArchive(archivePath, { limits: { maxTotalBytes: 8 } });
+
+      expect(await reader.readText('small.txt')).toBe('small');
+      await expectLimitError(() => reader.readText('other.txt'));
+    });
+  });
+
+  describe('Entry Hashes', () => {
+    it('should expose and verify SHA-256 hashes', async () => {
+      const archivePath = path.join(TEST_DIR, 'hashes.eng');
+      const content = Buffer.from('content-addressed payload');
+      const expected = crypto.createHash('sha256').update(content).digest('hex');
+
+      const writer = new EngramWriter(archivePath, { compressionLevel: 3 });
+      writer.addFile('payload.bin', content);
+      writer.addText('other.txt', 'other');
+      writer.finalize();
+
+      const reader = new EngramArchive(archivePath);
+      expect(reader.getMetadata('payload.bin')?.hash).toBe(expected);
+      expect(await reader.hashFile('payload.bin')).toBe(expected);
+      await expect(reader.hashFile('missing.bin')).rejects.toThrow();
+    });
+  });
+
+  describe('Encryption', () => {
+    it('should round-trip encrypted entries with the right key', async () => {
+      const archivePath = path.join(TEST_DIR, 'encrypted.eng');
+      const key = crypto.randomBytes(32);
+
+      const writer = new EngramWriter(archivePath, {
+        encryption: { cipher: Cipher.ChaCha20Poly1305, key, keyId: 'primary' }
+      });
+      writer.addText('secret.txt', 'top secret '.repeat(50));
+      writer.addManifest(createManifest({ name: 'encrypted', version: '1.0.0' }));
+      writer.finalize();
+
+      const raw = fs.readFileSync(archivePath);
+      expect(raw.includes(Buffer.from('top secret'))).toBe(false);
+
+      const reader = new EngramArchive(archivePath, {
+        keys: [{ key: crypto.randomBytes(32), keyId: 'old' }, { key, keyId: 'primary' }]
+      });
+      expect(reader.getMetadata('secret.txt')?.encrypted).toBe(true);
+      expect(await reader.readText('secret.txt')).toBe('top secret '.repeat(50));
+      expect(reader.readManifest()?.name).toBe('encrypted');
+    });
+
+    it('should fail to read without a matching key', async () => {
+      const archivePath = path.join(TEST_DIR, 'encrypted-keys.eng');
+
+      const writer = new EngramWriter(archivePath, {
+        encryption: { cipher: Cipher.Aes256Gcm, key: crypto.randomBytes(32) }
+      });
+      writer.addText('secret.txt', 'hidden');
+      writer.finalize();
+
+      const keyless = new EngramArchive(archivePath);
+      expect(keyless.contains('secret.txt')).toBe(true);
+      await expect(keyless.readText('secret.txt')).rejects.toThrow();
+
+      const wrongKey = new EngramArchive(archivePath, { keys: [{ key: crypto.randomBytes(32) }] });
+      await expect(wrongKey.readText('secret.txt')).rejects.toThrow();
+    });
+
+    it('should not record plaintext hashes of encrypted entries', async () => {
+      const archivePath = path.join(TEST_DIR, 'encrypted-hashes.eng');
+      const key = crypto.randomBytes(32);
+      const publisher = rawKeyPair();
+      const payload = Buffer.from('confidential '.repeat(100));
+
+      const writer = new EngramWriter(archivePath, {
+        deduplicate: true,
+        encryption: { cipher: Cipher.Aes256Gcm, key }
+      });
+      writer.addFile('a.txt', payload);
+      writer.addFile('b.txt', payload);
+      writer.finalize({ signingKey: publisher.secretKey });
+
+      const reader = new EngramArchive(archivePath, {
+        trustedKeys: [publisher.publicKey],
+        requireSignature: true,
+        keys: [{ key }]
+      });
+      const expected = crypto.createHash('sha256').update(payload).digest('hex');
+      expect(reader.getMetadata('a.txt')?.hash).toBeUndefined();
+      expect(reader.getMetadata('a.txt')?.uncompressedSize).not.toBe(payload.length);
+      expect(await reader.hashFile('a.txt')).toBe(expected);
+      expect(reader.getMetadata('b.txt')?.aliasOf).toBe('a.txt');
+      expect(Buffer.compare(await reader.readFile('b.txt'), payload)).toBe(0);
+    });
+
+    it('should query encrypted databases', () => {
+      const dbPath = path.join(TEST_DIR, 'encrypted-source.db');
+      const archivePath = path.join(TEST_DIR, 'encrypted-db.eng');
+      const key = crypto.randomBytes(32);
+      createTestDatabase(dbPath);
+
+      const writer = new EngramWriter(archivePath, {
+        encryption: { cipher: Cipher.Aes256Gcm, key }
+      });
+      writer.addDatabase('data.db', dbPath);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath, { keys: [{ key }] }).openDatabase('data.db');
+      const users = db.query('SELECT name FROM users ORDER BY id');
+      expect(users[0].name).toBe('Alice');
+      expect(() => new EngramArchive(archivePath).openDatabase('data.db')).toThrow();
+    });
+  });
+
   describe('SQLite Database Access', () => {
     beforeAll(() => {
       // Create test database
@@ -250,6 +1014,254 @@ describe('Engram System Integration Tests', () => {
       const userCount = db.queryValue('SELECT COUNT(*) FROM users');
       expect(userCount).toBe(3);
     });
+
+    it('should expose archive entries through engram_files', () => {
+      const archivePath = path.join(TEST_DIR, 'files-table.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('avatars/alice.txt', 'alice avatar');
+      writer.addText('avatars/bob.txt', 'bob avatar');
+      writer.addDatabase('data.db', TEST_DB);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath).openDatabase('data.db');
+
+      const paths = db.query("SELECT path FROM engram_files WHERE path LIKE 'avatars/%' ORDER BY path");
+      expect(paths.map((row: any) => row.path)).toEqual(['avatars/alice.txt', 'avatars/bob.txt']);
+
+      const joined = db.query(`
+        SELECT u.name, f.size, CAST(f.data AS TEXT) AS content
+        FROM users u JOIN engram_files f ON f.path = 'avatars/' || lower(u.name) || '.txt'
+        ORDER BY u.id
+      `);
+      expect(joined).toEqual([
+        { name: 'Alice', size: 12, content: 'alice avatar' },
+        { name: 'Bob', size: 10, content: 'bob avatar' },
+      ]);
+
+      const size = db.queryValue("SELECT size FROM engram_files WHERE path = 'data.db'");
+      expect(size).toBe(fs.statSync(TEST_DB).size);
+      expect(db.queryValue("SELECT COUNT(*) FROM engram_files WHERE path = 'missing.txt'")).toBe(0);
+    });
+
+    it('should resolve archive entries with SQL functions', () => {
+      const archivePath = path.join(TEST_DIR, 'sql-functions.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('posts/1.md', '# Hello');
+      writer.addJson('posts/1.json', { title: 'Hello', tags: ['intro', 'news'], views: 12 });
+      writer.addDatabase('data.db', TEST_DB);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath).openDatabase('data.db');
+
+      expect(db.queryValue("SELECT engram_read_text('posts/1.md')")).toBe('# Hello');
+      expect(db.queryValue("SELECT length(engram_read('posts/1.md'))")).toBe(7);
+      expect(db.queryValue("SELECT engram_json('posts/1.json', '$.title')")).toBe('Hello');
+      expect(db.queryValue("SELECT engram_json('posts/1.json', '$.views')")).toBe(12);
+      expect(db.queryValue("SELECT engram_json('posts/1.json', '$.tags[#-1]')")).toBe('news');
+      expect(JSON.parse(db.queryValue("SELECT engram_json('posts/1.json', '$.tags')"))).toEqual(['intro', 'news']);
+      expect(db.queryValue("SELECT engram_json('posts/1.json', '$.missing')")).toBeNull();
+
+      expect(db.queryValue("SELECT engram_exists('posts/1.md')")).toBe(1);
+      expect(db.queryValue("SELECT engram_exists('posts/2.md')")).toBe(0);
+      expect(db.queryValue("SELECT engram_read_text('posts/2.md')")).toBeNull();
+
+      expect(() => db.queryValue("SELECT engram_json('posts/1.md', '$')")).toThrow(/not valid JSON/);
+    });
+
+    it('should attach databases from archives and disk', () => {
+      const statsDb = path.join(TEST_DIR, 'stats.db');
+      const cacheDb = path.join(TEST_DIR, 'cache.db');
+      for (const file of [statsDb, cacheDb]) {
+        fs.rmSync(file, { force: true });
+      }
+
+      const stats = new Database(statsDb);
+      stats.exec('CREATE TABLE scores (user_id INTEGER, score INTEGER)');
+      stats.exec('INSERT INTO scores VALUES (1, 10), (2, 20), (1, 5)');
+      stats.close();
+
+      const cache = new Database(cacheDb);
+      cache.exec('CREATE TABLE labels (user_id INTEGER, label TEXT)');
+      cache.exec("INSERT INTO labels VALUES (2, 'vip')");
+      cache.close();
+
+      const usersPath = path.join(TEST_DIR, 'attach-users.eng');
+      const usersWriter = new EngramWriter(usersPath);
+      usersWriter.addDatabase('data.db', TEST_DB);
+      usersWriter.finalize();
+
+      const statsPath = path.join(TEST_DIR, 'attach-stats.eng');
+      const statsWriter = new EngramWriter(statsPath);
+      statsWriter.addDatabase('stats.db', statsDb);
+      statsWriter.finalize();
+
+      const db = new EngramArchive(usersPath).openDatabase('data.db');
+      db.attach(new EngramArchive(statsPath), 'stats.db', 'stats');
+      db.attach(cacheDb, 'cache');
+
+      const totals = db.query(`
+        SELECT u.name, SUM(s.score) AS total, l.label
+        FROM users u
+        JOIN stats.scores s ON s.user_id = u.id
+        LEFT JOIN cache.labels l ON l.user_id = u.id
+        GROUP BY u.id ORDER BY u.id
+      `);
+      expect(totals).toEqual([
+        { name: 'Alice', total: 15, label: null },
+        { name: 'Bob', total: 20, label: 'vip' },
+      ]);
+
+      expect(() => db.execute('INSERT INTO stats.scores VALUES (3, 1)')).toThrow();
+      expect(() => db.attach(path.join(TEST_DIR, 'missing.db'), 'missing')).toThrow(/does not exist/);
+      expect(() => db.attach(cacheDb, 'cache')).toThrow();
+    });
+
+    it('should call user-defined functions', () => {
+      const archivePath = path.join(TEST_DIR, 'udf.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addDatabase('data.db', TEST_DB);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath).openDatabase('data.db');
+
+      db.function('shout', { deterministic: true }, (name: string) => `${name.toUpperCase()}!`);
+      db.function('add_all', { varargs: true }, (...values: number[]) => values.reduce((a, b) => a + b, 0));
+      db.function('is_even', (n: number) => n % 2 === 0);
+      db.function('fail', () => {
+        throw new Error('boom');
+      });
+
+      const names = db.query('SELECT shout(name) AS name FROM users ORDER BY id');
+      expect(names.map((row: any) => row.name)).toEqual(['ALICE!', 'BOB!', 'CHARLIE!']);
+      expect(db.queryValue('SELECT add_all(1, 2, 3.5)')).toBe(6.5);
+      expect(db.queryValue('SELECT add_all()')).toBe(0);
+      expect(db.queryValue('SELECT is_even(4)')).toBe(1);
+      expect(() => db.queryValue('SELECT shout()')).toThrow();
+      expect(() => db.queryValue('SELECT fail()')).toThrow(/boom/);
+
+      db.function('reenter', () => db.queryValue('SELECT 1'));
+      expect(() => db.queryValue('SELECT reenter()')).toThrow(/busy/);
+      expect(db.queryValue('SELECT shout(name) FROM users WHERE id = 1')).toBe('ALICE!');
+
+      const other = new EngramArchive(archivePath).openDatabase('data.db');
+      db.function('count_other', () => other.queryValue('SELECT COUNT(*) FROM users'));
+      expect(db.queryValue('SELECT count_other()')).toBe(3);
+
+      db.function('joined', (...values: string[]) => values.join('-'));
+      db.function('pair', { arity: 2 }, (first: string, second = 'none') => `${first}/${second}`);
+      expect(db.queryValue("SELECT joined('a', 'b', 'c')")).toBe('a-b-c');
+      expect(db.queryValue("SELECT pair('a', 'b')")).toBe('a/b');
+      expect(() => db.queryValue("SELECT pair('a')")).toThrow();
+    });
+
+    it('should return blobs as Buffers and large integers as BigInts from queries', () => {
+      const archivePath = path.join(TEST_DIR, 'query-values.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addDatabase('data.db', TEST_DB);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath).openDatabase('data.db');
+
+      const row = db.queryOne("SELECT x'00ff' AS b, 9007199254740991 AS safe, 9007199254740993 AS big");
+      expect(Buffer.isBuffer(row.b)).toBe(true);
+      expect(row.b.toString('hex')).toBe('00ff');
+      expect(row.safe).toBe(9007199254740991);
+      expect(row.big).toBe(BigInt('9007199254740993'));
+
+      expect(db.queryValue('SELECT hex(?)', [Buffer.from([1, 2])])).toBe('0102');
+      expect(db.queryValue('SELECT hex(?)', [new Uint8Array([3, 4])])).toBe('0304');
+      expect(db.queryValue('SELECT ? + 1', [BigInt('9223372036854775806')])).toBe(
+        BigInt('9223372036854775807')
+      );
+      expect(db.queryValue('SELECT ?', [{ a: 1 }])).toBe('{"a":1}');
+      expect(db.queryValue('SELECT ?', [true])).toBe(1);
+    });
+
+    it('should pass blobs as Buffers and large integers as BigInts', () => {
+      const archivePath = path.join(TEST_DIR, 'udf-values.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addDatabase('data.db', TEST_DB);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath).openDatabase('data.db');
+
+      db.function('describe', (value: unknown) =>
+        Buffer.isBuffer(value) ? `buffer:${value.toString('hex')}` : typeof value
+      );
+      db.function('reverse_bytes', (value: Buffer) => Buffer.from(value).reverse());
+      db.function('bytes', () => new Uint8Array([1, 2, 3]));
+      db.function('increment', (value: number | bigint) =>
+        typeof value === 'bigint' ? value + BigInt(1) : value + 1
+      );
+      db.function('too_big', () => BigInt(2) ** BigInt(64));
+
+      expect(db.queryValue("SELECT describe(x'00ff10')")).toBe('buffer:00ff10');
+      expect(db.queryValue('SELECT describe(9007199254740991)')).toBe('number');
+      expect(db.queryValue('SELECT describe(9007199254740992)')).toBe('bigint');
+      expect(db.queryValue('SELECT describe(-9223372036854775808)')).toBe('bigint');
+      expect(db.queryValue('SELECT describe(NULL)')).toBe('object');
+      expect(db.queryValue("SELECT hex(reverse_bytes(x'0102ff'))")).toBe('FF0201');
+      expect(db.queryValue("SELECT typeof(reverse_bytes(x'00'))")).toBe('blob');
+      expect(db.queryValue('SELECT hex(bytes())')).toBe('010203');
+      expect(
+        db.queryValue('SELECT increment(9223372036854775806) = 9223372036854775807')
+      ).toBe(1);
+      expect(db.queryValue("SELECT typeof(increment(9007199254740993))")).toBe('integer');
+      expect(db.queryValue('SELECT increment(41)')).toBe(42);
+      expect(() => db.queryValue('SELECT too_big()')).toThrow(/64-bit/);
+
+      db.aggregate('total_bytes', {
+        start: 0,
+        step: (total: number, value: Buffer) => total + value.length,
+      });
+      expect(db.queryValue("SELECT total_bytes(b) FROM (SELECT x'0102' AS b UNION ALL SELECT x'03')")).toBe(3);
+    });
+
+    it('should call user-defined aggregates', () => {
+      const archivePath = path.join(TEST_DIR, 'udf-aggregate.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addDatabase('data.db', TEST_DB);
+      writer.finalize();
+
+      const db = new EngramArchive(archivePath).openDatabase('data.db');
+
+      db.aggregate('name_list', {
+        start: () => [] as string[],
+        step: (names: string[], name: string) => {
+          names.push(name);
+        },
+        result: (names: string[]) => names.join(','),
+      });
+      db.aggregate('js_sum', { start: 0, step: (sum: number, value: number) => sum + value });
+
+      expect(db.queryValue('SELECT name_list(name) FROM (SELECT name FROM users ORDER BY id)')).toBe(
+        'Alice,Bob,Charlie'
+      );
+      expect(db.queryValue('SELECT js_sum(id) FROM users')).toBe(6);
+      expect(db.queryValue('SELECT js_sum(id) FROM users WHERE id > 100')).toBe(0);
+
+      const grouped = db.query('SELECT id % 2 AS odd, js_sum(id) AS sum FROM users GROUP BY odd ORDER BY odd');
+      expect(grouped).toEqual([
+        { odd: 0, sum: 2 },
+        { odd: 1, sum: 4 },
+      ]);
+
+      db.aggregate('checked_sum', {
+        start: 0,
+        step: (sum: number, value: number) => {
+          if (value > 2) throw new Error(`too large: ${value}`);
+          return sum + value;
+        },
+      });
+      expect(() => db.queryValue('SELECT checked_sum(id) FROM users')).toThrow(/too large/);
+      expect(db.queryValue('SELECT checked_sum(id) FROM users WHERE id <= 2')).toBe(3);
+    });
   });
 
   describe('Error Handling', () => {
@@ -281,6 +1293,30 @@ describe('Engram System Integration Tests', () => {
       }).toThrow();
     });
 
+    it('should only publish the archive on finalize', () => {
+      const archivePath = path.join(TEST_DIR, 'atomic.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('test.txt', 'test');
+      expect(fs.existsSync(archivePath)).toBe(false);
+
+      writer.finalize();
+      expect(fs.existsSync(archivePath)).toBe(true);
+      expect(fs.readdirSync(TEST_DIR).some((file) => file.endsWith('.tmp'))).toBe(false);
+    });
+
+    it('should discard the partial archive on abort', () => {
+      const archivePath = path.join(TEST_DIR, 'aborted.eng');
+
+      const writer = new EngramWriter(archivePath);
+      writer.addText('test.txt', 'test');
+      writer.abort();
+
+      expect(fs.existsSync(archivePath)).toBe(false);
+      expect(fs.readdirSync(TEST_DIR).some((file) => file.includes('aborted.eng'))).toBe(false);
+      expect(() => writer.addText('another.txt', 'another')).toThrow('Writer already finalized');
+    });
+
     it('should not allow operations on finalized writer', () => {
       const archivePath = path.join(TEST_DIR, 'finalized.eng');
 